@@ -1,35 +1,24 @@
-//! One way to the contents of an entity, as you might do for debugging. A similar pattern could
-//! also be useful for serialization, or other row-oriented generic operations.
+//! One way to format the contents of an entity, as you might do for debugging. A similar pattern
+//! could also be useful for serialization, or other row-oriented generic operations.
 
-fn format_entity(entity: hecs::EntityRef<'_>) -> String {
-    fn fmt<T: hecs::Component + std::fmt::Display>(entity: hecs::EntityRef<'_>) -> Option<String> {
-        Some(entity.get::<T>()?.to_string())
-    }
-
-    const FUNCTIONS: &[&dyn Fn(hecs::EntityRef<'_>) -> Option<String>] =
-        &[&fmt::<i32>, &fmt::<bool>, &fmt::<f64>];
-
-    let mut out = String::new();
-    for f in FUNCTIONS {
-        if let Some(x) = f(entity) {
-            if out.is_empty() {
-                out.push_str("[");
-            } else {
-                out.push_str(", ");
-            }
-            out.push_str(&x);
-        }
-    }
-    if out.is_empty() {
-        out.push_str(&"[]");
-    } else {
-        out.push(']');
-    }
-    out
+#[derive(Debug, Default)]
+struct Position {
+    x: f32,
+    y: f32,
 }
 
 fn main() {
+    let mut registry = hecs::ComponentRegistry::new();
+    registry.register::<i32>("health").debug().display();
+    registry.register::<bool>("alive").display();
+    registry.register::<Position>("position").debug().default();
+
     let mut world = hecs::World::new();
     let e = world.spawn((42, true));
-    println!("{}", format_entity(world.entity(e).unwrap()));
+    world.spawn((7, Position { x: 1.0, y: -2.5 }));
+    for (_, pos) in world.query::<&mut Position>().iter() {
+        pos.x += pos.y;
+    }
+    println!("{}", registry.display_entity(world.entity(e).unwrap()));
+    println!("{:#?}", registry.display_world(&world));
 }
@@ -56,6 +56,10 @@ pub fn derive_bundle(input: TokenStream) -> TokenStream {
                 Self::static_type_info()
             }
 
+            fn key(&self) -> Option<std::any::TypeId> {
+                Self::static_key()
+            }
+
             #[allow(clippy::forget_copy)]
             unsafe fn put(mut self, mut f: impl FnMut(*mut u8, ::hecs::TypeInfo)) {
                 #(
@@ -98,6 +102,10 @@ pub fn derive_bundle(input: TokenStream) -> TokenStream {
                 info
             }
 
+            fn static_key() -> Option<std::any::TypeId> {
+                Some(std::any::TypeId::of::<Self>())
+            }
+
             unsafe fn get(
                 mut f: impl FnMut(::hecs::TypeInfo) -> Option<std::ptr::NonNull<u8>>,
             ) -> Result<Self, ::hecs::MissingComponent> {
@@ -0,0 +1,191 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::alloc::alloc::{alloc, dealloc, Layout};
+use core::ops::{Deref, DerefMut};
+use core::ptr::{self, NonNull};
+use core::{cmp, mem, slice};
+
+/// A source of memory for a `World`'s storage
+///
+/// Used for archetypes' component columns and entity arrays, sparse component storage, and the
+/// table mapping entity IDs to locations. Scratch space such as `EntityBuilder`'s is still
+/// obtained from the global allocator.
+///
+/// A `World` owns its allocator and calls it directly. References to allocators are allocators
+/// too, so a world may borrow an arena that outlives it, as in `World<&Arena>`.
+///
+/// # Safety
+///
+/// `allocate` must return either `None` or a pointer to a fresh block of memory that fits
+/// `layout`, which remains valid until passed to `deallocate`.
+pub unsafe trait Allocator: Send + Sync {
+    /// Allocate a block of memory fitting `layout`, which has nonzero size
+    ///
+    /// Returns `None` if the allocation failed.
+    fn allocate(&self, layout: Layout) -> Option<NonNull<u8>>;
+
+    /// Free a block of memory previously returned by `allocate`
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by a call to `allocate` on this allocator with the same
+    /// `layout`, and must not have been deallocated since.
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout);
+}
+
+/// The global allocator, as used by `Box` and `Vec`
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Global;
+
+unsafe impl Allocator for Global {
+    #[inline]
+    fn allocate(&self, layout: Layout) -> Option<NonNull<u8>> {
+        NonNull::new(unsafe { alloc(layout) })
+    }
+
+    #[inline]
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        dealloc(ptr.as_ptr(), layout);
+    }
+}
+
+unsafe impl<A: Allocator + ?Sized> Allocator for &A {
+    #[inline]
+    fn allocate(&self, layout: Layout) -> Option<NonNull<u8>> {
+        (**self).allocate(layout)
+    }
+
+    #[inline]
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        (**self).deallocate(ptr, layout);
+    }
+}
+
+/// Growable array of `Copy` values stored in memory from an `Allocator`
+///
+/// Doesn't store its allocator, which is instead passed to every call that allocates, and must be
+/// the same each time. The memory is only released by `free`; dropping an `AllocVec` leaks it.
+pub(crate) struct AllocVec<T: Copy> {
+    ptr: NonNull<T>,
+    len: usize,
+    capacity: usize,
+}
+
+impl<T: Copy> AllocVec<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            ptr: NonNull::dangling(),
+            len: 0,
+            capacity: if mem::size_of::<T>() == 0 {
+                usize::MAX
+            } else {
+                0
+            },
+        }
+    }
+
+    #[inline]
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Ensure there's room for at least `additional` more elements, amortizing reallocation
+    pub(crate) fn reserve<A: Allocator>(&mut self, alloc: &A, additional: usize) {
+        let needed = self.len.checked_add(additional).expect("capacity overflow");
+        if needed > self.capacity {
+            self.reallocate(alloc, cmp::max(needed, cmp::max(self.capacity * 2, 4)));
+        }
+    }
+
+    /// Ensure there's room for exactly `additional` more elements
+    pub(crate) fn reserve_exact<A: Allocator>(&mut self, alloc: &A, additional: usize) {
+        let needed = self.len.checked_add(additional).expect("capacity overflow");
+        if needed > self.capacity {
+            self.reallocate(alloc, needed);
+        }
+    }
+
+    pub(crate) fn resize<A: Allocator>(&mut self, alloc: &A, len: usize, value: T) {
+        if len > self.len {
+            self.reserve(alloc, len - self.len);
+            for i in self.len..len {
+                unsafe {
+                    self.ptr.as_ptr().add(i).write(value);
+                }
+            }
+        }
+        self.len = len;
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Release unused capacity
+    pub(crate) fn shrink_to_fit<A: Allocator>(&mut self, alloc: &A) {
+        if mem::size_of::<T>() == 0 || self.capacity == self.len {
+            return;
+        }
+        if self.len == 0 {
+            self.free(alloc);
+        } else {
+            self.reallocate(alloc, self.len);
+        }
+    }
+
+    fn reallocate<A: Allocator>(&mut self, alloc: &A, capacity: usize) {
+        let layout = Layout::array::<T>(capacity).expect("capacity overflow");
+        let new = alloc.allocate(layout).unwrap().cast::<T>();
+        unsafe {
+            ptr::copy_nonoverlapping(self.ptr.as_ptr(), new.as_ptr(), self.len);
+        }
+        self.release(alloc);
+        self.ptr = new;
+        self.capacity = capacity;
+    }
+
+    /// Release all memory, leaving the array empty
+    pub(crate) fn free<A: Allocator>(&mut self, alloc: &A) {
+        self.release(alloc);
+        *self = Self::new();
+    }
+
+    fn release<A: Allocator>(&mut self, alloc: &A) {
+        if self.capacity != 0 && mem::size_of::<T>() != 0 {
+            unsafe {
+                alloc.deallocate(self.ptr.cast(), Layout::array::<T>(self.capacity).unwrap());
+            }
+        }
+    }
+}
+
+impl<T: Copy> Deref for AllocVec<T> {
+    type Target = [T];
+
+    #[inline]
+    fn deref(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T: Copy> DerefMut for AllocVec<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+unsafe impl<T: Copy + Send> Send for AllocVec<T> {}
+unsafe impl<T: Copy + Sync> Sync for AllocVec<T> {}
@@ -12,17 +12,18 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::alloc::alloc::{alloc, dealloc, Layout};
-use crate::alloc::boxed::Box;
-use crate::alloc::{vec, vec::Vec};
+use crate::alloc::alloc::Layout;
+use crate::alloc::vec::Vec;
 use core::any::{type_name, TypeId};
-use core::cell::UnsafeCell;
+use core::convert::TryFrom;
 use core::hash::{BuildHasher, BuildHasherDefault, Hasher};
-use core::mem;
+use core::mem::MaybeUninit;
 use core::ptr::{self, NonNull};
+use core::{fmt, mem};
 
 use hashbrown::{hash_map::DefaultHashBuilder, HashMap};
 
+use crate::allocator::{AllocVec, Allocator};
 use crate::borrow::AtomicBorrow;
 use crate::query::Fetch;
 use crate::{Access, Component, Query};
@@ -31,15 +32,33 @@ use crate::{Access, Component, Query};
 ///
 /// Accessing `Archetype`s is only required for complex dynamic scheduling. To manipulate entities,
 /// go through the `World`.
+///
+/// Components are stored in one or more chunks, each holding a column per component type for
+/// `chunk_capacity` entities. By default an archetype has a single chunk that is reallocated as
+/// it grows; see `World::with_chunk_size` for fixed-size chunks that are never moved.
 pub struct Archetype {
     types: Vec<TypeInfo>,
     state: TypeIdMap<TypeState>,
     len: u32,
-    entities: Box<[u32]>,
-    // UnsafeCell allows unique references into `data` to be constructed while shared references
-    // containing the `Archetype` exist
-    data: UnsafeCell<NonNull<u8>>,
-    data_size: usize,
+    entities: AllocVec<u32>,
+    // Memory comes from the owning world's allocator, which is passed to each method that needs
+    // it, and is only released by `free`.
+    //
+    // Component data lives behind raw pointers, allowing unique references into it to be
+    // constructed while shared references containing the `Archetype` exist
+    chunks: Vec<NonNull<u8>>,
+    /// Size in bytes of each chunk
+    chunk_size: usize,
+    /// Number of entities each chunk has room for
+    chunk_capacity: u32,
+    /// Whether to grow by adding chunks of fixed capacity, rather than by reallocating one chunk
+    chunked: bool,
+    /// Archetypes reached by inserting a bundle, keyed by `DynamicBundle::key`
+    insert_edges: TypeIdMap<u32>,
+    /// Archetypes reached by removing a bundle, keyed by `Bundle::static_key`
+    remove_edges: TypeIdMap<u32>,
+    /// Whether any component type has relationships that must be unlinked on despawn
+    linked: bool,
 }
 
 impl Archetype {
@@ -51,11 +70,81 @@ impl Archetype {
         Self {
             types,
             state: HashMap::default(),
-            entities: Box::new([]),
+            entities: AllocVec::new(),
             len: 0,
-            data: UnsafeCell::new(NonNull::dangling()),
-            data_size: 0,
+            chunks: Vec::new(),
+            chunk_size: 0,
+            chunk_capacity: 0,
+            chunked: false,
+            insert_edges: HashMap::default(),
+            remove_edges: HashMap::default(),
+            linked: false,
+        }
+    }
+
+    /// Construct an archetype that stores components in chunks of roughly `chunk_size` bytes
+    ///
+    /// Each chunk holds at least one entity. Archetypes whose components are all zero-sized are
+    /// stored as if unchunked.
+    pub(crate) fn new_chunked(types: Vec<TypeInfo>, chunk_size: usize) -> Self {
+        let mut x = Self::new(types);
+        let row_size = x.types.iter().map(|ty| ty.layout.size()).sum::<usize>();
+        if row_size == 0 {
+            return x;
+        }
+        x.chunk_capacity = u32::try_from((chunk_size / row_size).max(1)).unwrap_or(u32::MAX);
+        // Types are sorted by descending alignment and sizes are multiples of alignment, so
+        // columns are packed without padding
+        let mut offset = 0;
+        for ty in &x.types {
+            x.state.insert(ty.id, TypeState::new(offset));
+            offset += ty.layout.size() * x.chunk_capacity as usize;
         }
+        x.chunk_size = offset;
+        x.chunked = true;
+        x
+    }
+
+    /// Whether any component type has relationships that must be unlinked on despawn
+    #[inline]
+    pub(crate) fn is_linked(&self) -> bool {
+        self.linked
+    }
+
+    pub(crate) fn set_linked(&mut self) {
+        self.linked = true;
+    }
+
+    /// The archetype entities move to when the bundle identified by `key` is inserted, if known
+    #[inline]
+    pub(crate) fn insert_edge(&self, key: TypeId) -> Option<u32> {
+        self.insert_edges.get(&key).copied()
+    }
+
+    pub(crate) fn set_insert_edge(&mut self, key: TypeId, target: u32) {
+        self.insert_edges.insert(key, target);
+    }
+
+    /// The archetype entities move to when the bundle identified by `key` is removed, if known
+    #[inline]
+    pub(crate) fn remove_edge(&self, key: TypeId) -> Option<u32> {
+        self.remove_edges.get(&key).copied()
+    }
+
+    pub(crate) fn set_remove_edge(&mut self, key: TypeId, target: u32) {
+        self.remove_edges.insert(key, target);
+    }
+
+    /// Renumber cached transitions after archetypes are removed
+    ///
+    /// `remap` maps each old archetype index to its new one, or to `u32::MAX` if it was removed.
+    pub(crate) fn remap_edges(&mut self, remap: &[u32]) {
+        let remap_edge = |_: &TypeId, target: &mut u32| {
+            *target = remap[*target as usize];
+            *target != u32::MAX
+        };
+        self.insert_edges.retain(remap_edge);
+        self.remove_edges.retain(remap_edge);
     }
 
     pub(crate) fn clear(&mut self) {
@@ -81,13 +170,75 @@ impl Archetype {
         self.state.contains_key(&id)
     }
 
-    pub(crate) fn get<T: Component>(&self) -> Option<NonNull<T>> {
-        let state = self.state.get(&TypeId::of::<T>())?;
-        Some(unsafe {
-            NonNull::new_unchecked(
-                (*self.data.get()).as_ptr().add(state.offset).cast::<T>() as *mut T
+    /// Pointer to the first `T` in `chunk`
+    pub(crate) fn get<T: Component>(&self, chunk: usize) -> Option<NonNull<T>> {
+        self.get_base(TypeId::of::<T>(), chunk).map(|x| x.cast())
+    }
+
+    /// Pointer to the `T` of the entity at `index`
+    ///
+    /// # Safety
+    /// `index` must be in-bounds
+    pub(crate) unsafe fn get_at<T: Component>(&self, index: u32) -> Option<NonNull<T>> {
+        self.get_dynamic(TypeId::of::<T>(), mem::size_of::<T>(), index)
+            .map(|x| x.cast())
+    }
+
+    /// Number of chunks containing entities
+    #[inline]
+    pub(crate) fn chunk_count(&self) -> usize {
+        if self.len == 0 {
+            0
+        } else if self.chunked {
+            ((self.len - 1) / self.chunk_capacity + 1) as usize
+        } else {
+            1
+        }
+    }
+
+    /// Index of the first entity in `chunk`
+    #[inline]
+    pub(crate) fn chunk_start(&self, chunk: usize) -> u32 {
+        if self.chunked {
+            chunk as u32 * self.chunk_capacity
+        } else {
+            0
+        }
+    }
+
+    /// Number of entities in `chunk`
+    #[inline]
+    pub(crate) fn chunk_len(&self, chunk: usize) -> u32 {
+        if self.chunked {
+            (self.len - self.chunk_start(chunk)).min(self.chunk_capacity)
+        } else {
+            self.len
+        }
+    }
+
+    /// The chunk containing the entity at `index`, and the entity's position within it
+    #[inline]
+    pub(crate) fn locate(&self, index: u32) -> (usize, u32) {
+        if self.chunked {
+            (
+                (index / self.chunk_capacity) as usize,
+                index % self.chunk_capacity,
             )
-        })
+        } else {
+            (0, index)
+        }
+    }
+
+    /// Number of entities, starting with the one at `index`, whose components are stored
+    /// contiguously
+    #[inline]
+    pub(crate) fn contiguous_len(&self, index: u32) -> u32 {
+        let rest = self.len - index;
+        if self.chunked {
+            rest.min(self.chunk_capacity - index % self.chunk_capacity)
+        } else {
+            rest
+        }
     }
 
     pub(crate) fn borrow<T: Component>(&self) {
@@ -110,6 +261,43 @@ impl Archetype {
         }
     }
 
+    /// Borrow the column of type `id` until the returned guard is dropped
+    pub(crate) fn borrow_column(&self, id: TypeId) -> ColumnBorrow<'_> {
+        self.borrow_dynamic(id);
+        ColumnBorrow {
+            archetype: self,
+            id,
+        }
+    }
+
+    pub(crate) fn borrow_dynamic(&self, id: TypeId) {
+        if let Some(x) = self.state.get(&id) {
+            if !x.borrow.borrow() {
+                panic!("{} already borrowed uniquely", self.type_name(id));
+            }
+        }
+    }
+
+    pub(crate) fn borrow_mut_dynamic(&self, id: TypeId) {
+        if let Some(x) = self.state.get(&id) {
+            if !x.borrow.borrow_mut() {
+                panic!("{} already borrowed", self.type_name(id));
+            }
+        }
+    }
+
+    pub(crate) fn release_dynamic(&self, id: TypeId) {
+        if let Some(x) = self.state.get(&id) {
+            x.borrow.release();
+        }
+    }
+
+    pub(crate) fn release_mut_dynamic(&self, id: TypeId) {
+        if let Some(x) = self.state.get(&id) {
+            x.borrow.release_mut();
+        }
+    }
+
     pub(crate) fn release<T: Component>(&self) {
         if let Some(x) = self.state.get(&TypeId::of::<T>()) {
             x.borrow.release();
@@ -140,6 +328,13 @@ impl Archetype {
         &self.types
     }
 
+    fn type_name(&self, id: TypeId) -> &'static str {
+        self.types
+            .iter()
+            .find(|ty| ty.id == id)
+            .map_or("<unknown>", |ty| ty.type_name)
+    }
+
     /// Enumerate the types of the components of entities stored in this archetype.
     ///
     /// Convenient for dispatching logic which needs to be performed on sets of type ids.
@@ -155,6 +350,13 @@ impl Archetype {
         self.types.iter().map(|typeinfo| typeinfo.id)
     }
 
+    /// Pointer to the first element of the `ty` column of `chunk`, if present
+    pub(crate) fn get_base(&self, ty: TypeId, chunk: usize) -> Option<NonNull<u8>> {
+        let state = self.state.get(&ty)?;
+        let base = self.chunks.get(chunk)?;
+        Some(unsafe { NonNull::new_unchecked(base.as_ptr().add(state.offset)) })
+    }
+
     /// `index` must be in-bounds
     pub(crate) unsafe fn get_dynamic(
         &self,
@@ -163,8 +365,10 @@ impl Archetype {
         index: u32,
     ) -> Option<NonNull<u8>> {
         debug_assert!(index < self.len);
+        let (chunk, index) = self.locate(index);
         Some(NonNull::new_unchecked(
-            (*self.data.get())
+            self.chunks
+                .get_unchecked(chunk)
                 .as_ptr()
                 .add(self.state.get(&ty)?.offset + size * index as usize)
                 .cast::<u8>(),
@@ -172,9 +376,9 @@ impl Archetype {
     }
 
     /// Every type must be written immediately after this call
-    pub(crate) unsafe fn allocate(&mut self, id: u32) -> u32 {
+    pub(crate) unsafe fn allocate<A: Allocator>(&mut self, alloc: &A, id: u32) -> u32 {
         if self.len as usize == self.entities.len() {
-            self.grow(self.len.max(64));
+            self.grow(alloc, self.len.max(64));
         }
 
         self.entities[self.len as usize] = id;
@@ -182,9 +386,9 @@ impl Archetype {
         self.len - 1
     }
 
-    pub(crate) fn reserve(&mut self, additional: u32) {
+    pub(crate) fn reserve<A: Allocator>(&mut self, alloc: &A, additional: u32) {
         if additional > (self.capacity() - self.len()) {
-            self.grow(additional - (self.capacity() - self.len()));
+            self.grow(alloc, additional - (self.capacity() - self.len()));
         }
     }
 
@@ -192,57 +396,118 @@ impl Archetype {
         self.entities.len() as u32
     }
 
-    fn grow(&mut self, increment: u32) {
+    /// Bytes allocated for component data
+    pub(crate) fn data_size(&self) -> usize {
+        self.chunks.len() * self.chunk_size
+    }
+
+    /// Release unused capacity
+    ///
+    /// Chunked archetypes free chunks that no longer contain any entities.
+    pub(crate) fn shrink_to_fit<A: Allocator>(&mut self, alloc: &A) {
+        if self.chunked {
+            let used = self.chunk_count();
+            if self.chunks.len() > used {
+                let layout = chunk_layout(&self.types, self.chunk_size);
+                for chunk in self.chunks.drain(used..) {
+                    unsafe {
+                        alloc.deallocate(chunk, layout);
+                    }
+                }
+                self.chunks.shrink_to_fit();
+                self.entities
+                    .resize(alloc, used * self.chunk_capacity as usize, !0);
+                self.entities.shrink_to_fit(alloc);
+            }
+        } else if self.capacity() > self.len {
+            self.reallocate(alloc, self.len as usize);
+        }
+    }
+
+    /// Drop every component and release all memory
+    pub(crate) fn free<A: Allocator>(&mut self, alloc: &A) {
+        self.clear();
+        if self.chunk_size != 0 {
+            let layout = chunk_layout(&self.types, self.chunk_size);
+            for chunk in self.chunks.drain(..) {
+                unsafe {
+                    alloc.deallocate(chunk, layout);
+                }
+            }
+        }
+        self.entities.free(alloc);
+    }
+
+    fn grow<A: Allocator>(&mut self, alloc: &A, increment: u32) {
+        if self.chunked {
+            self.grow_chunks(alloc, increment);
+        } else {
+            self.reallocate(alloc, self.entities.len() + increment as usize);
+        }
+    }
+
+    /// Move the components of an unchunked archetype into an allocation with room for exactly
+    /// `count` entities
+    fn reallocate<A: Allocator>(&mut self, alloc: &A, count: usize) {
+        debug_assert!(count >= self.len as usize);
         unsafe {
             let old_count = self.len as usize;
-            let count = old_count + increment as usize;
-            let mut new_entities = vec![!0; count].into_boxed_slice();
-            new_entities[0..old_count].copy_from_slice(&self.entities[0..old_count]);
-            self.entities = new_entities;
+            if count > self.entities.len() {
+                self.entities
+                    .reserve_exact(alloc, count - self.entities.len());
+            }
+            self.entities.resize(alloc, count, !0);
+            self.entities.shrink_to_fit(alloc);
 
-            let old_data_size = mem::replace(&mut self.data_size, 0);
+            let old_data_size = mem::replace(&mut self.chunk_size, 0);
             let mut state = HashMap::with_capacity_and_hasher(self.types.len(), Default::default());
             for ty in &self.types {
-                self.data_size = align(self.data_size, ty.layout.align());
-                state.insert(ty.id, TypeState::new(self.data_size));
-                self.data_size += ty.layout.size() * count;
+                self.chunk_size = align(self.chunk_size, ty.layout.align());
+                state.insert(ty.id, TypeState::new(self.chunk_size));
+                self.chunk_size += ty.layout.size() * count;
             }
-            let new_data = if self.data_size == 0 {
+            let new_data = if self.chunk_size == 0 {
                 NonNull::dangling()
             } else {
-                NonNull::new(alloc(
-                    Layout::from_size_align(
-                        self.data_size,
-                        self.types.first().map_or(1, |x| x.layout.align()),
-                    )
-                    .unwrap(),
-                ))
-                .unwrap()
+                alloc
+                    .allocate(chunk_layout(&self.types, self.chunk_size))
+                    .unwrap()
             };
+            let chunks = &mut self.chunks;
             if old_data_size != 0 {
                 for ty in &self.types {
                     let old_off = self.state.get(&ty.id).unwrap().offset;
                     let new_off = state.get(&ty.id).unwrap().offset;
                     ptr::copy_nonoverlapping(
-                        (*self.data.get()).as_ptr().add(old_off),
+                        chunks[0].as_ptr().add(old_off),
                         new_data.as_ptr().add(new_off),
                         ty.layout.size() * old_count,
                     );
                 }
-                dealloc(
-                    (*self.data.get()).as_ptr().cast(),
-                    Layout::from_size_align_unchecked(
-                        old_data_size,
-                        self.types.first().map_or(1, |x| x.layout.align()),
-                    ),
-                );
+                alloc.deallocate(chunks[0], chunk_layout(&self.types, old_data_size));
             }
 
-            self.data = UnsafeCell::new(new_data);
+            chunks.clear();
+            chunks.push(new_data);
+            self.chunk_capacity = count as u32;
             self.state = state;
         }
     }
 
+    /// Add enough chunks for at least `increment` more entities, leaving existing chunks in place
+    fn grow_chunks<A: Allocator>(&mut self, alloc: &A, increment: u32) {
+        let new_chunks = (increment as usize).div_ceil(self.chunk_capacity as usize);
+        let layout = chunk_layout(&self.types, self.chunk_size);
+        for _ in 0..new_chunks {
+            self.chunks.push(alloc.allocate(layout).unwrap());
+        }
+
+        let additional = new_chunks * self.chunk_capacity as usize;
+        self.entities.reserve_exact(alloc, additional);
+        self.entities
+            .resize(alloc, self.entities.len() + additional, !0);
+    }
+
     /// Returns the ID of the entity moved into `index`, if any
     pub(crate) unsafe fn remove(&mut self, index: u32) -> Option<u32> {
         let last = self.len - 1;
@@ -303,6 +568,168 @@ impl Archetype {
         }
     }
 
+    /// Drop the components of every entity whose index is set in `remove`, moving the remaining
+    /// entities down to fill the gaps without changing their order
+    pub(crate) unsafe fn remove_rows(&mut self, remove: &[bool]) {
+        debug_assert_eq!(remove.len(), self.len as usize);
+        let mut len = 0;
+        for index in 0..self.len {
+            if remove[index as usize] {
+                for ty in &self.types {
+                    let removed = self
+                        .get_dynamic(ty.id, ty.layout.size(), index)
+                        .unwrap()
+                        .as_ptr();
+                    (ty.drop)(removed);
+                }
+                continue;
+            }
+            if len != index {
+                for ty in &self.types {
+                    ptr::copy_nonoverlapping(
+                        self.get_dynamic(ty.id, ty.layout.size(), index)
+                            .unwrap()
+                            .as_ptr(),
+                        self.get_dynamic(ty.id, ty.layout.size(), len)
+                            .unwrap()
+                            .as_ptr(),
+                        ty.layout.size(),
+                    );
+                }
+                self.entities[len as usize] = self.entities[index as usize];
+            }
+            len += 1;
+        }
+        self.len = len;
+    }
+
+    /// Move the entities whose index is set in `mask` to the end of `target`, preserving their
+    /// order, and move the remaining entities down to fill the gaps
+    ///
+    /// Entities beyond the end of `mask` stay put. Components of types `target` lacks are passed
+    /// to `removed`, and components of types only `target` has are left uninitialized, so they
+    /// must be written immediately. Returns the index in `target` of the first moved entity.
+    pub(crate) unsafe fn move_rows<A: Allocator>(
+        &mut self,
+        alloc: &A,
+        mask: &[bool],
+        target: &mut Archetype,
+        mut removed: impl FnMut(*mut u8, &TypeInfo),
+    ) -> u32 {
+        let count = mask.iter().take(self.len as usize).filter(|&&x| x).count() as u32;
+        target.reserve(alloc, count);
+        let base = target.len;
+        target.len += count;
+        if count == 0 {
+            return base;
+        }
+
+        // Each run of entities that are all moving or all staying is copied column by column
+        let mut next = base;
+        let mut len = 0;
+        let mut index = 0;
+        while index < self.len {
+            let moving = mask.get(index as usize).copied().unwrap_or(false);
+            let run = (index..self.len)
+                .take_while(|&i| mask.get(i as usize).copied().unwrap_or(false) == moving)
+                .count() as u32;
+            if moving {
+                self.for_each_run(index, &*target, next, run, |src, dst, n| {
+                    for ty in &self.types {
+                        let size = ty.layout.size();
+                        let src = self.get_dynamic(ty.id, size, src).unwrap().as_ptr();
+                        match target.get_dynamic(ty.id, size, dst) {
+                            Some(dst) => {
+                                ptr::copy_nonoverlapping(src, dst.as_ptr(), size * n as usize)
+                            }
+                            None => {
+                                for i in 0..n as usize {
+                                    removed(src.add(i * size), ty);
+                                }
+                            }
+                        }
+                    }
+                });
+                target.entities[next as usize..(next + run) as usize]
+                    .copy_from_slice(&self.entities[index as usize..(index + run) as usize]);
+                next += run;
+            } else {
+                if len != index {
+                    self.for_each_run(index, self, len, run, |src, dst, n| {
+                        for ty in &self.types {
+                            let size = ty.layout.size();
+                            // Runs are copied towards the start, so may overlap themselves
+                            ptr::copy(
+                                self.get_dynamic(ty.id, size, src).unwrap().as_ptr(),
+                                self.get_dynamic(ty.id, size, dst).unwrap().as_ptr(),
+                                size * n as usize,
+                            );
+                        }
+                    });
+                    self.entities
+                        .copy_within(index as usize..(index + run) as usize, len as usize);
+                }
+                len += run;
+            }
+            index += run;
+        }
+        self.len = len;
+        base
+    }
+
+    /// Call `f(src, dst, n)` for successive pieces of the `count` entities starting at `src` in
+    /// `self` and `dst` in `target`, such that each piece is stored contiguously in both
+    fn for_each_run(
+        &self,
+        mut src: u32,
+        target: &Archetype,
+        mut dst: u32,
+        mut count: u32,
+        mut f: impl FnMut(u32, u32, u32),
+    ) {
+        while count > 0 {
+            let n = count
+                .min(self.contiguous_len(src))
+                .min(target.contiguous_len(dst));
+            f(src, dst, n);
+            src += n;
+            dst += n;
+            count -= n;
+        }
+    }
+
+    /// Rearrange entities so that the entity at index `order[i]` moves to index `i`
+    ///
+    /// `order` must be a permutation of `0..self.len()`.
+    pub(crate) fn permute(&mut self, order: &[u32]) {
+        debug_assert_eq!(order.len(), self.len as usize);
+        let mut scratch = Vec::new();
+        for ty in &self.types {
+            let size = ty.layout.size();
+            if size == 0 {
+                continue;
+            }
+            scratch.clear();
+            scratch.resize(size * order.len(), MaybeUninit::<u8>::uninit());
+            let buffer = scratch.as_mut_ptr().cast::<u8>();
+            unsafe {
+                for (new, &old) in order.iter().enumerate() {
+                    let src = self.get_dynamic(ty.id, size, old).unwrap().as_ptr();
+                    ptr::copy_nonoverlapping(src, buffer.add(new * size), size);
+                }
+                for new in 0..self.len {
+                    let dst = self.get_dynamic(ty.id, size, new).unwrap().as_ptr();
+                    ptr::copy_nonoverlapping(buffer.add(new as usize * size), dst, size);
+                }
+            }
+        }
+        let entities = order
+            .iter()
+            .map(|&old| self.entities[old as usize])
+            .collect::<Vec<_>>();
+        self.entities[..entities.len()].copy_from_slice(&entities);
+    }
+
     pub(crate) unsafe fn put_dynamic(
         &mut self,
         component: *mut u8,
@@ -324,20 +751,35 @@ impl Archetype {
     }
 }
 
-impl Drop for Archetype {
+impl fmt::Debug for Archetype {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Archetype")
+            .field("types", &TypeNames(&self.types))
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+/// Shared borrow of an archetype's column, released when dropped
+pub(crate) struct ColumnBorrow<'a> {
+    archetype: &'a Archetype,
+    id: TypeId,
+}
+
+impl Drop for ColumnBorrow<'_> {
     fn drop(&mut self) {
-        self.clear();
-        if self.data_size != 0 {
-            unsafe {
-                dealloc(
-                    (*self.data.get()).as_ptr().cast(),
-                    Layout::from_size_align_unchecked(
-                        self.data_size,
-                        self.types.first().map_or(1, |x| x.layout.align()),
-                    ),
-                );
-            }
-        }
+        self.archetype.release_dynamic(self.id);
+    }
+}
+
+/// Formats a list of component types by name
+pub(crate) struct TypeNames<'a>(pub(crate) &'a [TypeInfo]);
+
+impl fmt::Debug for TypeNames<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|ty| ty.type_name))
+            .finish()
     }
 }
 
@@ -405,6 +847,7 @@ pub struct TypeInfo {
     id: TypeId,
     layout: Layout,
     drop: unsafe fn(*mut u8),
+    type_name: &'static str,
 }
 
 impl TypeInfo {
@@ -418,6 +861,7 @@ impl TypeInfo {
             id: TypeId::of::<T>(),
             layout: Layout::new::<T>(),
             drop: drop_ptr::<T>,
+            type_name: type_name::<T>(),
         }
     }
 
@@ -429,6 +873,10 @@ impl TypeInfo {
         self.layout
     }
 
+    pub(crate) fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
     pub(crate) unsafe fn drop(&self, data: *mut u8) {
         (self.drop)(data)
     }
@@ -459,6 +907,11 @@ impl PartialEq for TypeInfo {
 
 impl Eq for TypeInfo {}
 
+/// Layout of a chunk of `size` bytes holding columns of `types`
+fn chunk_layout(types: &[TypeInfo], size: usize) -> Layout {
+    Layout::from_size_align(size, types.first().map_or(1, |x| x.layout.align())).unwrap()
+}
+
 fn align(x: usize, alignment: usize) -> usize {
     debug_assert!(alignment.is_power_of_two());
     (x + alignment - 1) & (!alignment + 1)
@@ -12,12 +12,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::alloc::vec::Vec;
 use core::any::TypeId;
+use core::fmt;
 use core::ops::{Deref, DerefMut};
 use core::ptr::NonNull;
 use core::sync::atomic::{AtomicUsize, Ordering};
 
-use crate::archetype::Archetype;
+use crate::archetype::{Archetype, TypeInfo, TypeNames};
+use crate::sparse::SparseSets;
 use crate::{Component, MissingComponent};
 
 pub struct AtomicBorrow(AtomicUsize);
@@ -73,13 +76,9 @@ impl<'a, T: Component> Ref<'a, T> {
         archetype: &'a Archetype,
         index: u32,
     ) -> Result<Self, MissingComponent> {
-        let target = NonNull::new_unchecked(
-            archetype
-                .get::<T>()
-                .ok_or_else(MissingComponent::new::<T>)?
-                .as_ptr()
-                .add(index as usize),
-        );
+        let target = archetype
+            .get_at::<T>(index)
+            .ok_or_else(MissingComponent::new::<T>)?;
         archetype.borrow::<T>();
         Ok(Self { archetype, target })
     }
@@ -112,13 +111,9 @@ impl<'a, T: Component> RefMut<'a, T> {
         archetype: &'a Archetype,
         index: u32,
     ) -> Result<Self, MissingComponent> {
-        let target = NonNull::new_unchecked(
-            archetype
-                .get::<T>()
-                .ok_or_else(MissingComponent::new::<T>)?
-                .as_ptr()
-                .add(index as usize),
-        );
+        let target = archetype
+            .get_at::<T>(index)
+            .ok_or_else(MissingComponent::new::<T>)?;
         archetype.borrow_mut::<T>();
         Ok(Self { archetype, target })
     }
@@ -151,21 +146,43 @@ impl<'a, T: Component> DerefMut for RefMut<'a, T> {
 pub struct EntityRef<'a> {
     archetype: Option<&'a Archetype>,
     index: u32,
+    id: u32,
+    sparse: &'a SparseSets,
 }
 
 impl<'a> EntityRef<'a> {
-    /// Construct a `Ref` for an entity with no components
-    pub(crate) fn empty() -> Self {
+    /// Construct a `Ref` for the entity `id`, which has no components stored in archetypes
+    pub(crate) fn empty(sparse: &'a SparseSets, id: u32) -> Self {
         Self {
             archetype: None,
             index: 0,
+            id,
+            sparse,
         }
     }
 
-    pub(crate) unsafe fn new(archetype: &'a Archetype, index: u32) -> Self {
+    pub(crate) unsafe fn new(archetype: &'a Archetype, index: u32, sparse: &'a SparseSets) -> Self {
         Self {
             archetype: Some(archetype),
             index,
+            id: archetype.entity_id(index),
+            sparse,
+        }
+    }
+
+    /// The archetype and index at which the entity's component of type `ty` is stored, if any
+    ///
+    /// Components in sparse-set storage are found in their set's own archetype.
+    pub(crate) fn component(&self, ty: TypeId) -> Option<(&'a Archetype, u32)> {
+        match self.sparse.get(ty) {
+            Some(set) => Some((set.storage(), set.row(self.id)?)),
+            None => {
+                let archetype = self.archetype?;
+                if !archetype.has_dynamic(ty) {
+                    return None;
+                }
+                Some((archetype, self.index))
+            }
         }
     }
 
@@ -174,14 +191,16 @@ impl<'a> EntityRef<'a> {
     /// Panics if the component is already uniquely borrowed from another entity with the same
     /// components.
     pub fn get<T: Component>(&self) -> Option<Ref<'a, T>> {
-        Some(unsafe { Ref::new(self.archetype?, self.index).ok()? })
+        let (archetype, index) = self.component(TypeId::of::<T>())?;
+        Some(unsafe { Ref::new(archetype, index).ok()? })
     }
 
     /// Uniquely borrow the component of type `T`, if it exists
     ///
     /// Panics if the component is already borrowed from another entity with the same components.
     pub fn get_mut<T: Component>(&self) -> Option<RefMut<'a, T>> {
-        Some(unsafe { RefMut::new(self.archetype?, self.index).ok()? })
+        let (archetype, index) = self.component(TypeId::of::<T>())?;
+        Some(unsafe { RefMut::new(archetype, index).ok()? })
     }
 
     /// Enumerate the types of the entity's components
@@ -191,9 +210,30 @@ impl<'a> EntityRef<'a> {
     /// user-defined trait with methods for serialization, or to be called after spawning or before
     /// despawning to maintain secondary indices.
     pub fn component_types(&self) -> impl Iterator<Item = TypeId> + 'a {
+        self.component_infos().map(|ty| ty.id())
+    }
+
+    /// Enumerate the type metadata of the entity's components
+    pub(crate) fn component_infos(&self) -> impl Iterator<Item = TypeInfo> + 'a {
+        let id = self.id;
         self.archetype
             .into_iter()
-            .flat_map(|arch| arch.types().iter().map(|ty| ty.id()))
+            .flat_map(|arch| arch.types().iter().copied())
+            .chain(
+                self.sparse
+                    .iter()
+                    .filter(move |set| set.row(id).is_some())
+                    .map(|set| set.type_info()),
+            )
+    }
+}
+
+impl fmt::Debug for EntityRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let types = self.component_infos().collect::<Vec<_>>();
+        f.debug_struct("EntityRef")
+            .field("components", &TypeNames(&types))
+            .finish()
     }
 }
 
@@ -34,6 +34,13 @@ pub trait DynamicBundle {
     /// called at most once on any given value.
     #[doc(hidden)]
     unsafe fn put(self, f: impl FnMut(*mut u8, TypeInfo));
+    /// Identifier shared by every value with the same set of component types, if cheaply known
+    ///
+    /// Used to cache archetype transitions.
+    #[doc(hidden)]
+    fn key(&self) -> Option<TypeId> {
+        None
+    }
 }
 
 /// A statically typed collection of components
@@ -45,6 +52,14 @@ pub trait Bundle: DynamicBundle {
     #[doc(hidden)]
     fn static_type_info() -> Vec<TypeInfo>;
 
+    /// Identifier for this bundle's set of component types, if cheaply known
+    ///
+    /// See `DynamicBundle::key`.
+    #[doc(hidden)]
+    fn static_key() -> Option<TypeId> {
+        None
+    }
+
     /// Construct `Self` by moving components out of pointers fetched by `f`
     ///
     /// # Safety
@@ -88,6 +103,10 @@ macro_rules! tuple_impl {
                 Self::static_type_info()
             }
 
+            fn key(&self) -> Option<TypeId> {
+                Self::static_key()
+            }
+
             #[allow(unused_variables, unused_mut)]
             unsafe fn put(self, mut f: impl FnMut(*mut u8, TypeInfo)) {
                 #[allow(non_snake_case)]
@@ -120,6 +139,10 @@ macro_rules! tuple_impl {
                 xs
             }
 
+            fn static_key() -> Option<TypeId> {
+                Some(TypeId::of::<Self>())
+            }
+
             #[allow(unused_variables, unused_mut)]
             unsafe fn get(mut f: impl FnMut(TypeInfo) -> Option<NonNull<u8>>) -> Result<Self, MissingComponent> {
                 #[allow(non_snake_case)]
@@ -0,0 +1,404 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::alloc::vec::Vec;
+use core::any::TypeId;
+use core::ops::Range;
+use core::ptr::NonNull;
+
+use crate::archetype::Archetype;
+use crate::entities::EntityMeta;
+use crate::{Access, Entity};
+
+/// A query whose component types are only known at runtime
+///
+/// Built from lists of component `TypeId`s, and executed with `World::query_dynamic`. Useful for
+/// scripting integration and other tooling that can't name component types statically. Entities
+/// are matched if they have every `read`, `write`, and `with` type, and none of the `without`
+/// types.
+///
+/// Query results expose raw pointers to components, ordered as every `read` type in the order
+/// they were added followed by every `write` type in the order they were added.
+///
+/// # Example
+/// ```
+/// # use hecs::*;
+/// # use std::any::TypeId;
+/// let mut world = World::new();
+/// let a = world.spawn((123, true));
+/// let b = world.spawn((456,));
+/// let mut query = DynamicQuery::new();
+/// query.write(TypeId::of::<i32>()).without(TypeId::of::<bool>());
+/// for (_, row) in world.query_dynamic(&query).iter() {
+///     unsafe { *row.get(0).cast::<i32>().as_ptr() *= 2; }
+/// }
+/// assert_eq!(*world.get::<i32>(a).unwrap(), 123);
+/// assert_eq!(*world.get::<i32>(b).unwrap(), 912);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct DynamicQuery {
+    read: Vec<TypeId>,
+    write: Vec<TypeId>,
+    with: Vec<TypeId>,
+    without: Vec<TypeId>,
+}
+
+impl DynamicQuery {
+    /// Create a query matching every entity and accessing no components
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Require and immutably borrow components of type `ty`
+    pub fn read(&mut self, ty: TypeId) -> &mut Self {
+        self.read.push(ty);
+        self
+    }
+
+    /// Require and uniquely borrow components of type `ty`
+    pub fn write(&mut self, ty: TypeId) -> &mut Self {
+        self.write.push(ty);
+        self
+    }
+
+    /// Require components of type `ty` without borrowing them
+    pub fn with(&mut self, ty: TypeId) -> &mut Self {
+        self.with.push(ty);
+        self
+    }
+
+    /// Skip entities having components of type `ty`
+    pub fn without(&mut self, ty: TypeId) -> &mut Self {
+        self.without.push(ty);
+        self
+    }
+
+    /// Number of component pointers yielded for each entity
+    pub fn columns(&self) -> usize {
+        self.read.len() + self.write.len()
+    }
+
+    /// How, if at all, this query will access entities in `archetype`
+    pub fn access(&self, archetype: &Archetype) -> Option<Access> {
+        let required = self.read.iter().chain(&self.write).chain(&self.with);
+        for &ty in required {
+            if !archetype.has_dynamic(ty) {
+                return None;
+            }
+        }
+        if self.without.iter().any(|&ty| archetype.has_dynamic(ty)) {
+            return None;
+        }
+        Some(if !self.write.is_empty() {
+            Access::Write
+        } else if !self.read.is_empty() {
+            Access::Read
+        } else {
+            Access::Iterate
+        })
+    }
+
+    fn borrow(&self, archetype: &Archetype) {
+        for &ty in &self.read {
+            archetype.borrow_dynamic(ty);
+        }
+        for &ty in &self.write {
+            archetype.borrow_mut_dynamic(ty);
+        }
+    }
+
+    fn release(&self, archetype: &Archetype) {
+        for &ty in &self.read {
+            archetype.release_dynamic(ty);
+        }
+        for &ty in &self.write {
+            archetype.release_mut_dynamic(ty);
+        }
+    }
+}
+
+/// A borrow of a `World` sufficient to execute a `DynamicQuery`
+///
+/// Note that borrows are not released until this object is dropped.
+pub struct DynamicQueryBorrow<'w> {
+    meta: &'w [EntityMeta],
+    archetypes: &'w [Archetype],
+    query: &'w DynamicQuery,
+    /// Archetype chunks matched by the query, and the range of `columns` that describes each
+    chunks: Vec<(&'w Archetype, usize, Range<usize>)>,
+    columns: Vec<Column>,
+    borrowed: bool,
+}
+
+impl<'w> DynamicQueryBorrow<'w> {
+    pub(crate) fn new(
+        meta: &'w [EntityMeta],
+        archetypes: &'w [Archetype],
+        query: &'w DynamicQuery,
+    ) -> Self {
+        Self {
+            meta,
+            archetypes,
+            query,
+            chunks: Vec::new(),
+            columns: Vec::new(),
+            borrowed: false,
+        }
+    }
+
+    /// Execute the query, yielding each matched entity's component pointers
+    ///
+    /// Must be called only once per query.
+    pub fn iter<'q>(&'q mut self) -> DynamicQueryIter<'q, 'w> {
+        self.borrow();
+        let borrow = &*self;
+        DynamicQueryIter {
+            chunks: borrow.chunks(),
+            current: None,
+            index: 0,
+        }
+    }
+
+    /// Execute the query, yielding the component columns of each chunk of each matched archetype
+    ///
+    /// Must be called only once per query.
+    pub fn iter_chunks<'q>(&'q mut self) -> DynamicChunks<'q, 'w> {
+        self.borrow();
+        let borrow = &*self;
+        borrow.chunks()
+    }
+
+    fn chunks<'q>(&'q self) -> DynamicChunks<'q, 'w> {
+        DynamicChunks {
+            borrow: self,
+            inner: self.chunks.iter(),
+        }
+    }
+
+    fn borrow(&mut self) {
+        if self.borrowed {
+            panic!(
+                "called DynamicQueryBorrow::iter twice on the same borrow; construct a new query instead"
+            );
+        }
+        for x in self.archetypes {
+            let access = self.query.access(x);
+            // TODO: Release prior borrows on failure?
+            if access >= Some(Access::Read) {
+                self.query.borrow(x);
+            }
+            if access.is_none() {
+                continue;
+            }
+            for chunk in 0..x.chunk_count() {
+                let start = self.columns.len();
+                for &ty in self.query.read.iter().chain(&self.query.write) {
+                    let size = x
+                        .types()
+                        .iter()
+                        .find(|info| info.id() == ty)
+                        .unwrap()
+                        .layout()
+                        .size();
+                    self.columns.push(Column {
+                        base: x.get_base(ty, chunk).unwrap(),
+                        size,
+                    });
+                }
+                self.chunks.push((x, chunk, start..self.columns.len()));
+            }
+        }
+        self.borrowed = true;
+    }
+}
+
+unsafe impl Send for DynamicQueryBorrow<'_> {}
+unsafe impl Sync for DynamicQueryBorrow<'_> {}
+
+impl Drop for DynamicQueryBorrow<'_> {
+    fn drop(&mut self) {
+        if self.borrowed {
+            for x in self.archetypes {
+                if self.query.access(x) >= Some(Access::Read) {
+                    self.query.release(x);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Column {
+    base: NonNull<u8>,
+    size: usize,
+}
+
+impl Column {
+    unsafe fn get(&self, index: usize) -> NonNull<u8> {
+        NonNull::new_unchecked(self.base.as_ptr().add(self.size * index))
+    }
+}
+
+/// Iterator over the archetypes matched by a `DynamicQuery`
+pub struct DynamicChunks<'q, 'w> {
+    borrow: &'q DynamicQueryBorrow<'w>,
+    inner: core::slice::Iter<'q, (&'w Archetype, usize, Range<usize>)>,
+}
+
+unsafe impl Send for DynamicChunks<'_, '_> {}
+unsafe impl Sync for DynamicChunks<'_, '_> {}
+
+impl<'q, 'w> Iterator for DynamicChunks<'q, 'w> {
+    type Item = DynamicChunk<'q>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (archetype, chunk, ref columns) = *self.inner.next()?;
+        Some(DynamicChunk {
+            meta: self.borrow.meta,
+            archetype,
+            chunk,
+            columns: &self.borrow.columns[columns.clone()],
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl ExactSizeIterator for DynamicChunks<'_, '_> {}
+
+/// Contiguous columns of components for the entities in one chunk of an archetype matched by a
+/// `DynamicQuery`
+#[derive(Copy, Clone)]
+pub struct DynamicChunk<'q> {
+    meta: &'q [EntityMeta],
+    archetype: &'q Archetype,
+    chunk: usize,
+    columns: &'q [Column],
+}
+
+impl<'q> DynamicChunk<'q> {
+    /// Number of entities in this chunk
+    pub fn len(&self) -> usize {
+        self.archetype.chunk_len(self.chunk) as usize
+    }
+
+    /// Whether this chunk contains no entities
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The archetype this chunk's entities belong to
+    pub fn archetype(&self) -> &'q Archetype {
+        self.archetype
+    }
+
+    /// The entity in row `index`
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn entity(&self, index: usize) -> Entity {
+        assert!(index < self.len(), "index out of bounds");
+        let id = self
+            .archetype
+            .entity_id(self.archetype.chunk_start(self.chunk) + index as u32);
+        Entity {
+            id,
+            generation: self.meta[id as usize].generation,
+        }
+    }
+
+    /// Pointer to the first element of the query's `column`th component type
+    ///
+    /// Rows of the column are laid out contiguously, in the same order as `entity`.
+    ///
+    /// Panics if `column` is out of bounds.
+    pub fn column(&self, column: usize) -> NonNull<u8> {
+        self.columns[column].base
+    }
+}
+
+/// Component pointers for a single entity matched by a `DynamicQuery`
+#[derive(Copy, Clone)]
+pub struct DynamicRow<'q> {
+    columns: &'q [Column],
+    index: usize,
+}
+
+impl<'q> DynamicRow<'q> {
+    /// Number of component pointers in this row
+    pub fn len(&self) -> usize {
+        self.columns.len()
+    }
+
+    /// Whether this row contains no component pointers
+    pub fn is_empty(&self) -> bool {
+        self.columns.is_empty()
+    }
+
+    /// Pointer to the entity's component of the query's `column`th type
+    ///
+    /// The pointee may only be mutated if `column` refers to a `write` type.
+    ///
+    /// Panics if `column` is out of bounds.
+    pub fn get(&self, column: usize) -> NonNull<u8> {
+        unsafe { self.columns[column].get(self.index) }
+    }
+
+    /// Iterate over the entity's component pointers in query order
+    pub fn iter(&self) -> impl Iterator<Item = NonNull<u8>> + 'q {
+        let index = self.index;
+        self.columns.iter().map(move |x| unsafe { x.get(index) })
+    }
+}
+
+/// Iterator over the set of entities matched by a `DynamicQuery`
+pub struct DynamicQueryIter<'q, 'w> {
+    chunks: DynamicChunks<'q, 'w>,
+    current: Option<DynamicChunk<'q>>,
+    index: usize,
+}
+
+unsafe impl Send for DynamicQueryIter<'_, '_> {}
+unsafe impl Sync for DynamicQueryIter<'_, '_> {}
+
+impl<'q, 'w> Iterator for DynamicQueryIter<'q, 'w> {
+    type Item = (Entity, DynamicRow<'q>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.current {
+                None => {
+                    self.current = Some(self.chunks.next()?);
+                    self.index = 0;
+                }
+                Some(chunk) => {
+                    if self.index == chunk.len() {
+                        self.current = None;
+                        continue;
+                    }
+                    let index = self.index;
+                    self.index += 1;
+                    return Some((
+                        chunk.entity(index),
+                        DynamicRow {
+                            columns: chunk.columns,
+                            index,
+                        },
+                    ));
+                }
+            }
+        }
+    }
+}
@@ -1,39 +1,60 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
+#[cfg(not(feature = "std"))]
+use core::cell::UnsafeCell;
 use core::cmp;
 use core::convert::TryFrom;
-use core::sync::atomic::{AtomicI64, Ordering};
+use core::num::NonZeroU32;
+use core::ops::Range;
+#[cfg(not(feature = "std"))]
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, AtomicI64, Ordering};
 use core::{fmt, mem};
 #[cfg(feature = "std")]
 use std::error::Error;
 
+use hashbrown::HashMap;
+
+use crate::allocator::{AllocVec, Allocator};
+
 /// Lightweight unique ID of an entity
 ///
 /// Obtained from `World::spawn`. Can be stored to refer to an entity in the future.
+/// `Option<Entity>` is the same size as `Entity`.
 #[derive(Clone, Copy, Hash, Eq, Ord, PartialEq, PartialOrd)]
 pub struct Entity {
-    pub(crate) generation: u32,
+    pub(crate) generation: Generation,
     pub(crate) id: u32,
 }
 
+/// Counter distinguishing successive entities that reuse the same ID
+///
+/// Never zero, so that `Option<Entity>` is no larger than `Entity`.
+pub(crate) type Generation = NonZeroU32;
+
 impl Entity {
-    /// Convert to a form convenient for passing outside of rust
-    ///
-    /// Only useful for identifying entities within the same instance of an application. Do not use
-    /// for serialization between runs.
+    /// Convert to a form convenient for passing outside of rust, or for persisting
     ///
-    /// No particular structure is guaranteed for the returned bits.
+    /// The generation occupies the high 32 bits and the ID the low 32 bits, so the result is never
+    /// zero. This layout is stable, so the bits may be saved and later passed to `from_bits`, as
+    /// `Entity`'s serde implementation does. A restored entity is only meaningful in the world it
+    /// came from, or once translated to another world with an `EntityMap`.
     pub fn to_bits(self) -> u64 {
-        u64::from(self.generation) << 32 | u64::from(self.id)
+        u64::from(self.generation.get()) << 32 | u64::from(self.id)
     }
 
     /// Reconstruct an `Entity` previously destructured with `to_bits`
     ///
-    /// Only useful when applied to results from `to_bits` in the same instance of an application.
-    pub fn from_bits(bits: u64) -> Self {
-        Self {
-            generation: (bits >> 32) as u32,
+    /// Returns `None` if `bits` could not have been produced by `to_bits`, e.g. if they're zero.
+    /// Generations start at one, so bits produced by versions of hecs whose generations started
+    /// at zero may be rejected or refer to a different entity.
+    pub fn from_bits(bits: u64) -> Option<Self> {
+        Some(Self {
+            generation: Generation::new((bits >> 32) as _)?,
             id: bits as u32,
-        }
+        })
     }
 
     /// Extract a transiently unique identifier
@@ -52,16 +73,103 @@ impl fmt::Debug for Entity {
     }
 }
 
+#[cfg(feature = "serde")]
+impl serde::Serialize for Entity {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_bits().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Entity {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bits = u64::deserialize(deserializer)?;
+        Entity::from_bits(bits).ok_or_else(|| {
+            serde::de::Error::invalid_value(
+                serde::de::Unexpected::Other("entity with zero generation"),
+                &"an entity",
+            )
+        })
+    }
+}
+
+/// An `Entity` together with the number of times its ID's generation has wrapped around
+///
+/// Obtained from `World::wide_entity`. Where an `Entity` carries a 32-bit generation, a
+/// `WideEntity` effectively carries a 64-bit one, so it never refers to an entity spawned after the
+/// one it was obtained for was despawned, even in worlds whose generations wrap around; see
+/// `World::set_wide_generations`.
+#[derive(Debug, Clone, Copy, Hash, Eq, Ord, PartialEq, PartialOrd)]
+pub struct WideEntity {
+    pub(crate) epoch: u32,
+    pub(crate) entity: Entity,
+}
+
+impl WideEntity {
+    /// The entity referred to, which is only meaningful while the `WideEntity` is valid
+    pub fn entity(self) -> Entity {
+        self.entity
+    }
+
+    /// Convert to a form convenient for passing outside of rust
+    ///
+    /// The number of times the generation has wrapped around occupies the high 32 bits, followed
+    /// by `Entity::to_bits`.
+    pub fn to_bits(self) -> u128 {
+        u128::from(self.epoch) << 64 | u128::from(self.entity.to_bits())
+    }
+
+    /// Reconstruct a `WideEntity` previously destructured with `to_bits`
+    ///
+    /// Returns `None` if `bits` could not have been produced by `to_bits`.
+    pub fn from_bits(bits: u128) -> Option<Self> {
+        if bits >> 96 != 0 {
+            return None;
+        }
+        Some(Self {
+            epoch: (bits >> 64) as u32,
+            entity: Entity::from_bits(bits as u64)?,
+        })
+    }
+}
+
+/// Handle to a range of entity IDs configured on a `World`
+///
+/// Obtained from `World::add_id_range`. Entities spawned or reserved in a range always have IDs
+/// within it, so worlds allocating from disjoint ranges never hand out the same ID.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct IdRange(pub(crate) u32);
+
+impl IdRange {
+    /// The range used by `World::spawn` and `World::reserve_entities`
+    ///
+    /// Initially covers every ID; see `World::set_id_range`.
+    pub const DEFAULT: IdRange = IdRange(0);
+}
+
+/// Order in which the IDs of despawned entities are reused; see `World::set_reuse_policy`
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ReusePolicy {
+    /// Reuse the most recently freed ID first, keeping the range of live IDs compact
+    #[default]
+    Lifo,
+    /// Reuse the least recently freed ID first, maximizing the time before any ID is reused
+    Fifo,
+    /// Hold each freed ID back until this many more IDs have been freed, then reuse the most
+    /// recently released ID first
+    Quarantine(u32),
+}
+
 /// An iterator returning a sequence of Entity values from `Entities::reserve_entities`.
 pub struct ReserveEntitiesIterator<'a> {
-    // Metas, so we can recover the current generation for anything in the freelist.
-    meta: &'a [EntityMeta],
-
     // Reserved IDs formerly in the freelist to hand out.
-    id_iter: core::slice::Iter<'a, u32>,
+    id_iter: core::slice::Iter<'a, Entity>,
 
     // New Entity IDs to hand out, outside the range of meta.len().
     id_range: core::ops::Range<u32>,
+
+    // IDs already allocated from a `SharedEntities`.
+    shared: vec::IntoIter<Entity>,
 }
 
 impl<'a> Iterator for ReserveEntitiesIterator<'a> {
@@ -70,24 +178,216 @@ impl<'a> Iterator for ReserveEntitiesIterator<'a> {
     fn next(&mut self) -> Option<Self::Item> {
         self.id_iter
             .next()
-            .map(|&id| Entity {
-                generation: self.meta[id as usize].generation,
-                id,
+            .copied()
+            .or_else(|| {
+                self.id_range.next().map(|id| Entity {
+                    generation: Generation::MIN,
+                    id,
+                })
             })
-            .or_else(|| self.id_range.next().map(|id| Entity { generation: 0, id }))
+            .or_else(|| self.shared.next())
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
-        let len = self.id_iter.len() + self.id_range.len();
+        let len = self.id_iter.len() + self.id_range.len() + self.shared.len();
         (len, Some(len))
     }
 }
 
 impl<'a> core::iter::ExactSizeIterator for ReserveEntitiesIterator<'a> {}
 
+/// Entity ID allocator that can be shared by several `World`s
+///
+/// Worlds sharing an allocator never hand out the same ID for different entities, so an `Entity`
+/// spawned or reserved in one can be passed to `World::spawn_at` in the others to attach a
+/// different set of components to the same object. Each world still tracks the locations of its
+/// own entities. See `World::share_entities`.
+///
+/// Despawning an entity in any world returns its ID to the allocator, so an entity should be
+/// despawned from every world at once. `World::spawn_at` replaces any stale entity that shares
+/// its ID.
+#[derive(Default)]
+pub struct SharedEntities {
+    state: Lock<SharedState>,
+}
+
+impl SharedEntities {
+    /// Create an allocator whose default range covers every ID
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl fmt::Debug for SharedEntities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedEntities").finish()
+    }
+}
+
+#[derive(Default)]
+struct SharedState {
+    ids: IdSpace,
+    /// Current generation of every ID ever allocated
+    generations: Vec<Generation>,
+    /// IDs reserved by each attached world that it hasn't yet flushed, or `None` for free slots
+    reserved: Vec<Option<Vec<Entity>>>,
+}
+
+impl SharedState {
+    fn alloc(&mut self, range: IdRange) -> Entity {
+        let entity = self.ids.alloc(range);
+        self.set_generation(entity);
+        entity
+    }
+
+    fn alloc_at(&mut self, entity: Entity) {
+        self.ids.alloc_at(entity.id);
+        self.set_generation(entity);
+    }
+
+    /// Release `entity`'s ID, unless another world already has
+    fn free(&mut self, entity: Entity) {
+        if let Some(generation) = self.generations.get_mut(entity.id as usize) {
+            if *generation == entity.generation {
+                match self.ids.next_generation(entity.id, *generation) {
+                    Some(x) => {
+                        *generation = x;
+                        self.ids.free(Entity {
+                            generation: x,
+                            id: entity.id,
+                        });
+                    }
+                    None => self.ids.retired += 1,
+                }
+            }
+        }
+    }
+
+    fn set_generation(&mut self, entity: Entity) {
+        let index = entity.id as usize;
+        if self.generations.len() <= index {
+            self.generations.resize(index + 1, Generation::MIN);
+        }
+        self.generations[index] = entity.generation;
+    }
+}
+
+/// Mutex guarding the state of a `SharedEntities`
+#[cfg(feature = "std")]
+#[derive(Default)]
+struct Lock<T>(std::sync::Mutex<T>);
+
+#[cfg(feature = "std")]
+impl<T> Lock<T> {
+    fn lock(&self) -> std::sync::MutexGuard<'_, T> {
+        // Critical sections leave the state consistent even if they panic partway through
+        self.0
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+/// Minimal spin lock, suitable for the short critical sections of `SharedEntities`, used where
+/// `std`'s mutex is unavailable
+#[cfg(not(feature = "std"))]
 #[derive(Default)]
+struct Lock<T> {
+    locked: AtomicBool,
+    value: UnsafeCell<T>,
+}
+
+#[cfg(not(feature = "std"))]
+impl<T> Lock<T> {
+    fn lock(&self) -> LockGuard<'_, T> {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        LockGuard(self)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+unsafe impl<T: Send> Send for Lock<T> {}
+#[cfg(not(feature = "std"))]
+unsafe impl<T: Send> Sync for Lock<T> {}
+
+#[cfg(not(feature = "std"))]
+struct LockGuard<'a, T>(&'a Lock<T>);
+
+#[cfg(not(feature = "std"))]
+impl<T> Deref for LockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.0.value.get() }
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl<T> DerefMut for LockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.0.value.get() }
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl<T> Drop for LockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.0.locked.store(false, Ordering::Release);
+    }
+}
+
 pub(crate) struct Entities {
-    pub meta: Vec<EntityMeta>,
+    pub meta: AllocVec<EntityMeta>,
+    ids: Ids,
+}
+
+/// Where a world gets its entity IDs from
+enum Ids {
+    Local(IdSpace),
+    Shared {
+        shared: Arc<SharedEntities>,
+        /// Index of this world's reservations in `SharedState::reserved`
+        slot: usize,
+        /// Whether any IDs have been reserved since the last `flush`
+        reserved: AtomicBool,
+    },
+}
+
+/// Allocation state for every range of entity IDs
+struct IdSpace {
+    /// Ranges IDs are allocated from, indexed by `IdRange`
+    ranges: Vec<IdAllocator>,
+    /// Number of IDs permanently withdrawn after their generation saturated
+    retired: usize,
+    policy: ReusePolicy,
+    /// Whether saturated generations wrap around rather than retiring their IDs
+    wide: bool,
+    /// Number of times each ID's generation has wrapped around, if any
+    epochs: HashMap<u32, u32>,
+}
+
+impl Default for IdSpace {
+    fn default() -> Self {
+        Self {
+            ranges: vec![IdAllocator::new(0..u32::MAX)],
+            retired: 0,
+            policy: ReusePolicy::default(),
+            wide: false,
+            epochs: HashMap::default(),
+        }
+    }
+}
+
+/// Allocator for the IDs in a single `IdRange`
+struct IdAllocator {
+    /// IDs that may be allocated
+    ids: Range<u32>,
+    /// Smallest ID in `ids` that has never been allocated
+    next: u32,
 
     // The `pending` and `free_cursor` fields describe three sets of Entity IDs
     // that have been freed or are in the process of being allocated:
@@ -100,8 +400,8 @@ pub(crate) struct Entities {
     //   reserved by `reserve_entities` or `reserve_entity()`. They are now waiting
     //   for `flush()` to make them fully allocated.
     //
-    // - The count of new IDs that do not yet exist in `self.meta()`, but which
-    //   we have handed out and reserved. `flush()` will allocate room for them in `self.meta()`.
+    // - The count of new IDs starting at `next`, which we have handed out and
+    //   reserved. `flush()` will allocate room for them in `meta`.
     //
     // The contents of `pending` look like this:
     //
@@ -118,25 +418,133 @@ pub(crate) struct Entities {
     //
     // Once the freelist runs out, `free_cursor` starts going negative.
     // The more negative it is, the more IDs have been reserved starting exactly at
-    // the end of `meta.len()`.
+    // `next`.
     //
     // This formulation allows us to reserve any number of IDs first from the freelist
     // and then from the new IDs, using only a single atomic subtract.
     //
     // Once `flush()` is done, `free_cursor` will equal `pending.len()`.
-    pending: Vec<u32>,
+    //
+    // Freed IDs are stored with the generation they'll next be allocated with.
+    pending: Vec<Entity>,
     free_cursor: AtomicI64,
+
+    /// Freed IDs not yet added to the freelist, oldest first, as governed by the `ReusePolicy`
+    ///
+    /// Only modified through `&mut self`, when nothing is reserved, so reservation never needs
+    /// to look here.
+    queue: VecDeque<Entity>,
 }
 
-impl Entities {
-    /// Reserve entity IDs concurrently
+impl IdAllocator {
+    fn new(ids: Range<u32>) -> Self {
+        Self {
+            next: ids.start,
+            ids,
+            pending: Vec::new(),
+            free_cursor: AtomicI64::new(0),
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Whether no IDs have been allocated or reserved
+    fn is_unused(&mut self) -> bool {
+        self.next == self.ids.start && self.pending.is_empty() && *self.free_cursor.get_mut() == 0
+    }
+
+    /// Compute the `n`th new ID, panicking if it's out of range
+    fn new_id(&self, n: i64) -> u32 {
+        u32::try_from(i64::from(self.next) + n)
+            .ok()
+            .filter(|&id| id < self.ids.end)
+            .expect("too many entities")
+    }
+
+    fn sync_free_cursor(&mut self) {
+        *self.free_cursor.get_mut() = self.pending.len() as i64;
+    }
+
+    /// Move queued IDs that `policy` allows to be reused into the freelist
     ///
-    /// Storage for entity generation and location is lazily allocated by calling `flush`.
-    pub fn reserve_entities(&self, count: u32) -> ReserveEntitiesIterator {
+    /// Must not be called while reservations are awaiting `flush()`.
+    fn release(&mut self, policy: ReusePolicy) {
+        match policy {
+            ReusePolicy::Lifo => self.pending.extend(self.queue.drain(..)),
+            ReusePolicy::Fifo => {
+                // The freelist is consumed from the end, so the oldest ID goes last. Newer IDs
+                // wait until it's empty.
+                if self.pending.is_empty() {
+                    self.pending.extend(self.queue.drain(..).rev());
+                }
+            }
+            ReusePolicy::Quarantine(n) => {
+                while self.queue.len() > n as usize {
+                    self.pending.push(self.queue.pop_front().unwrap());
+                }
+            }
+        }
+        self.sync_free_cursor();
+    }
+}
+
+impl IdSpace {
+    /// The generation `id` takes after `generation`, or `None` if `id` must be retired
+    fn next_generation(&mut self, id: u32, generation: Generation) -> Option<Generation> {
+        if let Some(x) = generation.checked_add(1) {
+            return Some(x);
+        }
+        if !self.wide {
+            return None;
+        }
+        let epoch = self.epochs.entry(id).or_insert(0);
+        *epoch = epoch.wrapping_add(1);
+        Some(Generation::MIN)
+    }
+
+    /// Number of times `id`'s generation has wrapped around
+    fn epoch(&self, id: u32) -> u32 {
+        self.epochs.get(&id).copied().unwrap_or(0)
+    }
+
+    fn set_range(&mut self, range: IdRange, ids: Range<u32>) {
+        let index = range.0 as usize;
+        assert!(
+            self.ranges[index].is_unused(),
+            "IDs have already been allocated from this range"
+        );
+        self.check_range(&ids, Some(index));
+        self.ranges[index] = IdAllocator::new(ids);
+    }
+
+    fn add_range(&mut self, ids: Range<u32>) -> IdRange {
+        self.check_range(&ids, None);
+        let range = IdRange(u32::try_from(self.ranges.len()).unwrap());
+        self.ranges.push(IdAllocator::new(ids));
+        range
+    }
+
+    fn check_range(&self, ids: &Range<u32>, ignore: Option<usize>) {
+        assert!(ids.start < ids.end, "empty ID range");
+        let overlaps = self
+            .ranges
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| Some(i) != ignore)
+            .any(|(_, x)| ids.start < x.ids.end && x.ids.start < ids.end);
+        assert!(!overlaps, "ID ranges must not overlap");
+    }
+
+    /// The range containing `id`, if any
+    fn range_of(&mut self, id: u32) -> Option<&mut IdAllocator> {
+        self.ranges.iter_mut().find(|x| x.ids.contains(&id))
+    }
+
+    fn reserve_entities(&self, range: IdRange, count: u32) -> ReserveEntitiesIterator<'_> {
+        let range = &self.ranges[range.0 as usize];
         // Use one atomic subtract to grab a range of new IDs. The range might be
         // entirely nonnegative, meaning all IDs come from the freelist, or entirely
         // negative, meaning they are all new IDs to allocate, or a mix of both.
-        let range_end = self.free_cursor.fetch_sub(count as i64, Ordering::Relaxed);
+        let range_end = range.free_cursor.fetch_sub(count as i64, Ordering::Relaxed);
         let range_start = range_end - count as i64;
 
         let freelist_range = range_start.max(0) as usize..range_end.max(0) as usize;
@@ -145,57 +553,329 @@ impl Entities {
             // We satisfied all requests from the freelist.
             (0, 0)
         } else {
-            // We need to allocate some new Entity IDs outside of the range of self.meta.
+            // We need to allocate some new Entity IDs starting at `next`.
             //
             // `range_start` covers some negative territory, e.g. `-3..6`.
             // Since the nonnegative values `0..6` are handled by the freelist, that
             // means we need to handle the negative range here.
             //
             // In this example, we truncate the end to 0, leaving us with `-3..0`.
-            // Then we negate these values to indicate how far beyond the end of `meta.end()`
-            // to go, yielding `meta.len()+0 .. meta.len()+3`.
-            let base = self.meta.len() as i64;
-
-            let new_id_end = u32::try_from(base - range_start).expect("too many entities");
-
+            // Then we negate these values to indicate how far beyond `next`
+            // to go, yielding `next+0 .. next+3`.
+            //
             // `new_id_end` is in range, so no need to check `start`.
-            let new_id_start = (base - range_end.min(0)) as u32;
+            let new_id_end = range.new_id(-range_start - 1) + 1;
+            let new_id_start = range.next + (-range_end.min(0)) as u32;
 
             (new_id_start, new_id_end)
         };
 
         ReserveEntitiesIterator {
-            meta: &self.meta[..],
-            id_iter: self.pending[freelist_range].iter(),
+            id_iter: range.pending[freelist_range].iter(),
             id_range: new_id_start..new_id_end,
+            shared: Vec::new().into_iter(),
         }
     }
 
-    /// Reserve one entity ID concurrently
-    ///
-    /// Equivalent to `self.reserve_entities(1).next().unwrap()`, but more efficient.
-    pub fn reserve_entity(&self) -> Entity {
-        let n = self.free_cursor.fetch_sub(1, Ordering::Relaxed);
+    fn reserve_entity(&self, range: IdRange) -> Entity {
+        let range = &self.ranges[range.0 as usize];
+        let n = range.free_cursor.fetch_sub(1, Ordering::Relaxed);
         if n > 0 {
             // Allocate from the freelist.
-            let id = self.pending[(n - 1) as usize];
+            range.pending[(n - 1) as usize]
+        } else {
+            // Grab a new ID, starting at `next`. `flush()` must eventually be called
+            // to make it valid.
+            //
+            // As `free_cursor` goes more and more negative, we return IDs farther
+            // and farther beyond `next`.
             Entity {
-                generation: self.meta[id as usize].generation,
-                id,
+                generation: Generation::MIN,
+                id: range.new_id(-n),
             }
+        }
+    }
+
+    fn needs_flush(&self) -> bool {
+        self.ranges
+            .iter()
+            .any(|range| range.free_cursor.load(Ordering::Relaxed) != range.pending.len() as i64)
+    }
+
+    /// Allocate an ID, which must not be called while reservations are awaiting `flush()`
+    fn alloc(&mut self, range: IdRange) -> Entity {
+        let range = &mut self.ranges[range.0 as usize];
+        range.release(self.policy);
+        if let Some(entity) = range.pending.pop() {
+            range.sync_free_cursor();
+            entity
         } else {
-            // Grab a new ID, outside the range of `meta.len()`. `flush()` must
-            // eventually be called to make it valid.
-            //
-            // As `self.free_cursor` goes more and more negative, we return IDs farther
-            // and farther beyond `meta.len()`.
+            let id = range.new_id(0);
+            range.next += 1;
             Entity {
-                generation: 0,
-                id: u32::try_from(self.meta.len() as i64 - n).expect("too many entities"),
+                generation: Generation::MIN,
+                id,
+            }
+        }
+    }
+
+    /// Ensure `id` is no longer available for allocation
+    fn alloc_at(&mut self, id: u32) {
+        let range = match self.range_of(id) {
+            Some(x) => x,
+            None => return,
+        };
+        if id >= range.next {
+            // Skipped IDs may be allocated later
+            range.pending.extend((range.next..id).map(|id| Entity {
+                generation: Generation::MIN,
+                id,
+            }));
+            range.next = id + 1;
+        } else if let Some(index) = range.pending.iter().position(|x| x.id == id) {
+            range.pending.remove(index);
+        } else if let Some(index) = range.queue.iter().position(|x| x.id == id) {
+            range.queue.remove(index);
+        }
+        range.sync_free_cursor();
+    }
+
+    /// Make an ID available for reuse, with the generation it will next be allocated with
+    fn free(&mut self, entity: Entity) {
+        let policy = self.policy;
+        if let Some(range) = self.range_of(entity.id) {
+            range.queue.push_back(entity);
+            range.release(policy);
+        }
+    }
+
+    fn set_policy(&mut self, policy: ReusePolicy) {
+        self.policy = policy;
+        for range in &mut self.ranges {
+            range.release(policy);
+        }
+    }
+
+    /// Finish allocating reserved IDs, passing each to `init`
+    fn flush(&mut self, mut init: impl FnMut(Entity)) {
+        for range in &mut self.ranges {
+            // Not racey due because of self is &mut.
+            let free_cursor = *range.free_cursor.get_mut();
+
+            let new_free_cursor = if free_cursor >= 0 {
+                free_cursor as usize
+            } else {
+                let old_next = range.next;
+                range.next += -free_cursor as u32;
+                for id in old_next..range.next {
+                    init(Entity {
+                        generation: Generation::MIN,
+                        id,
+                    });
+                }
+
+                *range.free_cursor.get_mut() = 0;
+                0
+            };
+
+            for entity in range.pending.drain(new_free_cursor..) {
+                init(entity);
             }
+            range.release(self.policy);
+        }
+    }
+
+    fn freelist_len(&self) -> usize {
+        self.ranges
+            .iter()
+            .map(|x| x.free_cursor.load(Ordering::Relaxed).max(0) as usize)
+            .sum()
+    }
+
+    fn pending_capacity(&self) -> usize {
+        self.ranges
+            .iter()
+            .map(|x| x.pending.capacity() + x.queue.capacity())
+            .sum()
+    }
+
+    fn shrink_to_fit(&mut self) {
+        for range in &mut self.ranges {
+            range.pending.shrink_to_fit();
+            range.queue.shrink_to_fit();
         }
     }
 
+    fn clear(&mut self) {
+        self.retired = 0;
+        for range in &mut self.ranges {
+            range.next = range.ids.start;
+            range.pending.clear();
+            range.queue.clear();
+            *range.free_cursor.get_mut() = 0;
+        }
+    }
+}
+
+impl Entities {
+    pub fn new() -> Self {
+        Self {
+            meta: AllocVec::new(),
+            ids: Ids::Local(IdSpace::default()),
+        }
+    }
+
+    /// Allocate IDs from `shared` from now on
+    ///
+    /// Panics if any IDs have been allocated.
+    pub fn share(&mut self, shared: Arc<SharedEntities>) {
+        self.verify_flushed();
+        assert!(
+            self.meta.iter().all(|x| x.is_vacant()),
+            "entities have already been spawned"
+        );
+        let slot = {
+            let mut state = shared.state.lock();
+            match state.reserved.iter().position(|x| x.is_none()) {
+                Some(slot) => {
+                    state.reserved[slot] = Some(Vec::new());
+                    slot
+                }
+                None => {
+                    state.reserved.push(Some(Vec::new()));
+                    state.reserved.len() - 1
+                }
+            }
+        };
+        self.release_all();
+        self.meta.clear();
+        self.ids = Ids::Shared {
+            shared,
+            slot,
+            reserved: AtomicBool::new(false),
+        };
+    }
+
+    /// The allocator shared with other worlds, if any
+    pub fn shared(&self) -> Option<&Arc<SharedEntities>> {
+        match self.ids {
+            Ids::Local(_) => None,
+            Ids::Shared { ref shared, .. } => Some(shared),
+        }
+    }
+
+    /// Change the IDs the range `range` allocates from
+    ///
+    /// Panics if any IDs have been allocated from `range`, or if `ids` is empty or overlaps
+    /// another range.
+    pub fn set_range(&mut self, range: IdRange, ids: Range<u32>) {
+        match self.ids {
+            Ids::Local(ref mut x) => x.set_range(range, ids),
+            Ids::Shared { ref shared, .. } => shared.state.lock().ids.set_range(range, ids),
+        }
+    }
+
+    /// Change the order in which freed IDs are reused
+    pub fn set_policy(&mut self, policy: ReusePolicy) {
+        self.verify_flushed();
+        match self.ids {
+            Ids::Local(ref mut x) => x.set_policy(policy),
+            Ids::Shared { ref shared, .. } => shared.state.lock().ids.set_policy(policy),
+        }
+    }
+
+    /// Whether saturated generations wrap around rather than retiring their IDs
+    pub fn set_wide(&mut self, wide: bool) {
+        match self.ids {
+            Ids::Local(ref mut x) => x.wide = wide,
+            Ids::Shared { ref shared, .. } => shared.state.lock().ids.wide = wide,
+        }
+    }
+
+    /// Number of times `id`'s generation has wrapped around
+    pub fn epoch(&self, id: u32) -> u32 {
+        match self.ids {
+            Ids::Local(ref x) => x.epoch(id),
+            Ids::Shared { ref shared, .. } => shared.state.lock().ids.epoch(id),
+        }
+    }
+
+    /// Add a new range of IDs to allocate from
+    ///
+    /// Panics if `ids` is empty or overlaps another range.
+    pub fn add_range(&mut self, ids: Range<u32>) -> IdRange {
+        match self.ids {
+            Ids::Local(ref mut x) => x.add_range(ids),
+            Ids::Shared { ref shared, .. } => shared.state.lock().ids.add_range(ids),
+        }
+    }
+
+    /// Reserve entity IDs concurrently
+    ///
+    /// Storage for entity generation and location is lazily allocated by calling `flush`.
+    pub fn reserve_entities(&self, count: u32) -> ReserveEntitiesIterator {
+        self.reserve_entities_in(IdRange::DEFAULT, count)
+    }
+
+    /// Reserve entity IDs from `range` concurrently
+    pub fn reserve_entities_in(&self, range: IdRange, count: u32) -> ReserveEntitiesIterator<'_> {
+        match self.ids {
+            Ids::Local(ref x) => x.reserve_entities(range, count),
+            Ids::Shared { .. } => {
+                let entities = self.reserve_shared(|state, reserved| {
+                    let start = reserved.len();
+                    for _ in 0..count {
+                        reserved.push(state.alloc(range));
+                    }
+                    reserved[start..].to_vec()
+                });
+                ReserveEntitiesIterator {
+                    id_iter: [].iter(),
+                    id_range: 0..0,
+                    shared: entities.into_iter(),
+                }
+            }
+        }
+    }
+
+    /// Reserve one entity ID concurrently
+    ///
+    /// Equivalent to `self.reserve_entities(1).next().unwrap()`, but more efficient.
+    pub fn reserve_entity(&self) -> Entity {
+        self.reserve_entity_in(IdRange::DEFAULT)
+    }
+
+    /// Reserve one entity ID from `range` concurrently
+    pub fn reserve_entity_in(&self, range: IdRange) -> Entity {
+        match self.ids {
+            Ids::Local(ref x) => x.reserve_entity(range),
+            Ids::Shared { .. } => self.reserve_shared(|state, reserved| {
+                let entity = state.alloc(range);
+                reserved.push(entity);
+                entity
+            }),
+        }
+    }
+
+    /// Allocate IDs from the shared allocator to be initialized by the next `flush`
+    fn reserve_shared<T>(&self, f: impl FnOnce(&mut SharedState, &mut Vec<Entity>) -> T) -> T {
+        let (shared, slot, reserved) = match self.ids {
+            Ids::Shared {
+                ref shared,
+                slot,
+                ref reserved,
+            } => (shared, slot, reserved),
+            Ids::Local(_) => unreachable!(),
+        };
+        let mut state = shared.state.lock();
+        let state = &mut *state;
+        let mut list = state.reserved[slot].take().unwrap();
+        let result = f(state, &mut list);
+        state.reserved[slot] = Some(list);
+        reserved.store(true, Ordering::Relaxed);
+        result
+    }
+
     /// Check that we do not have pending work requiring `flush()` to be called.
     fn verify_flushed(&mut self) {
         debug_assert!(
@@ -207,68 +887,206 @@ impl Entities {
     /// Allocate an entity ID directly
     ///
     /// Location should be written immediately.
-    pub fn alloc(&mut self) -> Entity {
+    pub fn alloc<A: Allocator>(&mut self, alloc: &A) -> Entity {
+        self.alloc_in(alloc, IdRange::DEFAULT)
+    }
+
+    /// Allocate an entity ID from `range` directly
+    ///
+    /// Location should be written immediately.
+    pub fn alloc_in<A: Allocator>(&mut self, alloc: &A, range: IdRange) -> Entity {
         self.verify_flushed();
 
-        if let Some(id) = self.pending.pop() {
-            let new_free_cursor = self.pending.len() as i64;
-            self.free_cursor.store(new_free_cursor, Ordering::Relaxed); // Not racey due to &mut self
-            Entity {
-                generation: self.meta[id as usize].generation,
-                id,
-            }
-        } else {
-            let id = u32::try_from(self.meta.len()).expect("too many entities");
-            self.meta.push(EntityMeta::EMPTY);
-            Entity { generation: 0, id }
+        let entity = match self.ids {
+            Ids::Local(ref mut x) => x.alloc(range),
+            Ids::Shared { ref shared, .. } => shared.state.lock().alloc(range),
+        };
+        self.init_meta(alloc, entity);
+        entity
+    }
+
+    /// Allocate a specific entity ID, which must not be in use by this world
+    ///
+    /// Location should be written immediately.
+    pub fn alloc_at<A: Allocator>(&mut self, alloc: &A, entity: Entity) {
+        self.verify_flushed();
+
+        match self.ids {
+            Ids::Local(ref mut x) => x.alloc_at(entity.id),
+            Ids::Shared { ref shared, .. } => shared.state.lock().alloc_at(entity),
+        }
+        self.init_meta(alloc, entity);
+    }
+
+    /// Prepare the metadata of a newly allocated entity
+    fn init_meta<A: Allocator>(&mut self, alloc: &A, entity: Entity) {
+        let index = entity.id as usize;
+        if self.meta.len() <= index {
+            self.meta.resize(alloc, index + 1, EntityMeta::VACANT);
+        }
+        self.meta[index] = EntityMeta {
+            generation: entity.generation,
+            location: EntityMeta::EMPTY.location,
+        };
+    }
+
+    /// The live entity with ID `id`, if any
+    pub fn live(&self, id: u32) -> Option<Entity> {
+        let meta = self.meta.get(id as usize)?;
+        if meta.is_vacant() {
+            return None;
         }
+        Some(Entity {
+            generation: meta.generation,
+            id,
+        })
     }
 
     /// Destroy an entity, allowing it to be reused
     ///
     /// Must not be called while reserved entities are awaiting `flush()`.
     pub fn free(&mut self, entity: Entity) -> Result<Location, NoSuchEntity> {
+        self.free_inner(entity, true)
+    }
+
+    /// Destroy an entity without allowing its ID to be reused, ahead of `alloc_at`
+    pub fn free_keep_id(&mut self, entity: Entity) -> Result<Location, NoSuchEntity> {
+        self.free_inner(entity, false)
+    }
+
+    fn free_inner(&mut self, entity: Entity, reuse: bool) -> Result<Location, NoSuchEntity> {
         self.verify_flushed();
 
-        let meta = &mut self.meta[entity.id as usize];
-        if meta.generation != entity.generation {
+        let meta = self.meta.get_mut(entity.id as usize).ok_or(NoSuchEntity)?;
+        if meta.generation != entity.generation || meta.is_vacant() {
             return Err(NoSuchEntity);
         }
-        meta.generation += 1;
-
-        let loc = mem::replace(&mut meta.location, EntityMeta::EMPTY.location);
+        // A saturated generation can't distinguish future entities from stale handles, so unless
+        // generations wrap around, its ID is retired rather than reused. The generation is left
+        // as-is, keeping `entity` invalid.
+        let next_generation = match self.ids {
+            Ids::Local(ref mut x) => x.next_generation(entity.id, meta.generation),
+            // The shared state tracks wrapping itself, in `SharedState::free`
+            Ids::Shared { ref shared, .. } => match meta.generation.checked_add(1) {
+                None if shared.state.lock().ids.wide => Some(Generation::MIN),
+                x => x,
+            },
+        };
+        if let Some(x) = next_generation {
+            meta.generation = x;
+        }
 
-        self.pending.push(entity.id);
+        let loc = mem::replace(&mut meta.location, EntityMeta::VACANT.location);
+        if !reuse {
+            return Ok(loc);
+        }
 
-        let new_free_cursor = self.pending.len() as i64;
-        self.free_cursor.store(new_free_cursor, Ordering::Relaxed); // Not racey due to &mut self
+        match self.ids {
+            Ids::Local(ref mut x) => match next_generation {
+                Some(generation) => x.free(Entity {
+                    generation,
+                    id: entity.id,
+                }),
+                None => x.retired += 1,
+            },
+            Ids::Shared { ref shared, .. } => shared.state.lock().free(entity),
+        }
 
         Ok(loc)
     }
 
     /// Ensure at least `n` allocations can succeed without reallocating
-    pub fn reserve(&mut self, additional: u32) {
+    pub fn reserve<A: Allocator>(&mut self, alloc: &A, additional: u32) {
         self.verify_flushed();
 
-        let freelist_size = self.free_cursor.load(Ordering::Relaxed);
-        let shortfall = additional as i64 - freelist_size;
-        if shortfall > 0 {
-            self.meta.reserve(shortfall as usize);
-        }
+        let needed = match self.ids {
+            Ids::Local(ref x) => {
+                let range = &x.ranges[IdRange::DEFAULT.0 as usize];
+                let freelist_size = range.free_cursor.load(Ordering::Relaxed);
+                let shortfall = additional as i64 - freelist_size;
+                if shortfall <= 0 {
+                    return;
+                }
+                (i64::from(range.next) + shortfall) as usize
+            }
+            Ids::Shared { .. } => self.meta.len() + additional as usize,
+        };
+        self.meta
+            .reserve(alloc, needed.saturating_sub(self.meta.len()));
     }
 
     pub fn contains(&self, entity: Entity) -> bool {
-        // Note that out-of-range IDs are considered to be "contained" because
-        // they must be reserved IDs that we haven't flushed yet.
-        self.meta
-            .get(entity.id as usize)
-            .map_or(true, |meta| meta.generation == entity.generation)
+        self.get(entity).is_ok()
+    }
+
+    /// Number of freed IDs available for reuse
+    pub fn freelist_len(&self) -> usize {
+        match self.ids {
+            Ids::Local(ref x) => x.freelist_len(),
+            Ids::Shared { ref shared, .. } => shared.state.lock().ids.freelist_len(),
+        }
+    }
+
+    /// Number of IDs permanently withdrawn after their generation saturated
+    pub fn retired(&self) -> usize {
+        match self.ids {
+            Ids::Local(ref x) => x.retired,
+            Ids::Shared { ref shared, .. } => shared.state.lock().ids.retired,
+        }
+    }
+
+    pub fn pending_capacity(&self) -> usize {
+        match self.ids {
+            Ids::Local(ref x) => x.pending_capacity(),
+            Ids::Shared { .. } => 0,
+        }
+    }
+
+    pub fn shrink_to_fit<A: Allocator>(&mut self, alloc: &A) {
+        self.meta.shrink_to_fit(alloc);
+        if let Ids::Local(ref mut x) = self.ids {
+            x.shrink_to_fit();
+        }
     }
 
     pub fn clear(&mut self) {
+        self.release_all();
         self.meta.clear();
-        self.pending.clear();
-        self.free_cursor.store(0, Ordering::Relaxed); // Not racey due to &mut self
+        if let Ids::Local(ref mut x) = self.ids {
+            x.clear();
+        }
+    }
+
+    /// Release the memory of the location table
+    pub fn free_memory<A: Allocator>(&mut self, alloc: &A) {
+        self.meta.free(alloc);
+    }
+
+    /// Return this world's entities and reservations to the shared allocator, if any
+    fn release_all(&mut self) {
+        if let Ids::Shared {
+            ref shared,
+            slot,
+            ref mut reserved,
+        } = self.ids
+        {
+            let mut state = shared.state.lock();
+            for (id, meta) in self.meta.iter().enumerate() {
+                if !meta.is_vacant() {
+                    state.free(Entity {
+                        generation: meta.generation,
+                        id: id as u32,
+                    });
+                }
+            }
+            if *reserved.get_mut() {
+                let list = mem::take(state.reserved[slot].as_mut().unwrap());
+                for entity in list {
+                    state.free(entity);
+                }
+                *reserved.get_mut() = false;
+            }
+        }
     }
 
     /// Access the location storage of an entity
@@ -285,16 +1103,18 @@ impl Entities {
 
     /// Returns `Ok(Location { archetype: 0, index: undefined })` for pending entities
     pub fn get(&self, entity: Entity) -> Result<Location, NoSuchEntity> {
-        if self.meta.len() <= entity.id as usize {
-            return Ok(Location {
-                archetype: 0,
-                index: u32::max_value(),
-            });
-        }
-        let meta = &self.meta[entity.id as usize];
-        if meta.generation != entity.generation {
-            return Err(NoSuchEntity);
-        }
+        let meta = match self.meta.get(entity.id as usize) {
+            Some(meta) if meta.generation != entity.generation => return Err(NoSuchEntity),
+            Some(meta) if !meta.is_vacant() => meta,
+            // Unknown IDs might have been reserved but not yet flushed
+            _ if self.needs_flush() => {
+                return Ok(Location {
+                    archetype: 0,
+                    index: u32::max_value(),
+                })
+            }
+            _ => return Err(NoSuchEntity),
+        };
         if meta.location.archetype == 0 {
             return Ok(Location {
                 archetype: 0,
@@ -309,74 +1129,120 @@ impl Entities {
     /// # Safety
     /// Must only be called for currently allocated `id`s.
     pub unsafe fn resolve_unknown_gen(&self, id: u32) -> Entity {
-        let meta_len = self.meta.len();
-
-        if meta_len > id as usize {
-            let meta = &self.meta[id as usize];
-            Entity {
+        if let Some(meta) = self.meta.get(id as usize) {
+            return Entity {
                 generation: meta.generation,
                 id,
-            }
-        } else {
-            // See if it's pending, but not yet flushed.
-            let free_cursor = self.free_cursor.load(Ordering::Relaxed);
-            let num_pending = cmp::max(-free_cursor, 0) as usize;
-
-            if meta_len + num_pending > id as usize {
-                // Pending entities will have generation 0.
-                Entity { generation: 0, id }
-            } else {
-                panic!("entity id is out of range");
+            };
+        }
+        // See if it's pending, but not yet flushed.
+        if let Ids::Local(ref x) = self.ids {
+            if let Some(range) = x.ranges.iter().find(|x| x.ids.contains(&id)) {
+                let free_cursor = range.free_cursor.load(Ordering::Relaxed);
+                let num_pending = cmp::max(-free_cursor, 0);
+                if i64::from(id) < i64::from(range.next) + num_pending {
+                    // Pending entities will have the first generation.
+                    return Entity {
+                        generation: Generation::MIN,
+                        id,
+                    };
+                }
             }
         }
+        panic!("entity id is out of range");
     }
 
-    fn needs_flush(&mut self) -> bool {
-        // Not racey due to &mut self
-        self.free_cursor.load(Ordering::Relaxed) != self.pending.len() as i64
+    fn needs_flush(&self) -> bool {
+        match self.ids {
+            Ids::Local(ref x) => x.needs_flush(),
+            Ids::Shared { ref reserved, .. } => reserved.load(Ordering::Relaxed),
+        }
     }
 
     /// Allocates space for entities previously reserved with `reserve_entity` or
     /// `reserve_entities`, then initializes each one using the supplied function.
-    pub fn flush(&mut self, mut init: impl FnMut(u32, &mut Location)) {
-        // Not racey due because of self is &mut.
-        let free_cursor = self.free_cursor.load(Ordering::Relaxed);
-
-        let new_free_cursor = if free_cursor >= 0 {
-            free_cursor as usize
-        } else {
-            let old_meta_len = self.meta.len();
-            let new_meta_len = old_meta_len + -free_cursor as usize;
-            self.meta.resize(new_meta_len, EntityMeta::EMPTY);
-
-            for (id, meta) in self.meta.iter_mut().enumerate().skip(old_meta_len) {
-                init(id as u32, &mut meta.location);
+    pub fn flush<A: Allocator>(&mut self, alloc: &A, mut init: impl FnMut(u32, &mut Location)) {
+        let meta = &mut self.meta;
+        let mut init_entity = |entity: Entity| {
+            let index = entity.id as usize;
+            if meta.len() <= index {
+                meta.resize(alloc, index + 1, EntityMeta::VACANT);
             }
-
-            self.free_cursor.store(0, Ordering::Relaxed);
-            0
+            meta[index] = EntityMeta {
+                generation: entity.generation,
+                location: EntityMeta::EMPTY.location,
+            };
+            init(entity.id, &mut meta[index].location);
         };
+        match self.ids {
+            Ids::Local(ref mut x) => x.flush(init_entity),
+            Ids::Shared {
+                ref shared,
+                slot,
+                ref mut reserved,
+            } => {
+                // Not racey due to &mut self
+                if !mem::replace(reserved.get_mut(), false) {
+                    return;
+                }
+                let list = mem::take(shared.state.lock().reserved[slot].as_mut().unwrap());
+                for entity in list {
+                    init_entity(entity);
+                }
+            }
+        }
+    }
+}
 
-        for id in self.pending.drain(new_free_cursor..) {
-            init(id, &mut self.meta[id as usize].location);
+impl Drop for Entities {
+    fn drop(&mut self) {
+        if let Ids::Shared {
+            ref shared, slot, ..
+        } = self.ids
+        {
+            // Entities remain allocated, as other worlds may still refer to them, but IDs reserved
+            // and never flushed belong to no one else
+            let mut state = shared.state.lock();
+            for entity in state.reserved[slot].take().unwrap() {
+                state.free(entity);
+            }
         }
     }
 }
 
+impl Default for Entities {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Copy, Clone)]
 pub(crate) struct EntityMeta {
-    pub generation: u32,
+    pub generation: Generation,
     pub location: Location,
 }
 
 impl EntityMeta {
     const EMPTY: EntityMeta = EntityMeta {
-        generation: 0,
+        generation: Generation::MIN,
         location: Location {
             archetype: 0,
             index: u32::max_value(), // dummy value, to be filled in
         },
     };
+
+    /// Metadata of an ID that isn't allocated
+    const VACANT: EntityMeta = EntityMeta {
+        generation: Generation::MIN,
+        location: Location {
+            archetype: u32::MAX,
+            index: u32::MAX,
+        },
+    };
+
+    fn is_vacant(&self) -> bool {
+        self.location.archetype == u32::MAX
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -401,16 +1267,19 @@ impl Error for NoSuchEntity {}
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::allocator::Global;
     use hashbrown::{HashMap, HashSet};
     use rand::{rngs::StdRng, Rng, SeedableRng};
 
     #[test]
     fn entity_bits_roundtrip() {
         let e = Entity {
-            generation: 0xDEADBEEF,
+            generation: Generation::new(0xDEADBEEF).unwrap(),
             id: 0xBAADF00D,
         };
-        assert_eq!(Entity::from_bits(e.to_bits()), e);
+        assert_eq!(Entity::from_bits(e.to_bits()), Some(e));
+        assert_eq!(Entity::from_bits(0xBAADF00D), None);
+        assert_eq!(mem::size_of::<Option<Entity>>(), 8);
     }
 
     #[test]
@@ -419,13 +1288,13 @@ mod tests {
 
         let mut e = Entities::default();
         let mut first_unused = 0u32;
-        let mut id_to_gen: HashMap<u32, u32> = Default::default();
+        let mut id_to_gen: HashMap<u32, Generation> = Default::default();
         let mut free_set: HashSet<u32> = Default::default();
 
         for _ in 0..100 {
             let alloc = rng.gen_bool(0.7);
             if alloc || first_unused == 0 {
-                let entity = e.alloc();
+                let entity = e.alloc(&Global);
 
                 let id = entity.id;
                 if !free_set.is_empty() {
@@ -445,7 +1314,7 @@ mod tests {
                 let generation = id_to_gen.remove(&id);
                 let entity = Entity {
                     id,
-                    generation: generation.unwrap_or(0),
+                    generation: generation.unwrap_or(Generation::MIN),
                 };
 
                 assert_eq!(e.free(entity).is_ok(), generation.is_some());
@@ -460,7 +1329,7 @@ mod tests {
         let mut e = Entities::default();
 
         for _ in 0..2 {
-            let entity = e.alloc();
+            let entity = e.alloc(&Global);
             assert!(e.contains(entity));
 
             e.free(entity).unwrap();
@@ -474,12 +1343,19 @@ mod tests {
         }
     }
 
+    fn free_cursor(e: &Entities) -> i64 {
+        match e.ids {
+            Ids::Local(ref x) => x.ranges[0].free_cursor.load(Ordering::Relaxed),
+            Ids::Shared { .. } => unreachable!(),
+        }
+    }
+
     // Shared test code parameterized by how we want to allocate an Entity block.
     fn reserve_test_helper(reserve_n: impl FnOnce(&mut Entities, u32) -> Vec<Entity>) {
         let mut e = Entities::default();
 
         // Allocate 10 items.
-        let mut v1: Vec<Entity> = (0..10).map(|_| e.alloc()).collect();
+        let mut v1: Vec<Entity> = (0..10).map(|_| e.alloc(&Global)).collect();
         assert_eq!(v1.iter().map(|e| e.id).max(), Some(9));
         for &entity in v1.iter() {
             assert!(e.contains(entity));
@@ -490,7 +1366,7 @@ mod tests {
         for entity in v1.drain(6..) {
             e.free(entity).unwrap();
         }
-        assert_eq!(e.free_cursor.load(Ordering::Relaxed), 4);
+        assert_eq!(free_cursor(&e), 4);
 
         // Reserve 10 entities, so 4 will come from the freelist.
         // This means we will have allocated 10 + 10 - 4 total items, so max id is 15.
@@ -509,10 +1385,10 @@ mod tests {
         }
 
         // 6 will come from pending.
-        assert_eq!(e.free_cursor.load(Ordering::Relaxed), -6);
+        assert_eq!(free_cursor(&e), -6);
 
         let mut flushed = Vec::new();
-        e.flush(|id, _| flushed.push(id));
+        e.flush(&Global, |id, _| flushed.push(id));
         flushed.sort_unstable();
 
         assert_eq!(flushed, (6..16).collect::<Vec<_>>());
@@ -527,4 +1403,101 @@ mod tests {
     fn reserve_entities() {
         reserve_test_helper(|e, n| e.reserve_entities(n).collect())
     }
+
+    #[test]
+    fn id_ranges() {
+        let mut e = Entities::default();
+        e.set_range(IdRange::DEFAULT, 0..100);
+        let high = e.add_range(100..104);
+
+        let a = e.alloc_in(&Global, high);
+        let b = e.alloc(&Global);
+        assert_eq!((a.id, b.id), (100, 0));
+
+        // Freed IDs are only reused by their own range.
+        e.free(a).unwrap();
+        assert_eq!(e.alloc(&Global).id, 1);
+        let a2 = e.alloc_in(&Global, high);
+        assert_eq!((a2.id, a2.generation.get()), (100, 2));
+
+        let reserved: Vec<u32> = e.reserve_entities_in(high, 2).map(|x| x.id).collect();
+        assert_eq!(reserved, [101, 102]);
+        assert_eq!(e.reserve_entity().id, 2);
+        assert_eq!(unsafe { e.resolve_unknown_gen(102) }.id, 102);
+
+        let mut flushed = Vec::new();
+        e.flush(&Global, |id, _| flushed.push(id));
+        flushed.sort_unstable();
+        assert_eq!(flushed, [2, 101, 102]);
+
+        assert_eq!(e.alloc_in(&Global, high).id, 103);
+    }
+
+    #[test]
+    fn reuse_policy() {
+        fn ids(e: &mut Entities, policy: ReusePolicy) -> Vec<u32> {
+            e.clear();
+            e.set_policy(policy);
+            let entities = (0..4).map(|_| e.alloc(&Global)).collect::<Vec<_>>();
+            for &x in &entities[..3] {
+                e.free(x).unwrap();
+            }
+            let mut ids = vec![e.reserve_entity().id];
+            e.flush(&Global, |_, _| {});
+            ids.extend((0..3).map(|_| e.alloc(&Global).id));
+            ids
+        }
+
+        let mut e = Entities::default();
+        assert_eq!(ids(&mut e, ReusePolicy::Lifo), [2, 1, 0, 4]);
+        assert_eq!(ids(&mut e, ReusePolicy::Fifo), [0, 1, 2, 4]);
+        assert_eq!(ids(&mut e, ReusePolicy::Quarantine(1)), [1, 0, 4, 5]);
+        assert_eq!(ids(&mut e, ReusePolicy::Quarantine(3)), [4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn retire_saturated() {
+        let mut e = Entities::default();
+        let a = e.alloc(&Global);
+        e.meta[a.id as usize].generation = Generation::MAX;
+        let a = Entity {
+            generation: Generation::MAX,
+            id: a.id,
+        };
+        e.free(a).unwrap();
+        assert_eq!(e.retired(), 1);
+        assert_eq!(e.freelist_len(), 0);
+        assert!(!e.contains(a));
+        assert!(e.free(a).is_err());
+        assert_ne!(e.alloc(&Global).id, a.id);
+    }
+
+    #[test]
+    fn wrap_saturated() {
+        let mut e = Entities::default();
+        e.set_wide(true);
+        let a = e.alloc(&Global);
+        e.meta[a.id as usize].generation = Generation::MAX;
+        let a = Entity {
+            generation: Generation::MAX,
+            id: a.id,
+        };
+        assert_eq!(e.epoch(a.id), 0);
+        e.free(a).unwrap();
+        assert_eq!(e.retired(), 0);
+        assert_eq!(e.epoch(a.id), 1);
+        let b = e.alloc(&Global);
+        assert_eq!((b.id, b.generation), (a.id, Generation::MIN));
+        assert!(!e.contains(a));
+        assert!(e.contains(b));
+    }
+
+    #[test]
+    #[should_panic(expected = "too many entities")]
+    fn id_range_exhausted() {
+        let mut e = Entities::default();
+        e.set_range(IdRange::DEFAULT, 0..1);
+        e.alloc(&Global);
+        e.alloc(&Global);
+    }
 }
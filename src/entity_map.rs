@@ -0,0 +1,192 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::alloc::vec::Vec;
+#[cfg(all(feature = "serde", feature = "std"))]
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use hashbrown::HashMap;
+
+use crate::{Allocator, Entity, World};
+
+/// Correspondence between entities in some source, such as a saved game, and the entities
+/// representing them in a destination `World`
+///
+/// Populate with `insert`, or with `get_or_reserve` to obtain destination IDs for entities that
+/// haven't been loaded yet, then rewrite loaded components with `MapEntities`.
+///
+/// # Example
+/// ```
+/// # use hecs::*;
+/// struct Target(Entity);
+///
+/// impl MapEntities for Target {
+///     fn map_entities(&mut self, map: &EntityMap) {
+///         self.0.map_entities(map);
+///     }
+/// }
+///
+/// // Entities and components as they were saved from some other world
+/// let mut source = World::new();
+/// let (a, b) = (source.spawn(()), source.spawn(()));
+/// let saved = [(a, Target(b)), (b, Target(a))];
+///
+/// let mut world = World::new();
+/// let mut map = EntityMap::new();
+/// for &(entity, _) in &saved {
+///     map.get_or_reserve(entity, &world);
+/// }
+/// for (entity, mut target) in saved {
+///     target.map_entities(&map);
+///     world.insert_one(map.get(entity).unwrap(), target).unwrap();
+/// }
+/// let new_a = map.get(a).unwrap();
+/// let new_b = map.get(b).unwrap();
+/// assert_ne!(new_a, new_b);
+/// assert_eq!(world.get::<Target>(new_a).unwrap().0, new_b);
+/// assert_eq!(world.get::<Target>(new_b).unwrap().0, new_a);
+/// ```
+#[derive(Debug, Default)]
+pub struct EntityMap {
+    map: HashMap<Entity, Entity>,
+    /// Whether `resolve` has been passed an entity that isn't mapped
+    #[cfg(all(feature = "serde", feature = "std"))]
+    unresolved: AtomicBool,
+}
+
+impl Clone for EntityMap {
+    fn clone(&self) -> Self {
+        Self {
+            map: self.map.clone(),
+            ..Self::default()
+        }
+    }
+}
+
+impl EntityMap {
+    /// Create an empty map
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that `source` is represented by `destination`, returning the previous mapping
+    pub fn insert(&mut self, source: Entity, destination: Entity) -> Option<Entity> {
+        self.map.insert(source, destination)
+    }
+
+    /// The entity representing `source`, if any
+    pub fn get(&self, source: Entity) -> Option<Entity> {
+        self.map.get(&source).copied()
+    }
+
+    /// The entity representing `source`, noting its absence for `take_unresolved` if there isn't
+    /// one
+    #[cfg(all(feature = "serde", feature = "std"))]
+    pub(crate) fn resolve(&self, source: Entity) -> Option<Entity> {
+        let x = self.get(source);
+        if x.is_none() {
+            self.unresolved.store(true, Ordering::Relaxed);
+        }
+        x
+    }
+
+    /// Whether `resolve` has failed since this was last called
+    #[cfg(all(feature = "serde", feature = "std"))]
+    pub(crate) fn take_unresolved(&self) -> bool {
+        self.unresolved.swap(false, Ordering::Relaxed)
+    }
+
+    /// The entity representing `source`, reserving a new one in `world` if there isn't one yet
+    ///
+    /// Reserved entities become real empty entities when `world` is next modified, so they can be
+    /// referred to by components before the entities they represent have been loaded. See
+    /// `World::reserve_entity`.
+    pub fn get_or_reserve<A: Allocator>(&mut self, source: Entity, world: &World<A>) -> Entity {
+        *self
+            .map
+            .entry(source)
+            .or_insert_with(|| world.reserve_entity())
+    }
+
+    /// Remove the mapping for `source`, returning the entity that represented it
+    pub fn remove(&mut self, source: Entity) -> Option<Entity> {
+        self.map.remove(&source)
+    }
+
+    /// Number of entities mapped
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Whether no entities are mapped
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Iterate over `(source, destination)` pairs, in arbitrary order
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = (Entity, Entity)> + '_ {
+        self.map
+            .iter()
+            .map(|(&source, &destination)| (source, destination))
+    }
+
+    /// Remove every mapping
+    pub fn clear(&mut self) {
+        self.map.clear();
+    }
+}
+
+/// Types that refer to entities, which can be rewritten to refer to the entities representing them
+/// elsewhere
+///
+/// Implement this for components holding `Entity` handles, usually by delegating to the `Entity`
+/// implementation for each handle.
+pub trait MapEntities {
+    /// Replace each entity referred to with its counterpart in `map`
+    ///
+    /// Entities missing from `map` should be left as-is.
+    fn map_entities(&mut self, map: &EntityMap);
+}
+
+impl MapEntities for Entity {
+    fn map_entities(&mut self, map: &EntityMap) {
+        if let Some(x) = map.get(*self) {
+            *self = x;
+        }
+    }
+}
+
+impl<T: MapEntities> MapEntities for Option<T> {
+    fn map_entities(&mut self, map: &EntityMap) {
+        if let Some(x) = self {
+            x.map_entities(map);
+        }
+    }
+}
+
+impl<T: MapEntities> MapEntities for Vec<T> {
+    fn map_entities(&mut self, map: &EntityMap) {
+        for x in self {
+            x.map_entities(map);
+        }
+    }
+}
+
+impl<T: MapEntities> MapEntities for [T] {
+    fn map_entities(&mut self, map: &EntityMap) {
+        for x in self {
+            x.map_entities(map);
+        }
+    }
+}
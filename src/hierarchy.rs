@@ -0,0 +1,141 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::alloc::vec::Vec;
+use core::mem;
+use core::ops::Deref;
+
+use crate::{Allocator, Entity, Global, World};
+
+/// Component identifying the parent of an entity in a hierarchy
+///
+/// Maintained by `World::set_parent` and `World::remove_parent`. Removing this component directly
+/// leaves the parent's `Children` inconsistent.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Parent(pub(crate) Entity);
+
+impl Parent {
+    /// The parent entity
+    pub fn get(&self) -> Entity {
+        self.0
+    }
+}
+
+/// Component listing the children of an entity in a hierarchy, in the order they were attached
+///
+/// Maintained by `World::set_parent` and `World::remove_parent`, and only present on entities
+/// that have at least one child. Removing this component directly leaves the children's `Parent`
+/// components inconsistent.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Children(pub(crate) Vec<Entity>);
+
+impl Deref for Children {
+    type Target = [Entity];
+    fn deref(&self) -> &[Entity] {
+        &self.0
+    }
+}
+
+/// Depth-first, pre-order iterator over the descendants of an entity
+///
+/// Returned by `World::descendants`.
+pub struct Descendants<'a, A: Allocator = Global> {
+    world: &'a World<A>,
+    stack: Vec<Entity>,
+}
+
+impl<'a, A: Allocator> Descendants<'a, A> {
+    pub(crate) fn new(world: &'a World<A>, root: Entity) -> Self {
+        let mut iter = Self {
+            world,
+            stack: Vec::new(),
+        };
+        iter.push_children(root);
+        iter
+    }
+
+    fn push_children(&mut self, entity: Entity) {
+        if let Ok(children) = self.world.get::<Children>(entity) {
+            self.stack.extend(children.iter().rev());
+        }
+    }
+}
+
+impl<A: Allocator> Iterator for Descendants<'_, A> {
+    type Item = Entity;
+
+    fn next(&mut self) -> Option<Entity> {
+        let entity = self.stack.pop()?;
+        self.push_children(entity);
+        Some(entity)
+    }
+}
+
+/// Iterator over the ancestors of an entity, nearest first
+///
+/// Returned by `World::ancestors`.
+pub struct Ancestors<'a, A: Allocator = Global> {
+    world: &'a World<A>,
+    current: Entity,
+}
+
+impl<'a, A: Allocator> Ancestors<'a, A> {
+    pub(crate) fn new(world: &'a World<A>, entity: Entity) -> Self {
+        Self {
+            world,
+            current: entity,
+        }
+    }
+}
+
+impl<A: Allocator> Iterator for Ancestors<'_, A> {
+    type Item = Entity;
+
+    fn next(&mut self) -> Option<Entity> {
+        self.current = self.world.get::<Parent>(self.current).ok()?.get();
+        Some(self.current)
+    }
+}
+
+/// Remove `child` from the `Children` of `parent`, dropping the component once it's empty
+pub(crate) fn remove_child<A: Allocator>(world: &mut World<A>, parent: Entity, child: Entity) {
+    let empty = match world.get_mut::<Children>(parent) {
+        Ok(mut children) => {
+            children.0.retain(|&x| x != child);
+            children.0.is_empty()
+        }
+        Err(_) => return,
+    };
+    if empty {
+        let _ = world.remove_one::<Children>(parent);
+    }
+}
+
+/// Detach `entity`, which is about to be despawned, from its parent
+pub(crate) fn unlink_parent<A: Allocator>(world: &mut World<A>, entity: Entity) {
+    if let Ok(parent) = world.get::<Parent>(entity).map(|x| x.get()) {
+        remove_child(world, parent, entity);
+    }
+}
+
+/// Orphan the children of `entity`, which is about to be despawned
+pub(crate) fn unlink_children<A: Allocator>(world: &mut World<A>, entity: Entity) {
+    let children = world
+        .get_mut::<Children>(entity)
+        .map(|mut x| mem::take(&mut x.0))
+        .unwrap_or_default();
+    for child in children {
+        let _ = world.remove_one::<Parent>(child);
+    }
+}
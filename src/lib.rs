@@ -62,22 +62,54 @@ macro_rules! smaller_tuples_too {
     };
 }
 
+mod allocator;
 mod archetype;
 mod borrow;
 mod bundle;
+mod dynamic_query;
 mod entities;
 mod entity_builder;
+mod entity_map;
+mod hierarchy;
 mod query;
 mod query_one;
+mod registry;
+mod relation;
+#[cfg(all(feature = "serde", feature = "std"))]
+mod scene;
+#[cfg(feature = "serde")]
+mod serialize;
+mod snapshot;
+mod sparse;
+mod stats;
 mod world;
 
+pub use allocator::{Allocator, Global};
 pub use archetype::Archetype;
 pub use borrow::{EntityRef, Ref, RefMut};
 pub use bundle::{Bundle, DynamicBundle, MissingComponent};
-pub use entities::{Entity, NoSuchEntity};
+pub use dynamic_query::{
+    DynamicChunk, DynamicChunks, DynamicQuery, DynamicQueryBorrow, DynamicQueryIter, DynamicRow,
+};
+pub use entities::{Entity, IdRange, NoSuchEntity, ReusePolicy, SharedEntities, WideEntity};
 pub use entity_builder::{BuiltEntity, EntityBuilder};
-pub use query::{Access, BatchedIter, Query, QueryBorrow, QueryIter, With, Without};
+pub use entity_map::{EntityMap, MapEntities};
+pub use hierarchy::{Ancestors, Children, Descendants, Parent};
+pub use query::{
+    Access, BatchedIter, Query, QueryBorrow, QueryIter, Sparse, With, WithSparse, Without,
+    WithoutSparse,
+};
 pub use query_one::QueryOne;
+pub use registry::{
+    ComponentInfo, ComponentRegistry, DisplayEntity, DisplayWorld, Registration, Unregistered,
+};
+pub use relation::{RelatedBy, RelationSources, RelationTargets, Relations};
+#[cfg(all(feature = "serde", feature = "std"))]
+pub use scene::{Prefab, Scene, SceneEntity};
+#[cfg(feature = "serde")]
+pub use serialize::{SerializeColumns, SerializeRows};
+pub use snapshot::SnapshotError;
+pub use stats::{ArchetypeStats, EntityStats, MemoryStats};
 pub use world::{ArchetypesGeneration, Component, ComponentError, Iter, SpawnBatchIter, World};
 
 // Unstable implementation details needed by the macros
@@ -88,6 +120,8 @@ pub use archetype::TypeInfo;
 pub use lazy_static;
 #[doc(hidden)]
 pub use query::Fetch;
+#[doc(hidden)]
+pub use sparse::SparseSets;
 
 #[cfg(feature = "macros")]
 pub use hecs_macros::Bundle;
@@ -12,11 +12,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use core::any::{type_name, TypeId};
 use core::marker::PhantomData;
 use core::ptr::NonNull;
 
 use crate::archetype::Archetype;
 use crate::entities::EntityMeta;
+use crate::sparse::SparseSets;
 use crate::{Component, Entity};
 
 /// A collection of component types to fetch from a `World`
@@ -30,6 +32,9 @@ pub trait Fetch<'a>: Sized {
     /// Type of value to be fetched
     type Item;
 
+    /// Whether `matches` may reject entities
+    const FILTERED: bool = false;
+
     /// A value on which `get` may never be called
     fn dangling() -> Self;
 
@@ -38,11 +43,28 @@ pub trait Fetch<'a>: Sized {
 
     /// Acquire dynamic borrows from `archetype`
     fn borrow(archetype: &Archetype);
-    /// Construct a `Fetch` for `archetype` if it should be traversed
-    fn new(archetype: &'a Archetype) -> Option<Self>;
+    /// Construct a `Fetch` for `chunk` of `archetype` if it should be traversed
+    fn new(archetype: &'a Archetype, chunk: usize, sparse: &'a SparseSets) -> Option<Self>;
     /// Release dynamic borrows acquired by `borrow`
     fn release(archetype: &Archetype);
 
+    /// Acquire dynamic borrows of sparse components, once per query
+    #[allow(unused_variables)]
+    fn borrow_sparse(sparse: &SparseSets) {}
+    /// Release dynamic borrows acquired by `borrow_sparse`
+    #[allow(unused_variables)]
+    fn release_sparse(sparse: &SparseSets) {}
+
+    /// Whether the `n`th entity in this archetype should be yielded
+    ///
+    /// # Safety
+    /// Bounds-checking must be performed externally
+    #[allow(unused_variables)]
+    #[inline]
+    unsafe fn matches(&self, n: usize) -> bool {
+        true
+    }
+
     /// Access the `n`th item in this archetype without bounds checking
     ///
     /// # Safety
@@ -89,13 +111,17 @@ impl<'a, T: Component> Fetch<'a> for FetchRead<T> {
     fn borrow(archetype: &Archetype) {
         archetype.borrow::<T>();
     }
-    fn new(archetype: &'a Archetype) -> Option<Self> {
-        archetype.get::<T>().map(Self)
+    fn new(archetype: &'a Archetype, chunk: usize, _: &'a SparseSets) -> Option<Self> {
+        archetype.get::<T>(chunk).map(Self)
     }
     fn release(archetype: &Archetype) {
         archetype.release::<T>();
     }
 
+    fn borrow_sparse(sparse: &SparseSets) {
+        debug_assert_dense::<T>(sparse);
+    }
+
     unsafe fn get(&self, n: usize) -> Self::Item {
         &*self.0.as_ptr().add(n)
     }
@@ -126,13 +152,17 @@ impl<'a, T: Component> Fetch<'a> for FetchWrite<T> {
     fn borrow(archetype: &Archetype) {
         archetype.borrow_mut::<T>();
     }
-    fn new(archetype: &'a Archetype) -> Option<Self> {
-        archetype.get::<T>().map(Self)
+    fn new(archetype: &'a Archetype, chunk: usize, _: &'a SparseSets) -> Option<Self> {
+        archetype.get::<T>(chunk).map(Self)
     }
     fn release(archetype: &Archetype) {
         archetype.release_mut::<T>();
     }
 
+    fn borrow_sparse(sparse: &SparseSets) {
+        debug_assert_dense::<T>(sparse);
+    }
+
     unsafe fn get(&self, n: usize) -> Self::Item {
         &mut *self.0.as_ptr().add(n)
     }
@@ -159,15 +189,26 @@ impl<'a, T: Fetch<'a>> Fetch<'a> for TryFetch<T> {
     fn borrow(archetype: &Archetype) {
         T::borrow(archetype)
     }
-    fn new(archetype: &'a Archetype) -> Option<Self> {
-        Some(Self(T::new(archetype)))
+    fn new(archetype: &'a Archetype, chunk: usize, sparse: &'a SparseSets) -> Option<Self> {
+        Some(Self(T::new(archetype, chunk, sparse)))
     }
     fn release(archetype: &Archetype) {
         T::release(archetype)
     }
 
+    fn borrow_sparse(sparse: &SparseSets) {
+        T::borrow_sparse(sparse)
+    }
+    fn release_sparse(sparse: &SparseSets) {
+        T::release_sparse(sparse)
+    }
+
     unsafe fn get(&self, n: usize) -> Option<T::Item> {
-        Some(self.0.as_ref()?.get(n))
+        let fetch = self.0.as_ref()?;
+        if !fetch.matches(n) {
+            return None;
+        }
+        Some(fetch.get(n))
     }
 }
 
@@ -200,6 +241,8 @@ pub struct FetchWithout<T, F>(F, PhantomData<fn(T)>);
 impl<'a, T: Component, F: Fetch<'a>> Fetch<'a> for FetchWithout<T, F> {
     type Item = F::Item;
 
+    const FILTERED: bool = F::FILTERED;
+
     fn dangling() -> Self {
         Self(F::dangling(), PhantomData)
     }
@@ -215,16 +258,27 @@ impl<'a, T: Component, F: Fetch<'a>> Fetch<'a> for FetchWithout<T, F> {
     fn borrow(archetype: &Archetype) {
         F::borrow(archetype)
     }
-    fn new(archetype: &'a Archetype) -> Option<Self> {
+    fn new(archetype: &'a Archetype, chunk: usize, sparse: &'a SparseSets) -> Option<Self> {
         if archetype.has::<T>() {
             return None;
         }
-        Some(Self(F::new(archetype)?, PhantomData))
+        Some(Self(F::new(archetype, chunk, sparse)?, PhantomData))
     }
     fn release(archetype: &Archetype) {
         F::release(archetype)
     }
 
+    fn borrow_sparse(sparse: &SparseSets) {
+        F::borrow_sparse(sparse)
+    }
+    fn release_sparse(sparse: &SparseSets) {
+        F::release_sparse(sparse)
+    }
+
+    unsafe fn matches(&self, n: usize) -> bool {
+        self.0.matches(n)
+    }
+
     unsafe fn get(&self, n: usize) -> F::Item {
         self.0.get(n)
     }
@@ -261,6 +315,8 @@ pub struct FetchWith<T, F>(F, PhantomData<fn(T)>);
 impl<'a, T: Component, F: Fetch<'a>> Fetch<'a> for FetchWith<T, F> {
     type Item = F::Item;
 
+    const FILTERED: bool = F::FILTERED;
+
     fn dangling() -> Self {
         Self(F::dangling(), PhantomData)
     }
@@ -276,16 +332,337 @@ impl<'a, T: Component, F: Fetch<'a>> Fetch<'a> for FetchWith<T, F> {
     fn borrow(archetype: &Archetype) {
         F::borrow(archetype)
     }
-    fn new(archetype: &'a Archetype) -> Option<Self> {
+    fn new(archetype: &'a Archetype, chunk: usize, sparse: &'a SparseSets) -> Option<Self> {
         if !archetype.has::<T>() {
             return None;
         }
-        Some(Self(F::new(archetype)?, PhantomData))
+        Some(Self(F::new(archetype, chunk, sparse)?, PhantomData))
+    }
+    fn release(archetype: &Archetype) {
+        F::release(archetype)
+    }
+
+    fn borrow_sparse(sparse: &SparseSets) {
+        F::borrow_sparse(sparse)
+    }
+    fn release_sparse(sparse: &SparseSets) {
+        F::release_sparse(sparse)
+    }
+
+    unsafe fn matches(&self, n: usize) -> bool {
+        self.0.matches(n)
+    }
+
+    unsafe fn get(&self, n: usize) -> F::Item {
+        self.0.get(n)
+    }
+}
+
+/// Catch `&T` and `&mut T` queries for a sparse `T`, which never match anything
+fn debug_assert_dense<T: Component>(sparse: &SparseSets) {
+    debug_assert!(
+        !sparse.contains(TypeId::of::<T>()),
+        "{} is registered as sparse, so must be queried through `Sparse`",
+        type_name::<T>()
+    );
+}
+
+/// Catch `Sparse` queries for a `T` that isn't sparse, which never match anything
+fn debug_assert_sparse<T: Component>(sparse: &SparseSets) {
+    debug_assert!(
+        sparse.contains(TypeId::of::<T>()),
+        "{} isn't registered as sparse, so can't be queried through `Sparse`",
+        type_name::<T>()
+    );
+}
+
+/// Query type for components in sparse-set storage
+///
+/// `Sparse<&T>` and `Sparse<&mut T>` access a component type registered with
+/// `World::register_sparse`, skipping entities that don't have it. Wrap in an `Option` to visit
+/// entities regardless. Querying a type that isn't registered matches no entities, and panics in
+/// debug builds.
+///
+/// Sparse components don't contribute to `Archetype::access`, so their borrows are not visible to
+/// schedulers inspecting archetypes.
+///
+/// # Example
+/// ```
+/// # use hecs::*;
+/// struct Selected;
+/// let mut world = World::new();
+/// world.register_sparse::<Selected>();
+/// let a = world.spawn((123,));
+/// let b = world.spawn((456, Selected));
+/// let entities = world.query::<(&i32, Sparse<&Selected>)>()
+///     .iter()
+///     .map(|(e, (&i, _))| (e, i))
+///     .collect::<Vec<_>>();
+/// assert_eq!(entities, &[(b, 456)]);
+/// ```
+pub struct Sparse<Q>(PhantomData<Q>);
+
+impl<T: Component> Query for Sparse<&T> {
+    type Fetch = FetchSparseRead<T>;
+}
+
+impl<T: Component> Query for Sparse<&mut T> {
+    type Fetch = FetchSparseWrite<T>;
+}
+
+/// Location of the `T` components of an archetype's entities within a sparse set
+struct SparseColumn<T> {
+    entities: NonNull<u32>,
+    rows: NonNull<u32>,
+    rows_len: usize,
+    base: NonNull<T>,
+}
+
+impl<T: Component> SparseColumn<T> {
+    fn dangling() -> Self {
+        Self {
+            entities: NonNull::dangling(),
+            rows: NonNull::dangling(),
+            rows_len: 0,
+            base: NonNull::dangling(),
+        }
+    }
+
+    fn new(archetype: &Archetype, chunk: usize, sparse: &SparseSets) -> Option<Self> {
+        let set = sparse.get(TypeId::of::<T>())?;
+        let rows = set.rows();
+        Some(Self {
+            entities: unsafe {
+                NonNull::new_unchecked(
+                    archetype
+                        .entities()
+                        .as_ptr()
+                        .add(archetype.chunk_start(chunk) as usize),
+                )
+            },
+            rows: unsafe { NonNull::new_unchecked(rows.as_ptr() as *mut u32) },
+            rows_len: rows.len(),
+            // Storage for the component type is only allocated once an instance is inserted
+            base: set.storage().get::<T>(0).unwrap_or_else(NonNull::dangling),
+        })
+    }
+
+    /// Row of the `n`th entity's component, if it has one
+    #[inline]
+    unsafe fn row(&self, n: usize) -> Option<usize> {
+        let id = *self.entities.as_ptr().add(n) as usize;
+        if id >= self.rows_len {
+            return None;
+        }
+        match *self.rows.as_ptr().add(id) {
+            u32::MAX => None,
+            row => Some(row as usize),
+        }
+    }
+
+    #[inline]
+    unsafe fn get(&self, n: usize) -> *mut T {
+        self.base.as_ptr().add(self.row(n).unwrap_or(0))
+    }
+}
+
+#[doc(hidden)]
+pub struct FetchSparseRead<T>(SparseColumn<T>);
+
+impl<'a, T: Component> Fetch<'a> for FetchSparseRead<T> {
+    type Item = &'a T;
+
+    const FILTERED: bool = true;
+
+    fn dangling() -> Self {
+        Self(SparseColumn::dangling())
+    }
+
+    fn access(_: &Archetype) -> Option<Access> {
+        Some(Access::Iterate)
+    }
+
+    fn borrow(_: &Archetype) {}
+    fn new(archetype: &'a Archetype, chunk: usize, sparse: &'a SparseSets) -> Option<Self> {
+        SparseColumn::new(archetype, chunk, sparse).map(Self)
+    }
+    fn release(_: &Archetype) {}
+
+    fn borrow_sparse(sparse: &SparseSets) {
+        debug_assert_sparse::<T>(sparse);
+        if let Some(set) = sparse.get(TypeId::of::<T>()) {
+            set.storage().borrow::<T>();
+        }
+    }
+    fn release_sparse(sparse: &SparseSets) {
+        if let Some(set) = sparse.get(TypeId::of::<T>()) {
+            set.storage().release::<T>();
+        }
+    }
+
+    #[inline]
+    unsafe fn matches(&self, n: usize) -> bool {
+        self.0.row(n).is_some()
+    }
+
+    unsafe fn get(&self, n: usize) -> Self::Item {
+        &*self.0.get(n)
+    }
+}
+
+#[doc(hidden)]
+pub struct FetchSparseWrite<T>(SparseColumn<T>);
+
+impl<'a, T: Component> Fetch<'a> for FetchSparseWrite<T> {
+    type Item = &'a mut T;
+
+    const FILTERED: bool = true;
+
+    fn dangling() -> Self {
+        Self(SparseColumn::dangling())
+    }
+
+    fn access(_: &Archetype) -> Option<Access> {
+        Some(Access::Iterate)
+    }
+
+    fn borrow(_: &Archetype) {}
+    fn new(archetype: &'a Archetype, chunk: usize, sparse: &'a SparseSets) -> Option<Self> {
+        SparseColumn::new(archetype, chunk, sparse).map(Self)
+    }
+    fn release(_: &Archetype) {}
+
+    fn borrow_sparse(sparse: &SparseSets) {
+        debug_assert_sparse::<T>(sparse);
+        if let Some(set) = sparse.get(TypeId::of::<T>()) {
+            set.storage().borrow_mut::<T>();
+        }
+    }
+    fn release_sparse(sparse: &SparseSets) {
+        if let Some(set) = sparse.get(TypeId::of::<T>()) {
+            set.storage().release_mut::<T>();
+        }
+    }
+
+    #[inline]
+    unsafe fn matches(&self, n: usize) -> bool {
+        self.0.row(n).is_some()
+    }
+
+    unsafe fn get(&self, n: usize) -> Self::Item {
+        &mut *self.0.get(n)
+    }
+}
+
+/// Query transformer skipping entities that have a `T` component in sparse-set storage
+///
+/// See also `QueryBorrow::without_sparse`.
+pub struct WithoutSparse<T, Q>(PhantomData<(Q, fn(T))>);
+
+impl<T: Component, Q: Query> Query for WithoutSparse<T, Q> {
+    type Fetch = FetchWithoutSparse<T, Q::Fetch>;
+}
+
+#[doc(hidden)]
+pub struct FetchWithoutSparse<T, F>(F, Option<SparseColumn<T>>);
+
+impl<'a, T: Component, F: Fetch<'a>> Fetch<'a> for FetchWithoutSparse<T, F> {
+    type Item = F::Item;
+
+    const FILTERED: bool = true;
+
+    fn dangling() -> Self {
+        Self(F::dangling(), None)
+    }
+
+    fn access(archetype: &Archetype) -> Option<Access> {
+        F::access(archetype)
+    }
+
+    fn borrow(archetype: &Archetype) {
+        F::borrow(archetype)
+    }
+    fn new(archetype: &'a Archetype, chunk: usize, sparse: &'a SparseSets) -> Option<Self> {
+        Some(Self(
+            F::new(archetype, chunk, sparse)?,
+            SparseColumn::new(archetype, chunk, sparse),
+        ))
     }
     fn release(archetype: &Archetype) {
         F::release(archetype)
     }
 
+    fn borrow_sparse(sparse: &SparseSets) {
+        F::borrow_sparse(sparse)
+    }
+    fn release_sparse(sparse: &SparseSets) {
+        F::release_sparse(sparse)
+    }
+
+    #[inline]
+    unsafe fn matches(&self, n: usize) -> bool {
+        let excluded = match self.1 {
+            Some(ref x) => x.row(n).is_some(),
+            None => false,
+        };
+        !excluded && self.0.matches(n)
+    }
+
+    unsafe fn get(&self, n: usize) -> F::Item {
+        self.0.get(n)
+    }
+}
+
+/// Query transformer skipping entities that do not have a `T` component in sparse-set storage
+///
+/// See also `QueryBorrow::with_sparse`.
+pub struct WithSparse<T, Q>(PhantomData<(Q, fn(T))>);
+
+impl<T: Component, Q: Query> Query for WithSparse<T, Q> {
+    type Fetch = FetchWithSparse<T, Q::Fetch>;
+}
+
+#[doc(hidden)]
+pub struct FetchWithSparse<T, F>(F, SparseColumn<T>);
+
+impl<'a, T: Component, F: Fetch<'a>> Fetch<'a> for FetchWithSparse<T, F> {
+    type Item = F::Item;
+
+    const FILTERED: bool = true;
+
+    fn dangling() -> Self {
+        Self(F::dangling(), SparseColumn::dangling())
+    }
+
+    fn access(archetype: &Archetype) -> Option<Access> {
+        F::access(archetype)
+    }
+
+    fn borrow(archetype: &Archetype) {
+        F::borrow(archetype)
+    }
+    fn new(archetype: &'a Archetype, chunk: usize, sparse: &'a SparseSets) -> Option<Self> {
+        Some(Self(
+            F::new(archetype, chunk, sparse)?,
+            SparseColumn::new(archetype, chunk, sparse)?,
+        ))
+    }
+    fn release(archetype: &Archetype) {
+        F::release(archetype)
+    }
+
+    fn borrow_sparse(sparse: &SparseSets) {
+        F::borrow_sparse(sparse)
+    }
+    fn release_sparse(sparse: &SparseSets) {
+        F::release_sparse(sparse)
+    }
+
+    #[inline]
+    unsafe fn matches(&self, n: usize) -> bool {
+        self.1.row(n).is_some() && self.0.matches(n)
+    }
+
     unsafe fn get(&self, n: usize) -> F::Item {
         self.0.get(n)
     }
@@ -297,15 +674,21 @@ impl<'a, T: Component, F: Fetch<'a>> Fetch<'a> for FetchWith<T, F> {
 pub struct QueryBorrow<'w, Q: Query> {
     meta: &'w [EntityMeta],
     archetypes: &'w [Archetype],
+    sparse: &'w SparseSets,
     borrowed: bool,
     _marker: PhantomData<Q>,
 }
 
 impl<'w, Q: Query> QueryBorrow<'w, Q> {
-    pub(crate) fn new(meta: &'w [EntityMeta], archetypes: &'w [Archetype]) -> Self {
+    pub(crate) fn new(
+        meta: &'w [EntityMeta],
+        archetypes: &'w [Archetype],
+        sparse: &'w SparseSets,
+    ) -> Self {
         Self {
             meta,
             archetypes,
+            sparse,
             borrowed: false,
             _marker: PhantomData,
         }
@@ -319,6 +702,7 @@ impl<'w, Q: Query> QueryBorrow<'w, Q> {
         QueryIter {
             borrow: self,
             archetype_index: 0,
+            chunk_index: 0,
             iter: ChunkIter::empty(),
         }
     }
@@ -331,6 +715,7 @@ impl<'w, Q: Query> QueryBorrow<'w, Q> {
         BatchedIter {
             borrow: self,
             archetype_index: 0,
+            chunk_index: 0,
             batch_size,
             batch: 0,
         }
@@ -348,6 +733,7 @@ impl<'w, Q: Query> QueryBorrow<'w, Q> {
                 Q::Fetch::borrow(x);
             }
         }
+        Q::Fetch::borrow_sparse(self.sparse);
         self.borrowed = true;
     }
 
@@ -399,11 +785,58 @@ impl<'w, Q: Query> QueryBorrow<'w, Q> {
         self.transform()
     }
 
+    /// Transform the query into one that requires a certain sparse component without borrowing it
+    ///
+    /// Equivalent to using a query type wrapped in `WithSparse`.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// struct Selected;
+    /// let mut world = World::new();
+    /// world.register_sparse::<Selected>();
+    /// let a = world.spawn((123, Selected));
+    /// let b = world.spawn((456,));
+    /// let entities = world.query::<&i32>()
+    ///     .with_sparse::<Selected>()
+    ///     .iter()
+    ///     .map(|(e, &i)| (e, i)) // Copy out of the world
+    ///     .collect::<Vec<_>>();
+    /// assert_eq!(entities, &[(a, 123)]);
+    /// ```
+    pub fn with_sparse<T: Component>(self) -> QueryBorrow<'w, WithSparse<T, Q>> {
+        self.transform()
+    }
+
+    /// Transform the query into one that skips entities having a certain sparse component
+    ///
+    /// Equivalent to using a query type wrapped in `WithoutSparse`.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// struct Selected;
+    /// let mut world = World::new();
+    /// world.register_sparse::<Selected>();
+    /// let a = world.spawn((123, Selected));
+    /// let b = world.spawn((456,));
+    /// let entities = world.query::<&i32>()
+    ///     .without_sparse::<Selected>()
+    ///     .iter()
+    ///     .map(|(e, &i)| (e, i)) // Copy out of the world
+    ///     .collect::<Vec<_>>();
+    /// assert_eq!(entities, &[(b, 456)]);
+    /// ```
+    pub fn without_sparse<T: Component>(self) -> QueryBorrow<'w, WithoutSparse<T, Q>> {
+        self.transform()
+    }
+
     /// Helper to change the type of the query
     fn transform<R: Query>(mut self) -> QueryBorrow<'w, R> {
         let x = QueryBorrow {
             meta: self.meta,
             archetypes: self.archetypes,
+            sparse: self.sparse,
             borrowed: self.borrowed,
             _marker: PhantomData,
         };
@@ -424,6 +857,7 @@ impl<'w, Q: Query> Drop for QueryBorrow<'w, Q> {
                     Q::Fetch::release(x);
                 }
             }
+            Q::Fetch::release_sparse(self.sparse);
         }
     }
 }
@@ -441,6 +875,7 @@ impl<'q, 'w, Q: Query> IntoIterator for &'q mut QueryBorrow<'w, Q> {
 pub struct QueryIter<'q, 'w, Q: Query> {
     borrow: &'q mut QueryBorrow<'w, Q>,
     archetype_index: usize,
+    chunk_index: usize,
     iter: ChunkIter<Q>,
 }
 
@@ -456,14 +891,17 @@ impl<'q, 'w, Q: Query> Iterator for QueryIter<'q, 'w, Q> {
             match unsafe { self.iter.next() } {
                 None => {
                     let archetype = self.borrow.archetypes.get(self.archetype_index)?;
+                    if self.chunk_index < archetype.chunk_count() {
+                        if let Some(iter) =
+                            ChunkIter::new(archetype, self.chunk_index, self.borrow.sparse)
+                        {
+                            self.chunk_index += 1;
+                            self.iter = iter;
+                            continue;
+                        }
+                    }
                     self.archetype_index += 1;
-                    self.iter =
-                        Q::Fetch::new(archetype).map_or(ChunkIter::empty(), |fetch| ChunkIter {
-                            entities: archetype.entities(),
-                            fetch,
-                            position: 0,
-                            len: archetype.len() as usize,
-                        });
+                    self.chunk_index = 0;
                     continue;
                 }
                 Some((id, components)) => {
@@ -489,11 +927,26 @@ impl<'q, 'w, Q: Query> Iterator for QueryIter<'q, 'w, Q> {
 
 impl<'q, 'w, Q: Query> ExactSizeIterator for QueryIter<'q, 'w, Q> {
     fn len(&self) -> usize {
+        let sparse = self.borrow.sparse;
         self.borrow
             .archetypes
             .iter()
             .filter(|&x| Q::Fetch::access(x).is_some())
-            .map(|x| x.len() as usize)
+            .map(|x| {
+                if !<Q::Fetch as Fetch<'q>>::FILTERED {
+                    return x.len() as usize;
+                }
+                // Entities may be rejected individually, so they must be counted one by one
+                (0..x.chunk_count())
+                    .map(|chunk| {
+                        Q::Fetch::new(x, chunk, sparse).map_or(0, |fetch| {
+                            (0..x.chunk_len(chunk) as usize)
+                                .filter(|&n| unsafe { fetch.matches(n) })
+                                .count()
+                        })
+                    })
+                    .sum()
+            })
             .sum()
     }
 }
@@ -515,15 +968,41 @@ impl<Q: Query> ChunkIter<Q> {
         }
     }
 
+    /// Iterate over every entity in `chunk` of `archetype`, if `Q` matches the archetype
+    fn new<'a>(archetype: &'a Archetype, chunk: usize, sparse: &'a SparseSets) -> Option<Self>
+    where
+        Q::Fetch: Fetch<'a>,
+    {
+        let fetch = Q::Fetch::new(archetype, chunk, sparse)?;
+        Some(Self {
+            entities: unsafe {
+                NonNull::new_unchecked(
+                    archetype
+                        .entities()
+                        .as_ptr()
+                        .add(archetype.chunk_start(chunk) as usize),
+                )
+            },
+            fetch,
+            position: 0,
+            len: archetype.chunk_len(chunk) as usize,
+        })
+    }
+
     #[inline]
     unsafe fn next<'a>(&mut self) -> Option<(u32, <Q::Fetch as Fetch<'a>>::Item)> {
-        if self.position == self.len {
-            return None;
+        loop {
+            if self.position == self.len {
+                return None;
+            }
+            let position = self.position;
+            self.position += 1;
+            if !self.fetch.matches(position) {
+                continue;
+            }
+            let entity = self.entities.as_ptr().add(position);
+            return Some((*entity, self.fetch.get(position)));
         }
-        let entity = self.entities.as_ptr().add(self.position);
-        let item = self.fetch.get(self.position);
-        self.position += 1;
-        Some((*entity, item))
     }
 }
 
@@ -531,6 +1010,7 @@ impl<Q: Query> ChunkIter<Q> {
 pub struct BatchedIter<'q, 'w, Q: Query> {
     borrow: &'q mut QueryBorrow<'w, Q>,
     archetype_index: usize,
+    chunk_index: usize,
     batch_size: u32,
     batch: u32,
 }
@@ -544,28 +1024,34 @@ impl<'q, 'w, Q: Query> Iterator for BatchedIter<'q, 'w, Q> {
     fn next(&mut self) -> Option<Self::Item> {
         loop {
             let archetype = self.borrow.archetypes.get(self.archetype_index)?;
-            let offset = self.batch_size * self.batch;
-            if offset >= archetype.len() {
+            if self.chunk_index >= archetype.chunk_count() {
                 self.archetype_index += 1;
+                self.chunk_index = 0;
                 self.batch = 0;
                 continue;
             }
-            if let Some(fetch) = Q::Fetch::new(archetype) {
+            let chunk_len = archetype.chunk_len(self.chunk_index);
+            let offset = self.batch_size * self.batch;
+            if offset >= chunk_len {
+                self.chunk_index += 1;
+                self.batch = 0;
+                continue;
+            }
+            if let Some(mut state) = ChunkIter::new(archetype, self.chunk_index, self.borrow.sparse)
+            {
                 self.batch += 1;
+                state.position = offset as usize;
+                state.len = (offset + self.batch_size.min(chunk_len - offset)) as usize;
                 return Some(Batch {
                     _marker: PhantomData,
                     meta: self.borrow.meta,
-                    state: ChunkIter {
-                        entities: archetype.entities(),
-                        fetch,
-                        len: (offset + self.batch_size.min(archetype.len() - offset)) as usize,
-                        position: offset as usize,
-                    },
+                    state,
                 });
             } else {
                 self.archetype_index += 1;
                 debug_assert_eq!(
-                    self.batch, 0,
+                    (self.chunk_index, self.batch),
+                    (0, 0),
                     "query fetch should always reject at the first batch or not at all"
                 );
                 continue;
@@ -604,6 +1090,8 @@ macro_rules! tuple_impl {
         impl<'a, $($name: Fetch<'a>),*> Fetch<'a> for ($($name,)*) {
             type Item = ($($name::Item,)*);
 
+            const FILTERED: bool = false $(|| $name::FILTERED)*;
+
             fn dangling() -> Self {
                 ($($name::dangling(),)*)
             }
@@ -622,14 +1110,31 @@ macro_rules! tuple_impl {
                 $($name::borrow(archetype);)*
             }
             #[allow(unused_variables)]
-            fn new(archetype: &'a Archetype) -> Option<Self> {
-                Some(($($name::new(archetype)?,)*))
+            fn new(archetype: &'a Archetype, chunk: usize, sparse: &'a SparseSets) -> Option<Self> {
+                Some(($($name::new(archetype, chunk, sparse)?,)*))
             }
             #[allow(unused_variables)]
             fn release(archetype: &Archetype) {
                 $($name::release(archetype);)*
             }
 
+            #[allow(unused_variables)]
+            fn borrow_sparse(sparse: &SparseSets) {
+                $($name::borrow_sparse(sparse);)*
+            }
+            #[allow(unused_variables)]
+            fn release_sparse(sparse: &SparseSets) {
+                $($name::release_sparse(sparse);)*
+            }
+
+            #[allow(unused_variables)]
+            #[inline]
+            unsafe fn matches(&self, n: usize) -> bool {
+                #[allow(non_snake_case)]
+                let ($($name,)*) = self;
+                true $(&& $name.matches(n))*
+            }
+
             #[allow(unused_variables)]
             unsafe fn get(&self, n: usize) -> Self::Item {
                 #[allow(non_snake_case)]
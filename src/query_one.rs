@@ -1,12 +1,14 @@
 use core::marker::PhantomData;
 
-use crate::query::{Fetch, With, Without};
+use crate::query::{Fetch, With, WithSparse, Without, WithoutSparse};
+use crate::sparse::SparseSets;
 use crate::{Archetype, Component, Query};
 
 /// A borrow of a `World` sufficient to execute the query `Q` on a single entity
 pub struct QueryOne<'a, Q: Query> {
     archetype: &'a Archetype,
     index: u32,
+    sparse: &'a SparseSets,
     borrowed: bool,
     _marker: PhantomData<Q>,
 }
@@ -17,10 +19,11 @@ impl<'a, Q: Query> QueryOne<'a, Q> {
     /// # Safety
     ///
     /// `index` must be in-bounds for `archetype`
-    pub(crate) unsafe fn new(archetype: &'a Archetype, index: u32) -> Self {
+    pub(crate) unsafe fn new(archetype: &'a Archetype, index: u32, sparse: &'a SparseSets) -> Self {
         Self {
             archetype,
             index,
+            sparse,
             borrowed: false,
             _marker: PhantomData,
         }
@@ -37,10 +40,15 @@ impl<'a, Q: Query> QueryOne<'a, Q> {
             panic!("called QueryOnce::get twice; construct a new query instead");
         }
         unsafe {
-            let fetch = Q::Fetch::new(self.archetype)?;
+            let (chunk, index) = self.archetype.locate(self.index);
+            let fetch = Q::Fetch::new(self.archetype, chunk, self.sparse)?;
+            if !fetch.matches(index as usize) {
+                return None;
+            }
             self.borrowed = true;
             Q::Fetch::borrow(self.archetype);
-            Some(fetch.get(self.index as usize))
+            Q::Fetch::borrow_sparse(self.sparse);
+            Some(fetch.get(index as usize))
         }
     }
 
@@ -58,11 +66,26 @@ impl<'a, Q: Query> QueryOne<'a, Q> {
         self.transform()
     }
 
+    /// Transform the query into one that requires a certain sparse component without borrowing it
+    ///
+    /// See `QueryBorrow::with_sparse` for details.
+    pub fn with_sparse<T: Component>(self) -> QueryOne<'a, WithSparse<T, Q>> {
+        self.transform()
+    }
+
+    /// Transform the query into one that skips entities having a certain sparse component
+    ///
+    /// See `QueryBorrow::without_sparse` for details.
+    pub fn without_sparse<T: Component>(self) -> QueryOne<'a, WithoutSparse<T, Q>> {
+        self.transform()
+    }
+
     /// Helper to change the type of the query
     fn transform<R: Query>(mut self) -> QueryOne<'a, R> {
         let x = QueryOne {
             archetype: self.archetype,
             index: self.index,
+            sparse: self.sparse,
             borrowed: self.borrowed,
             _marker: PhantomData,
         };
@@ -76,6 +99,7 @@ impl<Q: Query> Drop for QueryOne<'_, Q> {
     fn drop(&mut self) {
         if self.borrowed {
             Q::Fetch::release(self.archetype);
+            Q::Fetch::release_sparse(self.sparse);
         }
     }
 }
@@ -0,0 +1,508 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::alloc::vec::Vec;
+use core::any::TypeId;
+use core::fmt;
+use core::marker::PhantomData;
+
+use hashbrown::HashMap;
+
+use crate::archetype::{TypeIdMap, TypeInfo};
+use crate::{Allocator, Component, EntityBuilder, EntityRef, Global, World};
+
+/// A collection of component types known by name, with optional formatting and construction
+/// logic
+///
+/// Useful for debugging, editors, and other tooling that needs to operate on components without
+/// naming their types statically.
+///
+/// # Example
+/// ```
+/// # use hecs::*;
+/// let mut registry = ComponentRegistry::new();
+/// registry.register::<i32>("health").debug();
+/// registry.register::<&str>("name").display();
+/// let mut world = World::new();
+/// let e = world.spawn((10, "goblin", true));
+/// let entity = world.entity(e).unwrap();
+/// assert_eq!(registry.display_entity(entity).to_string(), "{health: 10, name: goblin}");
+/// ```
+#[derive(Default)]
+pub struct ComponentRegistry {
+    components: Vec<ComponentInfo>,
+    by_id: TypeIdMap<usize>,
+    by_name: HashMap<&'static str, usize>,
+    by_stable_id: HashMap<u32, usize>,
+}
+
+impl ComponentRegistry {
+    /// Create a registry with no known component types
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `T` as a component type named `name`
+    ///
+    /// Registering a type again replaces its previous name and stable ID and discards its vtables.
+    /// Panics if a different type is already registered as `name`.
+    pub fn register<T: Component>(&mut self, name: &'static str) -> Registration<'_, T> {
+        if let Some(&index) = self.by_name.get(name) {
+            if self.components[index].id() != TypeId::of::<T>() {
+                panic!("component name {} already registered", name);
+            }
+        }
+        let info = ComponentInfo {
+            name,
+            type_info: TypeInfo::of::<T>(),
+            debug: None,
+            display: None,
+            default: None,
+            stable_id: None,
+            pod: false,
+            #[cfg(feature = "serde")]
+            serde: None,
+            #[cfg(all(feature = "serde", feature = "std"))]
+            scene: None,
+            #[cfg(all(feature = "serde", feature = "std"))]
+            map_entities: None,
+        };
+        let index = match self.by_id.get(&TypeId::of::<T>()) {
+            Some(&index) => {
+                let old = &self.components[index];
+                self.by_name.remove(old.name);
+                if let Some(id) = old.stable_id {
+                    self.by_stable_id.remove(&id);
+                }
+                self.components[index] = info;
+                index
+            }
+            None => {
+                self.by_id.insert(TypeId::of::<T>(), self.components.len());
+                self.components.push(info);
+                self.components.len() - 1
+            }
+        };
+        self.by_name.insert(name, index);
+        Registration {
+            info: &mut self.components[index],
+            index,
+            by_stable_id: &mut self.by_stable_id,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Look up a registered component type by its `TypeId`
+    pub fn get(&self, id: TypeId) -> Option<&ComponentInfo> {
+        Some(&self.components[*self.by_id.get(&id)?])
+    }
+
+    /// Look up a registered component type by name
+    pub fn get_by_name(&self, name: &str) -> Option<&ComponentInfo> {
+        Some(&self.components[*self.by_name.get(name)?])
+    }
+
+    /// Look up a registered component type by the ID assigned with `Registration::stable_id`
+    pub fn get_by_stable_id(&self, id: u32) -> Option<&ComponentInfo> {
+        Some(&self.components[*self.by_stable_id.get(&id)?])
+    }
+
+    /// Iterate over all registered component types in the order they were registered
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = &'_ ComponentInfo> + '_ {
+        self.components.iter()
+    }
+
+    /// Format the registered components of `entity`
+    ///
+    /// Components are written in registration order, as `name: value`. Values are formatted with
+    /// their `Display` implementation if the result is formatted with `{}`, or with `Debug` if
+    /// formatted with `{:?}`, falling back to whichever is available. Components of unregistered
+    /// types are omitted.
+    ///
+    /// Formatting borrows each component, and hence panics if any is uniquely borrowed elsewhere.
+    pub fn display_entity<'a>(&'a self, entity: EntityRef<'a>) -> DisplayEntity<'a> {
+        DisplayEntity {
+            registry: self,
+            entity,
+        }
+    }
+
+    /// Format every entity in `world`
+    ///
+    /// Each entity is written with its ID followed by its components as formatted by
+    /// `display_entity`.
+    pub fn display_world<'a, A: Allocator>(&'a self, world: &'a World<A>) -> DisplayWorld<'a, A> {
+        DisplayWorld {
+            registry: self,
+            world,
+        }
+    }
+
+    /// Invoke `f` on every registered component of `entity`, in registration order
+    ///
+    /// Each component is borrowed for the duration of its call.
+    pub(crate) fn for_each<E>(
+        &self,
+        entity: EntityRef<'_>,
+        mut f: impl FnMut(&ComponentInfo, *const u8) -> Result<(), E>,
+    ) -> Result<(), E> {
+        for info in &self.components {
+            let ty = info.type_info;
+            let (archetype, index) = match entity.component(ty.id()) {
+                Some(x) => x,
+                None => continue,
+            };
+            let _borrow = archetype.borrow_column(ty.id());
+            let ptr = unsafe {
+                archetype
+                    .get_dynamic(ty.id(), ty.layout().size(), index)
+                    .unwrap()
+            };
+            f(info, ptr.as_ptr())?;
+        }
+        Ok(())
+    }
+}
+
+/// How to treat components whose types weren't registered with the functionality needed to save or
+/// load them, such as `Registration::serde`
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum Unregistered {
+    /// Leave them out when saving, and ignore them when loading
+    #[default]
+    Skip,
+    /// Fail with an error
+    Error,
+}
+
+/// Metadata for a component type registered in a `ComponentRegistry`
+pub struct ComponentInfo {
+    name: &'static str,
+    type_info: TypeInfo,
+    debug: Option<FormatFn>,
+    display: Option<FormatFn>,
+    default: Option<fn(&mut EntityBuilder)>,
+    stable_id: Option<u32>,
+    pod: bool,
+    #[cfg(feature = "serde")]
+    pub(crate) serde: Option<crate::serialize::SerdeFns>,
+    #[cfg(all(feature = "serde", feature = "std"))]
+    pub(crate) scene: Option<crate::scene::SceneFns>,
+    #[cfg(all(feature = "serde", feature = "std"))]
+    pub(crate) map_entities: Option<crate::scene::MapFn>,
+}
+
+type FormatFn = unsafe fn(*const u8, &mut fmt::Formatter<'_>) -> fmt::Result;
+
+impl ComponentInfo {
+    /// The name the component type was registered with
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The `TypeId` of the component type
+    ///
+    /// `TypeId`s may differ between builds, so should never be saved or sent elsewhere. Use
+    /// `name` or `stable_id` instead.
+    pub fn id(&self) -> TypeId {
+        self.type_info.id()
+    }
+
+    /// The ID assigned to the component type with `Registration::stable_id`, if any
+    pub fn stable_id(&self) -> Option<u32> {
+        self.stable_id
+    }
+
+    pub(crate) fn type_info(&self) -> TypeInfo {
+        self.type_info
+    }
+
+    /// Whether the component type was registered with a `Debug` implementation
+    pub fn has_debug(&self) -> bool {
+        self.debug.is_some()
+    }
+
+    /// Whether the component type was registered with a `Display` implementation
+    pub fn has_display(&self) -> bool {
+        self.display.is_some()
+    }
+
+    /// Whether the component type was registered with a `Default` implementation
+    pub fn has_default(&self) -> bool {
+        self.default.is_some()
+    }
+
+    /// Whether the component type was registered as plain old data
+    pub fn is_pod(&self) -> bool {
+        self.pod
+    }
+
+    /// Whether the component type was registered with `Serialize` and `Deserialize`
+    /// implementations
+    #[cfg(feature = "serde")]
+    pub fn has_serde(&self) -> bool {
+        self.serde.is_some()
+    }
+
+    /// Whether the component type was registered for use in scenes
+    #[cfg(all(feature = "serde", feature = "std"))]
+    pub fn has_scene(&self) -> bool {
+        self.scene.is_some()
+    }
+
+    /// Add a default-constructed instance of the component to `builder`
+    ///
+    /// Returns `false`, leaving `builder` untouched, if no `Default` implementation was
+    /// registered.
+    pub fn add_default(&self, builder: &mut EntityBuilder) -> bool {
+        match self.default {
+            Some(f) => {
+                f(builder);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Format the value at `ptr`, preferring `first` and falling back to `second`
+    ///
+    /// # Safety
+    /// `ptr` must point to a live value of this component type
+    unsafe fn fmt_value(
+        &self,
+        first: Option<FormatFn>,
+        second: Option<FormatFn>,
+        ptr: *const u8,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        match first.or(second) {
+            Some(format) => format(ptr, f),
+            None => f.write_str(".."),
+        }
+    }
+}
+
+/// Handle for attaching optional functionality to a newly registered component type
+///
+/// Returned by `ComponentRegistry::register`.
+pub struct Registration<'a, T> {
+    info: &'a mut ComponentInfo,
+    index: usize,
+    by_stable_id: &'a mut HashMap<u32, usize>,
+    _marker: PhantomData<fn(T)>,
+}
+
+impl<T: Component> Registration<'_, T> {
+    /// Identify the component type by `id` as well as by name
+    ///
+    /// Numeric IDs are more compact than names, making them suitable for identifying components
+    /// in network protocols and other space-sensitive formats. Like names, they're chosen by the
+    /// application, and hence remain the same across builds and platforms so long as the
+    /// application's registrations do. Panics if a different type already has the ID `id`.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// # use core::any::TypeId;
+    /// let mut registry = ComponentRegistry::new();
+    /// registry.register::<i32>("health").stable_id(7);
+    /// let info = registry.get_by_stable_id(7).unwrap();
+    /// assert_eq!(info.name(), "health");
+    /// assert_eq!(info.id(), TypeId::of::<i32>());
+    /// assert_eq!(registry.get_by_name("health").unwrap().stable_id(), Some(7));
+    /// ```
+    pub fn stable_id(self, id: u32) -> Self {
+        match self.by_stable_id.get(&id) {
+            Some(&index) if index != self.index => {
+                panic!("component ID {} already registered", id)
+            }
+            _ => {}
+        }
+        if let Some(old) = self.info.stable_id.replace(id) {
+            self.by_stable_id.remove(&old);
+        }
+        self.by_stable_id.insert(id, self.index);
+        self
+    }
+
+    /// Format the component with its `Debug` implementation
+    pub fn debug(self) -> Self
+    where
+        T: fmt::Debug,
+    {
+        unsafe fn fmt_debug<T: fmt::Debug>(
+            x: *const u8,
+            f: &mut fmt::Formatter<'_>,
+        ) -> fmt::Result {
+            fmt::Debug::fmt(&*x.cast::<T>(), f)
+        }
+        self.info.debug = Some(fmt_debug::<T>);
+        self
+    }
+
+    /// Format the component with its `Display` implementation
+    pub fn display(self) -> Self
+    where
+        T: fmt::Display,
+    {
+        unsafe fn fmt_display<T: fmt::Display>(
+            x: *const u8,
+            f: &mut fmt::Formatter<'_>,
+        ) -> fmt::Result {
+            fmt::Display::fmt(&*x.cast::<T>(), f)
+        }
+        self.info.display = Some(fmt_display::<T>);
+        self
+    }
+
+    /// Allow default-constructed instances of the component to be created dynamically
+    ///
+    /// See `ComponentInfo::add_default`.
+    pub fn default(self) -> Self
+    where
+        T: Default,
+    {
+        fn add_default<T: Component + Default>(builder: &mut EntityBuilder) {
+            builder.add(T::default());
+        }
+        self.info.default = Some(add_default::<T>);
+        self
+    }
+
+    /// Allow the component to be saved and loaded as raw bytes
+    ///
+    /// See `ComponentRegistry::snapshot`.
+    ///
+    /// # Safety
+    /// `T` must contain no padding, pointers, or references, and every bit pattern of the right
+    /// size must be a valid `T`
+    pub unsafe fn pod(self) -> Self
+    where
+        T: Copy,
+    {
+        self.info.pod = true;
+        self
+    }
+
+    /// Allow the component to be saved and loaded with its serde implementations
+    ///
+    /// See `ComponentRegistry::serialize_rows`.
+    #[cfg(feature = "serde")]
+    pub fn serde(self) -> Self
+    where
+        T: serde::Serialize + for<'de> serde::Deserialize<'de>,
+    {
+        self.info.serde = Some(crate::serialize::SerdeFns::of::<T>());
+        self
+    }
+
+    /// Allow the component to be loaded as part of a `Scene`
+    ///
+    /// See `ComponentRegistry::deserialize_scene`.
+    #[cfg(all(feature = "serde", feature = "std"))]
+    pub fn scene(self) -> Self
+    where
+        T: Clone + for<'de> serde::Deserialize<'de>,
+    {
+        self.info.scene = Some(crate::scene::SceneFns::of::<T>());
+        self
+    }
+
+    /// Allow the component to refer to other entities in a `Scene` through `SceneEntity`,
+    /// rewriting the references when it's spawned
+    #[cfg(all(feature = "serde", feature = "std"))]
+    pub fn map_entities(self) -> Self
+    where
+        T: crate::MapEntities,
+    {
+        unsafe fn map_entities<T: crate::MapEntities>(x: *mut u8, map: &crate::EntityMap) {
+            (*x.cast::<T>()).map_entities(map);
+        }
+        self.info.map_entities = Some(map_entities::<T>);
+        self
+    }
+}
+
+/// Helper for formatting an entity's components; see `ComponentRegistry::display_entity`
+pub struct DisplayEntity<'a> {
+    registry: &'a ComponentRegistry,
+    entity: EntityRef<'a>,
+}
+
+impl fmt::Display for DisplayEntity<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("{")?;
+        let mut first = true;
+        self.registry.for_each(self.entity, |info, ptr| {
+            if !first {
+                f.write_str(", ")?;
+            }
+            first = false;
+            write!(f, "{}: ", info.name)?;
+            unsafe { info.fmt_value(info.display, info.debug, ptr, f) }
+        })?;
+        f.write_str("}")
+    }
+}
+
+impl fmt::Debug for DisplayEntity<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        struct Value<'a>(&'a ComponentInfo, *const u8);
+        impl fmt::Debug for Value<'_> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                unsafe { self.0.fmt_value(self.0.debug, self.0.display, self.1, f) }
+            }
+        }
+
+        // Components must be formatted while borrowed, so the map is driven by hand
+        let mut map = f.debug_map();
+        self.registry.for_each(self.entity, |info, ptr| {
+            map.entry(&info.name, &Value(info, ptr));
+            Ok(())
+        })?;
+        map.finish()
+    }
+}
+
+/// Helper for formatting an entire world; see `ComponentRegistry::display_world`
+pub struct DisplayWorld<'a, A: Allocator = Global> {
+    registry: &'a ComponentRegistry,
+    world: &'a World<A>,
+}
+
+impl<A: Allocator> fmt::Display for DisplayWorld<'_, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (entity, components) in self.world {
+            writeln!(
+                f,
+                "{:?}: {}",
+                entity,
+                self.registry.display_entity(components)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl<A: Allocator> fmt::Debug for DisplayWorld<'_, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(
+                self.world
+                    .iter()
+                    .map(|(entity, components)| (entity, self.registry.display_entity(components))),
+            )
+            .finish()
+    }
+}
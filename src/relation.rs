@@ -0,0 +1,202 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::alloc::{vec, vec::Vec};
+use core::marker::PhantomData;
+use core::mem;
+
+use crate::{Allocator, Component, Entity, Ref, World};
+
+/// Component holding an entity's outgoing relationships of kind `R`
+///
+/// Each relationship pairs a target entity with a value of `R`, so an entity may be related to any
+/// number of distinct targets by the same kind of relationship. Maintained by `World::relate` and
+/// `World::unrelate`.
+///
+/// Querying for `&Relations<R>` visits every entity that has at least one relationship of kind
+/// `R`, regardless of target.
+#[derive(Debug, Clone)]
+pub struct Relations<R> {
+    pub(crate) pairs: Vec<(Entity, R)>,
+}
+
+impl<R> Relations<R> {
+    /// Iterate over the targets of these relationships and their associated values
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = (Entity, &'_ R)> + '_ {
+        self.pairs.iter().map(|(target, value)| (*target, value))
+    }
+
+    /// Iterate over the targets of these relationships
+    pub fn targets(&self) -> impl ExactSizeIterator<Item = Entity> + '_ {
+        self.pairs.iter().map(|x| x.0)
+    }
+
+    /// The value of the relationship with `target`, if any
+    pub fn get(&self, target: Entity) -> Option<&R> {
+        self.pairs.iter().find(|x| x.0 == target).map(|x| &x.1)
+    }
+
+    /// Mutably access the value of the relationship with `target`, if any
+    pub fn get_mut(&mut self, target: Entity) -> Option<&mut R> {
+        self.pairs
+            .iter_mut()
+            .find(|x| x.0 == target)
+            .map(|x| &mut x.1)
+    }
+
+    /// Number of relationships
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    /// Whether there are no relationships
+    ///
+    /// Always false for components attached by `World::relate`.
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
+
+/// Component listing the entities that have a relationship of kind `R` targeting this entity
+///
+/// The reverse index of `Relations<R>`, maintained alongside it.
+#[derive(Debug, Clone)]
+pub struct RelatedBy<R> {
+    pub(crate) sources: Vec<Entity>,
+    _marker: PhantomData<fn(R)>,
+}
+
+impl<R> RelatedBy<R> {
+    pub(crate) fn new(source: Entity) -> Self {
+        Self {
+            sources: vec![source],
+            _marker: PhantomData,
+        }
+    }
+
+    /// Entities that have a relationship of kind `R` with this entity
+    pub fn sources(&self) -> &[Entity] {
+        &self.sources
+    }
+}
+
+/// Iterator over the targets of an entity's relationships of kind `R`
+///
+/// Returned by `World::relation_targets`. Holds a shared borrow of the `Relations<R>` component.
+pub struct RelationTargets<'a, R: Component> {
+    relations: Option<Ref<'a, Relations<R>>>,
+    index: usize,
+}
+
+impl<'a, R: Component> RelationTargets<'a, R> {
+    pub(crate) fn new(relations: Option<Ref<'a, Relations<R>>>) -> Self {
+        Self {
+            relations,
+            index: 0,
+        }
+    }
+}
+
+impl<R: Component> Iterator for RelationTargets<'_, R> {
+    type Item = Entity;
+
+    fn next(&mut self) -> Option<Entity> {
+        let (target, _) = *self.relations.as_ref()?.pairs.get(self.index)?;
+        self.index += 1;
+        Some(target)
+    }
+}
+
+/// Iterator over the entities with a relationship of kind `R` targeting an entity
+///
+/// Returned by `World::relation_sources`. Holds a shared borrow of the `RelatedBy<R>` component.
+pub struct RelationSources<'a, R: Component> {
+    related: Option<Ref<'a, RelatedBy<R>>>,
+    index: usize,
+}
+
+impl<'a, R: Component> RelationSources<'a, R> {
+    pub(crate) fn new(related: Option<Ref<'a, RelatedBy<R>>>) -> Self {
+        Self { related, index: 0 }
+    }
+}
+
+impl<R: Component> Iterator for RelationSources<'_, R> {
+    type Item = Entity;
+
+    fn next(&mut self) -> Option<Entity> {
+        let source = *self.related.as_ref()?.sources.get(self.index)?;
+        self.index += 1;
+        Some(source)
+    }
+}
+
+/// Remove `source` from the `RelatedBy<R>` of `target`, dropping the component once it's empty
+pub(crate) fn remove_source<R: Component, A: Allocator>(
+    world: &mut World<A>,
+    target: Entity,
+    source: Entity,
+) {
+    let empty = match world.get_mut::<RelatedBy<R>>(target) {
+        Ok(mut related) => {
+            related.sources.retain(|&x| x != source);
+            related.sources.is_empty()
+        }
+        Err(_) => return,
+    };
+    if empty {
+        let _ = world.remove_one::<RelatedBy<R>>(target);
+    }
+}
+
+/// Remove the relationship with `target` from the `Relations<R>` of `source`, dropping the
+/// component once it's empty
+pub(crate) fn remove_target<R: Component, A: Allocator>(
+    world: &mut World<A>,
+    source: Entity,
+    target: Entity,
+) -> Option<R> {
+    let (value, empty) = {
+        let mut relations = world.get_mut::<Relations<R>>(source).ok()?;
+        let index = relations.pairs.iter().position(|x| x.0 == target)?;
+        let (_, value) = relations.pairs.remove(index);
+        (value, relations.pairs.is_empty())
+    };
+    if empty {
+        let _ = world.remove_one::<Relations<R>>(source);
+    }
+    Some(value)
+}
+
+/// Forget every relationship of kind `R` held by `source`, which is about to be despawned
+pub(crate) fn unlink_relations<R: Component, A: Allocator>(world: &mut World<A>, source: Entity) {
+    let pairs = world
+        .get_mut::<Relations<R>>(source)
+        .map(|mut x| mem::take(&mut x.pairs))
+        .unwrap_or_default();
+    for (target, _) in pairs {
+        remove_source::<R, A>(world, target, source);
+    }
+}
+
+/// Forget every relationship of kind `R` targeting `target`, which is about to be despawned
+pub(crate) fn unlink_related_by<R: Component, A: Allocator>(world: &mut World<A>, target: Entity) {
+    let sources = world
+        .get_mut::<RelatedBy<R>>(target)
+        .map(|mut x| mem::take(&mut x.sources))
+        .unwrap_or_default();
+    for source in sources {
+        remove_target::<R, A>(world, source, target);
+    }
+}
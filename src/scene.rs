@@ -0,0 +1,678 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::alloc::{string::String, sync::Arc, vec::Vec};
+use core::any::{Any, TypeId};
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use std::collections::hash_map::DefaultHasher;
+
+use hashbrown::HashMap;
+use serde::de::{self, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    Allocator, Component, ComponentRegistry, Entity, EntityBuilder, EntityMap, MapEntities,
+    Unregistered, World,
+};
+
+/// Rewrites the entities referred to by the component at a pointer
+pub(crate) type MapFn = unsafe fn(*mut u8, &EntityMap);
+
+type Value = Arc<dyn Any + Send + Sync>;
+type AddFn = fn(&(dyn Any + Send + Sync), Option<MapFn>, &EntityMap, &mut EntityBuilder);
+type ResolvesFn = fn(&(dyn Any + Send + Sync), MapFn, &EntityMap) -> bool;
+
+/// Type-erased scene support for a registered component type
+#[derive(Copy, Clone)]
+pub(crate) struct SceneFns {
+    deserialize: fn(&mut dyn erased_serde::Deserializer<'_>) -> Result<Value, erased_serde::Error>,
+    add: AddFn,
+    /// Whether `map` has a counterpart for every `SceneEntity` in a value
+    resolves: ResolvesFn,
+}
+
+impl SceneFns {
+    pub(crate) fn of<T>() -> Self
+    where
+        T: Component + Clone + for<'de> Deserialize<'de>,
+    {
+        fn deserialize<T: Component + for<'de> Deserialize<'de>>(
+            deserializer: &mut dyn erased_serde::Deserializer<'_>,
+        ) -> Result<Value, erased_serde::Error> {
+            Ok(Arc::new(erased_serde::deserialize::<T>(deserializer)?))
+        }
+        fn add<T: Component + Clone>(
+            value: &(dyn Any + Send + Sync),
+            map_entities: Option<MapFn>,
+            map: &EntityMap,
+            builder: &mut EntityBuilder,
+        ) {
+            let mut x = value.downcast_ref::<T>().unwrap().clone();
+            if let Some(f) = map_entities {
+                unsafe {
+                    f((&mut x as *mut T).cast(), map);
+                }
+            }
+            builder.add(x);
+        }
+        fn resolves<T: Component + Clone>(
+            value: &(dyn Any + Send + Sync),
+            map_entities: MapFn,
+            map: &EntityMap,
+        ) -> bool {
+            let mut x = value.downcast_ref::<T>().unwrap().clone();
+            unsafe {
+                map_entities((&mut x as *mut T).cast(), map);
+            }
+            !map.take_unresolved()
+        }
+        Self {
+            deserialize: deserialize::<T>,
+            add: add::<T>,
+            resolves: resolves::<T>,
+        }
+    }
+}
+
+/// Reference to an entity in a `Scene`, for use in components loaded from scenes
+///
+/// Deserialized from the `id` of an entity in the scene. Until the scene is spawned this refers
+/// to a placeholder, which `MapEntities` replaces with the spawned entity, so components holding
+/// a `SceneEntity` must be registered with `Registration::map_entities`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct SceneEntity(Entity);
+
+impl SceneEntity {
+    /// Refer to `entity`
+    pub fn new(entity: Entity) -> Self {
+        Self(entity)
+    }
+
+    /// The entity referred to
+    pub fn entity(self) -> Entity {
+        self.0
+    }
+
+    /// The placeholder referred to by entities with the `id` `id` until a scene is spawned
+    fn placeholder(id: &str) -> Entity {
+        let mut hasher = DefaultHasher::new();
+        id.hash(&mut hasher);
+        Entity::from_bits(hasher.finish() | 1 << 63).unwrap()
+    }
+}
+
+impl From<SceneEntity> for Entity {
+    fn from(x: SceneEntity) -> Self {
+        x.0
+    }
+}
+
+impl MapEntities for SceneEntity {
+    fn map_entities(&mut self, map: &EntityMap) {
+        if let Some(x) = map.resolve(self.0) {
+            self.0 = x;
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for SceneEntity {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(IdVisitor)
+    }
+}
+
+struct IdVisitor;
+
+impl<'de> Visitor<'de> for IdVisitor {
+    type Value = SceneEntity;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the id of an entity in the scene")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<SceneEntity, E> {
+        Ok(SceneEntity(SceneEntity::placeholder(v)))
+    }
+}
+
+impl ComponentRegistry {
+    /// Load a scene of entities and prefabs, such as one authored by hand in RON or JSON
+    ///
+    /// A scene has two optional fields. `prefabs` maps names to entity templates, and `entities`
+    /// lists the entities to spawn. Both prefabs and entities may have the fields `prefab`, naming
+    /// a prefab whose components they inherit, and `components`, mapping the names of component
+    /// types registered with `Registration::scene` to their values. Components override any of the
+    /// same type they inherit.
+    ///
+    /// Entities may also have an `id`, a string by which components of other entities in the scene
+    /// refer to them through `SceneEntity`. Whenever the scene is spawned, these references are
+    /// replaced with the spawned entities. Components that refer to entities must be registered
+    /// with `Registration::map_entities`, and prefabs can't refer to entities, as they may be
+    /// spawned outside the scene. References to `id`s that no entity in the scene has are an
+    /// error.
+    ///
+    /// Components named in the scene but not registered with `Registration::scene` are ignored or
+    /// reported according to `unregistered`.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// let mut registry = ComponentRegistry::new();
+    /// registry.register::<i32>("health").scene();
+    /// registry.register::<String>("name").scene();
+    /// registry.register::<SceneEntity>("target").scene().map_entities();
+    ///
+    /// let json = r#"{
+    ///     "prefabs": {
+    ///         "goblin": { "components": { "health": 10, "name": "goblin" } }
+    ///     },
+    ///     "entities": [
+    ///         { "id": "boss", "prefab": "goblin", "components": { "health": 50 } },
+    ///         { "prefab": "goblin", "components": { "target": "boss" } }
+    ///     ]
+    /// }"#;
+    /// let mut de = serde_json::Deserializer::from_str(json);
+    /// let scene = registry.deserialize_scene(&mut de, Unregistered::Error).unwrap();
+    ///
+    /// let mut world = World::new();
+    /// let entities = scene.spawn(&mut world);
+    /// let boss = entities[scene.index_of("boss").unwrap()];
+    /// assert_eq!(*world.get::<i32>(boss).unwrap(), 50);
+    /// let (minion, &target) = world.query::<&SceneEntity>().iter().next().unwrap();
+    /// assert_eq!(target.entity(), boss);
+    /// assert_eq!(*world.get::<i32>(minion).unwrap(), 10);
+    ///
+    /// // Prefabs can also be spawned individually
+    /// let mut builder = EntityBuilder::new();
+    /// scene.prefab("goblin").unwrap().add_to(&mut builder);
+    /// let goblin = world.spawn(builder.build());
+    /// assert_eq!(*world.get::<String>(goblin).unwrap(), "goblin");
+    /// ```
+    pub fn deserialize_scene<'de, D: Deserializer<'de>>(
+        &self,
+        deserializer: D,
+        unregistered: Unregistered,
+    ) -> Result<Scene, D::Error> {
+        deserializer.deserialize_struct(
+            "Scene",
+            SCENE_FIELDS,
+            SceneVisitor {
+                registry: self,
+                unregistered,
+            },
+        )
+    }
+}
+
+/// Entities and prefabs loaded by `ComponentRegistry::deserialize_scene`
+#[derive(Clone, Default)]
+pub struct Scene {
+    prefabs: HashMap<String, Prefab>,
+    /// Each entity's components, and the placeholder `SceneEntity`s referring to it, if any
+    entities: Vec<(Option<Entity>, Prefab)>,
+    /// Index in `entities` of the entity with each `id`
+    ids: HashMap<String, usize>,
+}
+
+impl Scene {
+    /// The prefab named `name`, including any components it inherits
+    pub fn prefab(&self, name: &str) -> Option<&Prefab> {
+        self.prefabs.get(name)
+    }
+
+    /// Number of entities in the scene
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    /// Whether the scene contains no entities
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    /// Position of the entity with the `id` `id` among the scene's entities
+    pub fn index_of(&self, id: &str) -> Option<usize> {
+        self.ids.get(id).copied()
+    }
+
+    /// Spawn a new copy of every entity in the scene into `world`
+    ///
+    /// Returns the spawned entities in the order they're listed in the scene; see `index_of`.
+    pub fn spawn<A: Allocator>(&self, world: &mut World<A>) -> Vec<Entity> {
+        let entities = self
+            .entities
+            .iter()
+            .map(|_| world.reserve_entity())
+            .collect::<Vec<_>>();
+        let mut map = EntityMap::new();
+        for (&(placeholder, _), &entity) in self.entities.iter().zip(&entities) {
+            if let Some(placeholder) = placeholder {
+                map.insert(placeholder, entity);
+            }
+        }
+        let mut builder = EntityBuilder::new();
+        for ((_, prefab), &entity) in self.entities.iter().zip(&entities) {
+            prefab.add_mapped(&mut builder, &map);
+            world.insert(entity, builder.build()).unwrap();
+        }
+        entities
+    }
+}
+
+/// A set of components from which any number of entities can be built
+#[derive(Clone, Default)]
+pub struct Prefab {
+    components: Vec<PrefabComponent>,
+}
+
+impl Prefab {
+    /// Add copies of the prefab's components to `builder`
+    pub fn add_to(&self, builder: &mut EntityBuilder) {
+        self.add_mapped(builder, &EntityMap::new());
+    }
+
+    /// Add copies of the prefab's components to `builder`, replacing the placeholders for
+    /// entities they refer to with their counterparts in `map`
+    fn add_mapped(&self, builder: &mut EntityBuilder, map: &EntityMap) {
+        for x in &self.components {
+            (x.add)(&*x.value, x.map_entities, map, builder);
+        }
+    }
+
+    /// Whether `map` has a counterpart for every `SceneEntity` in the prefab's components
+    fn resolves(&self, map: &EntityMap) -> bool {
+        self.components.iter().all(|x| x.resolves(map))
+    }
+
+    /// Number of components in the prefab
+    pub fn len(&self) -> usize {
+        self.components.len()
+    }
+
+    /// Whether the prefab has no components
+    pub fn is_empty(&self) -> bool {
+        self.components.is_empty()
+    }
+
+    /// Add `component`, replacing any existing component of the same type
+    fn set(&mut self, component: PrefabComponent) {
+        match self.components.iter_mut().find(|x| x.id == component.id) {
+            Some(x) => *x = component,
+            None => self.components.push(component),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct PrefabComponent {
+    id: TypeId,
+    value: Value,
+    add: AddFn,
+    resolves: ResolvesFn,
+    map_entities: Option<MapFn>,
+}
+
+impl PrefabComponent {
+    fn resolves(&self, map: &EntityMap) -> bool {
+        match self.map_entities {
+            Some(f) => (self.resolves)(&*self.value, f, map),
+            None => true,
+        }
+    }
+}
+
+/// A prefab or entity as written, before inheritance is resolved
+struct Template {
+    id: Option<String>,
+    prefab: Option<String>,
+    components: Vec<PrefabComponent>,
+}
+
+impl Template {
+    /// Combine the template's components with those it inherits
+    fn resolve<E: de::Error>(
+        &self,
+        templates: &HashMap<String, Template>,
+        prefabs: &mut HashMap<String, Prefab>,
+        stack: &mut Vec<String>,
+    ) -> Result<Prefab, E> {
+        let mut prefab = match self.prefab {
+            None => Prefab::default(),
+            Some(ref name) => {
+                if let Some(x) = prefabs.get(name) {
+                    x.clone()
+                } else {
+                    if stack.contains(name) {
+                        return Err(de::Error::custom(format_args!(
+                            "prefab {} inherits from itself",
+                            name
+                        )));
+                    }
+                    let template = templates.get(name).ok_or_else(|| {
+                        de::Error::custom(format_args!("unknown prefab {}", name))
+                    })?;
+                    stack.push(name.clone());
+                    let x = template.resolve(templates, prefabs, stack)?;
+                    stack.pop();
+                    prefabs.insert(name.clone(), x.clone());
+                    x
+                }
+            }
+        };
+        for x in &self.components {
+            prefab.set(x.clone());
+        }
+        Ok(prefab)
+    }
+}
+
+const SCENE_FIELDS: &[&str] = &["prefabs", "entities"];
+const TEMPLATE_FIELDS: &[&str] = &["id", "prefab", "components"];
+
+struct SceneVisitor<'a> {
+    registry: &'a ComponentRegistry,
+    unregistered: Unregistered,
+}
+
+impl<'de> Visitor<'de> for SceneVisitor<'_> {
+    type Value = Scene;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a scene")
+    }
+
+    fn visit_map<M: MapAccess<'de>>(self, mut map: M) -> Result<Scene, M::Error> {
+        let mut templates = None;
+        let mut entities = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match &key[..] {
+                "prefabs" if templates.is_none() => {
+                    templates = Some(map.next_value_seed(TemplatesSeed {
+                        registry: self.registry,
+                        unregistered: self.unregistered,
+                    })?);
+                }
+                "entities" if entities.is_none() => {
+                    entities = Some(map.next_value_seed(EntitiesSeed {
+                        registry: self.registry,
+                        unregistered: self.unregistered,
+                    })?);
+                }
+                "prefabs" | "entities" => {
+                    return Err(de::Error::custom(format_args!("duplicate field `{}`", key)));
+                }
+                _ => return Err(de::Error::unknown_field(&key, SCENE_FIELDS)),
+            }
+        }
+        let templates = templates.unwrap_or_default();
+        let entities = entities.unwrap_or_default();
+
+        let mut prefabs = HashMap::new();
+        let mut stack = Vec::new();
+        for (name, template) in &templates {
+            if prefabs.contains_key(name) {
+                continue;
+            }
+            stack.push(name.clone());
+            let prefab = template.resolve(&templates, &mut prefabs, &mut stack)?;
+            stack.pop();
+            prefabs.insert(name.clone(), prefab);
+        }
+
+        let mut scene = Scene {
+            prefabs,
+            entities: Vec::with_capacity(entities.len()),
+            ids: HashMap::new(),
+        };
+        let mut placeholders = EntityMap::new();
+        for template in entities {
+            let placeholder = match template.id {
+                Some(ref id) => {
+                    if scene.ids.contains_key(id) {
+                        return Err(de::Error::custom(format_args!(
+                            "duplicate entity id {}",
+                            id
+                        )));
+                    }
+                    let placeholder = SceneEntity::placeholder(id);
+                    if placeholders.insert(placeholder, placeholder).is_some() {
+                        return Err(de::Error::custom(format_args!(
+                            "entity id {} collides with another",
+                            id
+                        )));
+                    }
+                    scene.ids.insert(id.clone(), scene.entities.len());
+                    Some(placeholder)
+                }
+                None => None,
+            };
+            let prefab = template.resolve(&templates, &mut scene.prefabs, &mut stack)?;
+            scene.entities.push((placeholder, prefab));
+        }
+        for (index, (_, prefab)) in scene.entities.iter().enumerate() {
+            if !prefab.resolves(&placeholders) {
+                return Err(de::Error::custom(format_args!(
+                    "entity {} refers to an unknown entity id",
+                    index
+                )));
+            }
+        }
+        Ok(scene)
+    }
+}
+
+/// Deserializes a map of named prefabs
+struct TemplatesSeed<'a> {
+    registry: &'a ComponentRegistry,
+    unregistered: Unregistered,
+}
+
+impl<'de> DeserializeSeed<'de> for TemplatesSeed<'_> {
+    type Value = HashMap<String, Template>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for TemplatesSeed<'_> {
+    type Value = HashMap<String, Template>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a map of prefab names to prefabs")
+    }
+
+    fn visit_map<M: MapAccess<'de>>(self, mut map: M) -> Result<Self::Value, M::Error> {
+        let mut templates = HashMap::new();
+        while let Some(name) = map.next_key::<String>()? {
+            let template = map.next_value_seed(TemplateSeed {
+                registry: self.registry,
+                unregistered: self.unregistered,
+                prefab: true,
+            })?;
+            if template.id.is_some() {
+                return Err(de::Error::custom("prefabs can't have an id"));
+            }
+            if templates.insert(name, template).is_some() {
+                return Err(de::Error::custom("duplicate prefab"));
+            }
+        }
+        Ok(templates)
+    }
+}
+
+/// Deserializes a sequence of entities
+struct EntitiesSeed<'a> {
+    registry: &'a ComponentRegistry,
+    unregistered: Unregistered,
+}
+
+impl<'de> DeserializeSeed<'de> for EntitiesSeed<'_> {
+    type Value = Vec<Template>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for EntitiesSeed<'_> {
+    type Value = Vec<Template>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a sequence of entities")
+    }
+
+    fn visit_seq<S: SeqAccess<'de>>(self, mut seq: S) -> Result<Self::Value, S::Error> {
+        let mut entities = Vec::new();
+        while let Some(x) = seq.next_element_seed(TemplateSeed {
+            registry: self.registry,
+            unregistered: self.unregistered,
+            prefab: false,
+        })? {
+            entities.push(x);
+        }
+        Ok(entities)
+    }
+}
+
+/// Deserializes a prefab or entity
+struct TemplateSeed<'a> {
+    registry: &'a ComponentRegistry,
+    unregistered: Unregistered,
+    prefab: bool,
+}
+
+impl<'de> DeserializeSeed<'de> for TemplateSeed<'_> {
+    type Value = Template;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Template, D::Error> {
+        deserializer.deserialize_struct("Entity", TEMPLATE_FIELDS, self)
+    }
+}
+
+impl<'de> Visitor<'de> for TemplateSeed<'_> {
+    type Value = Template;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("an entity")
+    }
+
+    fn visit_map<M: MapAccess<'de>>(self, mut map: M) -> Result<Template, M::Error> {
+        let mut id = None;
+        let mut prefab = None;
+        let mut components = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match &key[..] {
+                "id" if id.is_none() => id = Some(map.next_value::<String>()?),
+                "prefab" if prefab.is_none() => prefab = Some(map.next_value::<String>()?),
+                "components" if components.is_none() => {
+                    components = Some(map.next_value_seed(ComponentsSeed {
+                        registry: self.registry,
+                        unregistered: self.unregistered,
+                        prefab: self.prefab,
+                    })?);
+                }
+                "id" | "prefab" | "components" => {
+                    return Err(de::Error::custom(format_args!("duplicate field `{}`", key)));
+                }
+                _ => return Err(de::Error::unknown_field(&key, TEMPLATE_FIELDS)),
+            }
+        }
+        Ok(Template {
+            id,
+            prefab,
+            components: components.unwrap_or_default(),
+        })
+    }
+}
+
+/// Deserializes a map of named components
+struct ComponentsSeed<'a> {
+    registry: &'a ComponentRegistry,
+    unregistered: Unregistered,
+    /// Whether the components belong to a prefab, and hence can't refer to entities
+    prefab: bool,
+}
+
+impl<'de> DeserializeSeed<'de> for ComponentsSeed<'_> {
+    type Value = Vec<PrefabComponent>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ComponentsSeed<'_> {
+    type Value = Vec<PrefabComponent>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a map of component names to values")
+    }
+
+    fn visit_map<M: MapAccess<'de>>(self, mut map: M) -> Result<Self::Value, M::Error> {
+        let mut components = Vec::<PrefabComponent>::new();
+        while let Some(name) = map.next_key::<String>()? {
+            let info = match self.registry.get_by_name(&name) {
+                Some(info) if info.has_scene() => info,
+                _ => match self.unregistered {
+                    Unregistered::Skip => {
+                        map.next_value::<IgnoredAny>()?;
+                        continue;
+                    }
+                    Unregistered::Error => {
+                        return Err(de::Error::custom(format_args!(
+                            "unregistered component {}",
+                            name
+                        )));
+                    }
+                },
+            };
+            if components.iter().any(|x| x.id == info.id()) {
+                return Err(de::Error::custom(format_args!(
+                    "duplicate component {}",
+                    name
+                )));
+            }
+            let fns = info.scene.unwrap();
+            let component = PrefabComponent {
+                id: info.id(),
+                value: map.next_value_seed(ValueSeed(fns))?,
+                add: fns.add,
+                resolves: fns.resolves,
+                map_entities: info.map_entities,
+            };
+            if self.prefab && !component.resolves(&EntityMap::new()) {
+                return Err(de::Error::custom(format_args!(
+                    "component {} of a prefab refers to an entity",
+                    name
+                )));
+            }
+            components.push(component);
+        }
+        Ok(components)
+    }
+}
+
+/// Deserializes a single component
+struct ValueSeed(SceneFns);
+
+impl<'de> DeserializeSeed<'de> for ValueSeed {
+    type Value = Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        let mut deserializer = <dyn erased_serde::Deserializer>::erase(deserializer);
+        (self.0.deserialize)(&mut deserializer).map_err(de::Error::custom)
+    }
+}
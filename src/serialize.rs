@@ -0,0 +1,836 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::alloc::{boxed::Box, string::String, vec::Vec};
+use core::fmt;
+use core::mem::ManuallyDrop;
+
+use serde::de::{self, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, SerializeMap, SerializeSeq, SerializeTuple};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    Allocator, Archetype, Component, ComponentInfo, ComponentRegistry, Entity, EntityBuilder,
+    EntityRef, Global, Unregistered, World,
+};
+
+/// Type-erased serde implementations of a registered component type
+#[derive(Copy, Clone)]
+pub(crate) struct SerdeFns {
+    serialize: unsafe fn(*const u8) -> *const dyn erased_serde::Serialize,
+    deserialize: fn(ErasedDeserializer<'_, '_>, &mut EntityBuilder) -> Result<(), ErasedError>,
+    deserialize_column: fn(ErasedDeserializer<'_, '_>) -> Result<Box<dyn Column>, ErasedError>,
+}
+
+type ErasedDeserializer<'a, 'de> = &'a mut dyn erased_serde::Deserializer<'de>;
+type ErasedError = erased_serde::Error;
+
+impl SerdeFns {
+    pub(crate) fn of<T>() -> Self
+    where
+        T: Component + Serialize + for<'de> Deserialize<'de>,
+    {
+        unsafe fn serialize<T: Serialize + 'static>(
+            x: *const u8,
+        ) -> *const dyn erased_serde::Serialize {
+            x.cast::<T>()
+        }
+        fn deserialize<T: Component + for<'de> Deserialize<'de>>(
+            deserializer: &mut dyn erased_serde::Deserializer<'_>,
+            builder: &mut EntityBuilder,
+        ) -> Result<(), erased_serde::Error> {
+            builder.add(erased_serde::deserialize::<T>(deserializer)?);
+            Ok(())
+        }
+        fn deserialize_column<T: Component + for<'de> Deserialize<'de>>(
+            deserializer: &mut dyn erased_serde::Deserializer<'_>,
+        ) -> Result<Box<dyn Column>, erased_serde::Error> {
+            Ok(Box::new(erased_serde::deserialize::<Vec<T>>(deserializer)?))
+        }
+        Self {
+            serialize: serialize::<T>,
+            deserialize: deserialize::<T>,
+            deserialize_column: deserialize_column::<T>,
+        }
+    }
+}
+
+/// Deserialized components of a single type, awaiting storage in a world
+trait Column {
+    fn len(&self) -> usize;
+
+    /// Pass a pointer to each component to `f` in turn, relinquishing ownership of it
+    fn take(self: Box<Self>, f: &mut dyn FnMut(*mut u8));
+}
+
+impl<T: Component> Column for Vec<T> {
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn take(self: Box<Self>, f: &mut dyn FnMut(*mut u8)) {
+        for x in *self {
+            let mut x = ManuallyDrop::new(x);
+            f((&mut *x as *mut T).cast());
+        }
+    }
+}
+
+impl ComponentRegistry {
+    /// Serialize every entity in `world`, one at a time
+    ///
+    /// The world is written as a sequence of `(entity, components)` pairs, where `components` is a
+    /// map from each registered name to the component's value. Only component types registered
+    /// with `Registration::serde` are written; `unregistered` decides whether any others are
+    /// skipped or reported as an error.
+    ///
+    /// Serialization borrows each component, and hence fails with a panic if any is uniquely
+    /// borrowed elsewhere.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// let mut registry = ComponentRegistry::new();
+    /// registry.register::<i32>("health").serde();
+    /// registry.register::<bool>("hostile").serde();
+    ///
+    /// let mut world = World::new();
+    /// let e = world.spawn((10, true));
+    /// let json = serde_json::to_string(&registry.serialize_rows(&world, Unregistered::Error))
+    ///     .unwrap();
+    ///
+    /// let mut loaded = World::new();
+    /// let mut de = serde_json::Deserializer::from_str(&json);
+    /// registry
+    ///     .deserialize_rows(&mut de, &mut loaded, Unregistered::Error)
+    ///     .unwrap();
+    /// assert_eq!(*loaded.get::<i32>(e).unwrap(), 10);
+    /// assert!(*loaded.get::<bool>(e).unwrap());
+    /// ```
+    pub fn serialize_rows<'a, A: Allocator>(
+        &'a self,
+        world: &'a World<A>,
+        unregistered: Unregistered,
+    ) -> SerializeRows<'a, A> {
+        SerializeRows {
+            registry: self,
+            world,
+            unregistered,
+        }
+    }
+
+    /// Spawn the entities written by `serialize_rows` into `world`
+    ///
+    /// Entities are spawned with `World::spawn_at`, so they keep their original handles, and
+    /// replace any existing entities with the same IDs. Components named in the input but not
+    /// registered with `Registration::serde` are ignored or reported according to
+    /// `unregistered`. Entities loaded before an error is encountered are left in `world`.
+    pub fn deserialize_rows<'de, D, A>(
+        &self,
+        deserializer: D,
+        world: &mut World<A>,
+        unregistered: Unregistered,
+    ) -> Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+        A: Allocator,
+    {
+        deserializer.deserialize_seq(RowsVisitor {
+            registry: self,
+            world,
+            unregistered,
+        })
+    }
+
+    /// Serialize every entity in `world`, one archetype at a time
+    ///
+    /// Each archetype is written as a tuple of the names of its components, the entities in it,
+    /// and a sequence of columns, each holding one component per entity in the same order. This is
+    /// more compact and faster to load than `serialize_rows`. Components stored in sparse sets are
+    /// written likewise, with one record per set, after all archetypes.
+    ///
+    /// Only component types registered with `Registration::serde` are written; `unregistered`
+    /// decides whether any others are skipped or reported as an error. Serialization borrows each
+    /// column, and hence fails with a panic if any of its components is uniquely borrowed
+    /// elsewhere.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// let mut registry = ComponentRegistry::new();
+    /// registry.register::<i32>("health").serde();
+    /// registry.register::<bool>("hostile").serde();
+    ///
+    /// let mut world = World::new();
+    /// let entities = world.spawn_batch((0..10).map(|i| (i, i % 2 == 0))).collect::<Vec<_>>();
+    /// let json = serde_json::to_string(&registry.serialize_columns(&world, Unregistered::Error))
+    ///     .unwrap();
+    ///
+    /// let mut loaded = World::new();
+    /// let mut de = serde_json::Deserializer::from_str(&json);
+    /// registry
+    ///     .deserialize_columns(&mut de, &mut loaded, Unregistered::Error)
+    ///     .unwrap();
+    /// assert_eq!(*loaded.get::<i32>(entities[3]).unwrap(), 3);
+    /// assert!(*loaded.get::<bool>(entities[4]).unwrap());
+    /// ```
+    pub fn serialize_columns<'a, A: Allocator>(
+        &'a self,
+        world: &'a World<A>,
+        unregistered: Unregistered,
+    ) -> SerializeColumns<'a, A> {
+        SerializeColumns {
+            registry: self,
+            world,
+            unregistered,
+        }
+    }
+
+    /// Spawn the entities written by `serialize_columns` into `world`
+    ///
+    /// Each archetype is loaded in full before any of its entities are spawned, then moved into
+    /// `world` at once. Entities keep their original handles, and replace any existing entities
+    /// with the same IDs, as with `World::spawn_at`. Component types that were stored in sparse
+    /// sets must be registered as sparse in `world` beforehand, and vice versa.
+    ///
+    /// Components named in the input but not registered with `Registration::serde` are ignored or
+    /// reported according to `unregistered`. Archetypes loaded before an error is encountered are
+    /// left in `world`.
+    pub fn deserialize_columns<'de, D, A>(
+        &self,
+        deserializer: D,
+        world: &mut World<A>,
+        unregistered: Unregistered,
+    ) -> Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+        A: Allocator,
+    {
+        deserializer.deserialize_tuple(
+            2,
+            ColumnsVisitor {
+                registry: self,
+                world,
+                unregistered,
+            },
+        )
+    }
+}
+
+/// Helper for serializing an entire world; see `ComponentRegistry::serialize_rows`
+pub struct SerializeRows<'a, A: Allocator = Global> {
+    registry: &'a ComponentRegistry,
+    world: &'a World<A>,
+    unregistered: Unregistered,
+}
+
+impl<A: Allocator> Serialize for SerializeRows<'_, A> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let len = self.world.archetypes().map(|x| x.len() as usize).sum();
+        let mut seq = serializer.serialize_seq(Some(len))?;
+        for (entity, components) in self.world {
+            seq.serialize_element(&Row {
+                registry: self.registry,
+                entity,
+                components,
+                unregistered: self.unregistered,
+            })?;
+        }
+        seq.end()
+    }
+}
+
+struct Row<'a> {
+    registry: &'a ComponentRegistry,
+    entity: Entity,
+    components: EntityRef<'a>,
+    unregistered: Unregistered,
+}
+
+impl Serialize for Row<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tuple = serializer.serialize_tuple(2)?;
+        tuple.serialize_element(&self.entity)?;
+        tuple.serialize_element(&RowComponents {
+            registry: self.registry,
+            entity: self.entity,
+            components: self.components,
+            unregistered: self.unregistered,
+        })?;
+        tuple.end()
+    }
+}
+
+struct RowComponents<'a> {
+    registry: &'a ComponentRegistry,
+    entity: Entity,
+    components: EntityRef<'a>,
+    unregistered: Unregistered,
+}
+
+impl Serialize for RowComponents<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut len = 0;
+        for ty in self.components.component_infos() {
+            match self.registry.get(ty.id()) {
+                Some(info) if info.has_serde() => len += 1,
+                _ if self.unregistered == Unregistered::Error => {
+                    return Err(ser::Error::custom(format_args!(
+                        "entity {:?} has a component of unregistered type {}",
+                        self.entity,
+                        ty.type_name()
+                    )));
+                }
+                _ => {}
+            }
+        }
+        let mut map = serializer.serialize_map(Some(len))?;
+        self.registry.for_each(self.components, |info, ptr| {
+            let fns = match info.serde {
+                Some(x) => x,
+                None => return Ok(()),
+            };
+            map.serialize_entry(info.name(), unsafe { &*(fns.serialize)(ptr) })
+        })?;
+        map.end()
+    }
+}
+
+struct RowsVisitor<'a, A: Allocator> {
+    registry: &'a ComponentRegistry,
+    world: &'a mut World<A>,
+    unregistered: Unregistered,
+}
+
+impl<'de, A: Allocator> Visitor<'de> for RowsVisitor<'_, A> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a sequence of entities")
+    }
+
+    fn visit_seq<S: SeqAccess<'de>>(self, mut seq: S) -> Result<(), S::Error> {
+        let mut builder = EntityBuilder::new();
+        while let Some(entity) = seq.next_element_seed(RowSeed {
+            registry: self.registry,
+            builder: &mut builder,
+            unregistered: self.unregistered,
+        })? {
+            self.world.spawn_at(entity, builder.build());
+        }
+        Ok(())
+    }
+}
+
+/// Deserializes an entity's handle, accumulating its components in `builder`
+struct RowSeed<'a> {
+    registry: &'a ComponentRegistry,
+    builder: &'a mut EntityBuilder,
+    unregistered: Unregistered,
+}
+
+impl<'de> DeserializeSeed<'de> for RowSeed<'_> {
+    type Value = Entity;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Entity, D::Error> {
+        deserializer.deserialize_tuple(2, self)
+    }
+}
+
+impl<'de> Visitor<'de> for RowSeed<'_> {
+    type Value = Entity;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("an entity and its components")
+    }
+
+    fn visit_seq<S: SeqAccess<'de>>(self, mut seq: S) -> Result<Entity, S::Error> {
+        let entity = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &"an entity and its components"))?;
+        seq.next_element_seed(ComponentsSeed {
+            registry: self.registry,
+            builder: self.builder,
+            unregistered: self.unregistered,
+        })?
+        .ok_or_else(|| de::Error::invalid_length(1, &"an entity and its components"))?;
+        Ok(entity)
+    }
+}
+
+/// Deserializes a map of named components into `builder`
+struct ComponentsSeed<'a> {
+    registry: &'a ComponentRegistry,
+    builder: &'a mut EntityBuilder,
+    unregistered: Unregistered,
+}
+
+impl<'de> DeserializeSeed<'de> for ComponentsSeed<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ComponentsSeed<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a map of component names to values")
+    }
+
+    fn visit_map<M: MapAccess<'de>>(self, mut map: M) -> Result<(), M::Error> {
+        while let Some(name) = map.next_key::<String>()? {
+            match self.registry.get_by_name(&name).and_then(|info| info.serde) {
+                Some(fns) => map.next_value_seed(ComponentSeed {
+                    fns,
+                    builder: self.builder,
+                })?,
+                None => match self.unregistered {
+                    Unregistered::Skip => {
+                        map.next_value::<IgnoredAny>()?;
+                    }
+                    Unregistered::Error => {
+                        return Err(de::Error::custom(format_args!(
+                            "unregistered component {}",
+                            name
+                        )));
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Deserializes a single component into `builder`
+struct ComponentSeed<'a> {
+    fns: SerdeFns,
+    builder: &'a mut EntityBuilder,
+}
+
+impl<'de> DeserializeSeed<'de> for ComponentSeed<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        let mut deserializer = <dyn erased_serde::Deserializer>::erase(deserializer);
+        (self.fns.deserialize)(&mut deserializer, self.builder).map_err(de::Error::custom)
+    }
+}
+
+/// Helper for serializing an entire world; see `ComponentRegistry::serialize_columns`
+pub struct SerializeColumns<'a, A: Allocator = Global> {
+    registry: &'a ComponentRegistry,
+    world: &'a World<A>,
+    unregistered: Unregistered,
+}
+
+impl<A: Allocator> Serialize for SerializeColumns<'_, A> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tuple = serializer.serialize_tuple(2)?;
+        tuple.serialize_element(&Archetypes {
+            registry: self.registry,
+            world: self.world,
+            archetypes: || self.world.archetypes().filter(|x| x.len() != 0),
+            unregistered: self.unregistered,
+        })?;
+        tuple.serialize_element(&Archetypes {
+            registry: self.registry,
+            world: self.world,
+            // Sets of unregistered types hold no entities that must be written
+            archetypes: || {
+                self.world.sparse_storages().filter(move |x| {
+                    x.len() != 0
+                        && (self.unregistered == Unregistered::Error
+                            || self
+                                .registry
+                                .get(x.types()[0].id())
+                                .is_some_and(ComponentInfo::has_serde))
+                })
+            },
+            unregistered: self.unregistered,
+        })?;
+        tuple.end()
+    }
+}
+
+struct Archetypes<'a, A: Allocator, F> {
+    registry: &'a ComponentRegistry,
+    world: &'a World<A>,
+    archetypes: F,
+    unregistered: Unregistered,
+}
+
+impl<'a, A, F, I> Serialize for Archetypes<'a, A, F>
+where
+    A: Allocator,
+    F: Fn() -> I,
+    I: Iterator<Item = &'a Archetype>,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some((self.archetypes)().count()))?;
+        for archetype in (self.archetypes)() {
+            let mut columns = Vec::with_capacity(archetype.types().len());
+            for ty in archetype.types() {
+                match self.registry.get(ty.id()) {
+                    Some(info) if info.has_serde() => columns.push(info),
+                    _ if self.unregistered == Unregistered::Error => {
+                        return Err(ser::Error::custom(format_args!(
+                            "unregistered component type {}",
+                            ty.type_name()
+                        )));
+                    }
+                    _ => {}
+                }
+            }
+            seq.serialize_element(&ArchetypeRecord {
+                world: self.world,
+                archetype,
+                columns: &columns,
+            })?;
+        }
+        seq.end()
+    }
+}
+
+struct ArchetypeRecord<'a, A: Allocator> {
+    world: &'a World<A>,
+    archetype: &'a Archetype,
+    columns: &'a [&'a ComponentInfo],
+}
+
+impl<A: Allocator> Serialize for ArchetypeRecord<'_, A> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tuple = serializer.serialize_tuple(3)?;
+        tuple.serialize_element(&Names(self.columns))?;
+        tuple.serialize_element(&ArchetypeEntities {
+            world: self.world,
+            archetype: self.archetype,
+        })?;
+        tuple.serialize_element(&Columns {
+            archetype: self.archetype,
+            columns: self.columns,
+        })?;
+        tuple.end()
+    }
+}
+
+struct Names<'a>(&'a [&'a ComponentInfo]);
+
+impl Serialize for Names<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.iter().map(|x| x.name()))
+    }
+}
+
+struct ArchetypeEntities<'a, A: Allocator> {
+    world: &'a World<A>,
+    archetype: &'a Archetype,
+}
+
+impl<A: Allocator> Serialize for ArchetypeEntities<'_, A> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(
+            (0..self.archetype.len())
+                .map(|i| unsafe { self.world.find_entity_from_id(self.archetype.entity_id(i)) }),
+        )
+    }
+}
+
+struct Columns<'a> {
+    archetype: &'a Archetype,
+    columns: &'a [&'a ComponentInfo],
+}
+
+impl Serialize for Columns<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.columns.len()))?;
+        for &info in self.columns {
+            let _borrow = self.archetype.borrow_column(info.type_info().id());
+            seq.serialize_element(&ColumnValues {
+                archetype: self.archetype,
+                info,
+            })?;
+        }
+        seq.end()
+    }
+}
+
+/// A column of an archetype, which must be borrowed for the duration of serialization
+struct ColumnValues<'a> {
+    archetype: &'a Archetype,
+    info: &'a ComponentInfo,
+}
+
+impl Serialize for ColumnValues<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let ty = self.info.type_info();
+        let serialize = self.info.serde.unwrap().serialize;
+        let mut seq = serializer.serialize_seq(Some(self.archetype.len() as usize))?;
+        for i in 0..self.archetype.len() {
+            unsafe {
+                let ptr = self
+                    .archetype
+                    .get_dynamic(ty.id(), ty.layout().size(), i)
+                    .unwrap();
+                seq.serialize_element(&*serialize(ptr.as_ptr()))?;
+            }
+        }
+        seq.end()
+    }
+}
+
+struct ColumnsVisitor<'a, A: Allocator> {
+    registry: &'a ComponentRegistry,
+    world: &'a mut World<A>,
+    unregistered: Unregistered,
+}
+
+impl<'de, A: Allocator> Visitor<'de> for ColumnsVisitor<'_, A> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("archetypes followed by sparse sets")
+    }
+
+    fn visit_seq<S: SeqAccess<'de>>(self, mut seq: S) -> Result<(), S::Error> {
+        for (i, sparse) in [false, true].iter().copied().enumerate() {
+            seq.next_element_seed(ArchetypesSeed {
+                registry: self.registry,
+                world: &mut *self.world,
+                unregistered: self.unregistered,
+                sparse,
+            })?
+            .ok_or_else(|| de::Error::invalid_length(i, &self))?;
+        }
+        Ok(())
+    }
+}
+
+/// Deserializes a sequence of archetypes, or of sparse sets if `sparse` is set, into `world`
+struct ArchetypesSeed<'a, A: Allocator> {
+    registry: &'a ComponentRegistry,
+    world: &'a mut World<A>,
+    unregistered: Unregistered,
+    sparse: bool,
+}
+
+impl<'de, A: Allocator> DeserializeSeed<'de> for ArchetypesSeed<'_, A> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, A: Allocator> Visitor<'de> for ArchetypesSeed<'_, A> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a sequence of archetypes")
+    }
+
+    fn visit_seq<S: SeqAccess<'de>>(self, mut seq: S) -> Result<(), S::Error> {
+        while let Some(record) = seq.next_element_seed(ArchetypeSeed {
+            registry: self.registry,
+            unregistered: self.unregistered,
+        })? {
+            record.insert(self.world, self.sparse)?;
+        }
+        Ok(())
+    }
+}
+
+/// A deserialized archetype, or sparse set, ready to be inserted into a world
+struct Record<'a> {
+    entities: Vec<Entity>,
+    /// Components of registered types, each with its type's metadata
+    columns: Vec<(&'a ComponentInfo, Box<dyn Column>)>,
+}
+
+impl Record<'_> {
+    fn insert<A: Allocator, E: de::Error>(
+        self,
+        world: &mut World<A>,
+        sparse: bool,
+    ) -> Result<(), E> {
+        let entities = self.entities;
+        if sparse {
+            for (info, column) in self.columns {
+                let inserted =
+                    unsafe { world.insert_sparse_column(&entities, info.id(), |f| column.take(f)) };
+                if !inserted {
+                    return Err(de::Error::custom(format_args!(
+                        "{} must be registered as sparse, and its entities must exist",
+                        info.name()
+                    )));
+                }
+            }
+            return Ok(());
+        }
+
+        let mut columns = self.columns;
+        columns.sort_unstable_by_key(|x| x.0.type_info());
+        let types = columns.iter().map(|x| x.0.type_info()).collect();
+        unsafe {
+            world.spawn_columns_at(&entities, types, |archetype, first| {
+                for (info, column) in columns {
+                    let ty = info.type_info();
+                    let mut index = first;
+                    column.take(&mut |ptr| {
+                        archetype.put_dynamic(ptr, ty.id(), ty.layout().size(), index);
+                        index += 1;
+                    });
+                }
+            })
+        }
+        .map_err(|ty| {
+            de::Error::custom(format_args!(
+                "{} is registered as sparse, and must be loaded as such",
+                ty.type_name()
+            ))
+        })
+    }
+}
+
+/// Deserializes an archetype's component names, entities, and columns
+struct ArchetypeSeed<'a> {
+    registry: &'a ComponentRegistry,
+    unregistered: Unregistered,
+}
+
+impl<'de, 'a> DeserializeSeed<'de> for ArchetypeSeed<'a> {
+    type Value = Record<'a>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Record<'a>, D::Error> {
+        deserializer.deserialize_tuple(3, self)
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for ArchetypeSeed<'a> {
+    type Value = Record<'a>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("component names, entities, and columns")
+    }
+
+    fn visit_seq<S: SeqAccess<'de>>(self, mut seq: S) -> Result<Record<'a>, S::Error> {
+        let names = seq
+            .next_element::<Vec<String>>()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let mut infos = Vec::with_capacity(names.len());
+        for name in &names {
+            let info = self
+                .registry
+                .get_by_name(name)
+                .filter(|info| info.has_serde());
+            if info.is_none() && self.unregistered == Unregistered::Error {
+                return Err(de::Error::custom(format_args!(
+                    "unregistered component {}",
+                    name
+                )));
+            }
+            if let Some(info) = info {
+                if infos
+                    .iter()
+                    .flatten()
+                    .any(|x: &&ComponentInfo| x.id() == info.id())
+                {
+                    return Err(de::Error::custom(format_args!(
+                        "duplicate component {}",
+                        name
+                    )));
+                }
+            }
+            infos.push(info);
+        }
+
+        let entities = seq
+            .next_element::<Vec<Entity>>()?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        let mut ids = entities.iter().map(|x| x.id()).collect::<Vec<_>>();
+        ids.sort_unstable();
+        if ids.windows(2).any(|x| x[0] == x[1]) {
+            return Err(de::Error::custom("duplicate entity"));
+        }
+
+        let columns = seq
+            .next_element_seed(ColumnsSeed {
+                infos: &infos,
+                len: entities.len(),
+            })?
+            .ok_or_else(|| de::Error::invalid_length(2, &self))?;
+        Ok(Record { entities, columns })
+    }
+}
+
+/// Deserializes one column per entry of `infos`, keeping those of registered types
+struct ColumnsSeed<'a, 'b> {
+    infos: &'b [Option<&'a ComponentInfo>],
+    /// Number of components in each column
+    len: usize,
+}
+
+impl<'de, 'a> DeserializeSeed<'de> for ColumnsSeed<'a, '_> {
+    type Value = Vec<(&'a ComponentInfo, Box<dyn Column>)>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for ColumnsSeed<'a, '_> {
+    type Value = Vec<(&'a ComponentInfo, Box<dyn Column>)>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} columns", self.infos.len())
+    }
+
+    fn visit_seq<S: SeqAccess<'de>>(self, mut seq: S) -> Result<Self::Value, S::Error> {
+        let mut columns = Vec::with_capacity(self.infos.len());
+        for (i, &info) in self.infos.iter().enumerate() {
+            let column = match info {
+                Some(info) => seq.next_element_seed(ColumnSeed(info))?.map(Some),
+                None => seq.next_element::<IgnoredAny>()?.map(|_| None),
+            };
+            match column.ok_or_else(|| de::Error::invalid_length(i, &self))? {
+                Some(column) if column.len() != self.len => {
+                    return Err(de::Error::invalid_length(
+                        column.len(),
+                        &"a component for every entity",
+                    ));
+                }
+                Some(column) => columns.push((info.unwrap(), column)),
+                None => {}
+            }
+        }
+        if seq.next_element::<IgnoredAny>()?.is_some() {
+            return Err(de::Error::invalid_length(self.infos.len() + 1, &self));
+        }
+        Ok(columns)
+    }
+}
+
+/// Deserializes a column of components of a registered type
+struct ColumnSeed<'a>(&'a ComponentInfo);
+
+impl<'de> DeserializeSeed<'de> for ColumnSeed<'_> {
+    type Value = Box<dyn Column>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        let mut deserializer = <dyn erased_serde::Deserializer>::erase(deserializer);
+        (self.0.serde.unwrap().deserialize_column)(&mut deserializer).map_err(de::Error::custom)
+    }
+}
@@ -0,0 +1,380 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::alloc::string::{String, ToString};
+use crate::alloc::vec::Vec;
+use core::{fmt, mem, ptr, slice, str};
+
+#[cfg(feature = "std")]
+use std::error::Error;
+
+use crate::{Allocator, Archetype, ComponentInfo, ComponentRegistry, Entity, Unregistered, World};
+
+/// Identifies snapshots
+const MAGIC: [u8; 8] = *b"hecssnap";
+/// Incremented whenever the snapshot format changes
+const VERSION: u32 = 1;
+/// Written in native byte order, revealing the byte order of the machine that wrote a snapshot
+const BYTE_ORDER: u32 = 0x0102_0304;
+
+impl ComponentRegistry {
+    /// Copy every entity in `world` into a compact binary snapshot
+    ///
+    /// Only components of types registered with `Registration::pod` are included, each column of
+    /// each archetype being stored as raw bytes with its native layout, so that snapshots can be
+    /// written and loaded with bulk copies. `unregistered` decides whether components of other
+    /// types are skipped or reported as an error.
+    ///
+    /// A snapshot can only be loaded on a platform with the same byte order as the one it was
+    /// written on. Component data is aligned relative to the start of the snapshot,
+    /// so it can be accessed in place if the snapshot is loaded at a suitably aligned address,
+    /// such as by memory mapping.
+    ///
+    /// Copying borrows each column, and hence panics if any of its components is uniquely
+    /// borrowed elsewhere.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// #[derive(Copy, Clone, Debug, PartialEq)]
+    /// #[repr(C)]
+    /// struct Position([f32; 3]);
+    ///
+    /// let mut registry = ComponentRegistry::new();
+    /// // Safe because `Position` has no padding and any bits are a valid `Position`
+    /// unsafe {
+    ///     registry.register::<Position>("position").pod();
+    /// }
+    ///
+    /// let mut world = World::new();
+    /// let e = world.spawn((Position([1.0, 2.0, 3.0]),));
+    /// let snapshot = registry.snapshot(&world, Unregistered::Error).unwrap();
+    ///
+    /// let mut loaded = World::new();
+    /// registry
+    ///     .load_snapshot(&snapshot, &mut loaded, Unregistered::Error)
+    ///     .unwrap();
+    /// assert_eq!(*loaded.get::<Position>(e).unwrap(), Position([1.0, 2.0, 3.0]));
+    /// ```
+    pub fn snapshot<A: Allocator>(
+        &self,
+        world: &World<A>,
+        unregistered: Unregistered,
+    ) -> Result<Vec<u8>, SnapshotError> {
+        let mut out = Writer(Vec::new());
+        out.bytes(&MAGIC);
+        out.u32(VERSION);
+        out.u32(BYTE_ORDER);
+
+        let archetypes = world
+            .archetypes()
+            .filter(|x| x.len() != 0)
+            .collect::<Vec<_>>();
+        // Sets of unregistered types hold no entities that must be written
+        let sparse = world
+            .sparse_storages()
+            .filter(|x| {
+                x.len() != 0
+                    && (unregistered == Unregistered::Error
+                        || self
+                            .get(x.types()[0].id())
+                            .is_some_and(ComponentInfo::is_pod))
+            })
+            .collect::<Vec<_>>();
+        out.u32(archetypes.len() as u32);
+        out.u32(sparse.len() as u32);
+        for archetype in archetypes.into_iter().chain(sparse) {
+            self.write_archetype(world, archetype, unregistered, &mut out)?;
+        }
+        Ok(out.0)
+    }
+
+    /// Spawn the entities in a snapshot written by `snapshot` into `world`
+    ///
+    /// Each archetype's components are copied directly from `snapshot` into `world`'s storage,
+    /// with no per-component work. Entities keep their original handles, and replace any existing
+    /// entities with the same IDs, as with `World::spawn_at`. Component types that were stored in
+    /// sparse sets must be registered as sparse in `world` beforehand, and vice versa.
+    ///
+    /// Components named in the snapshot but not registered with `Registration::pod` are ignored
+    /// or reported according to `unregistered`. Archetypes loaded before an error is encountered
+    /// are left in `world`.
+    pub fn load_snapshot<A: Allocator>(
+        &self,
+        snapshot: &[u8],
+        world: &mut World<A>,
+        unregistered: Unregistered,
+    ) -> Result<(), SnapshotError> {
+        let mut input = Reader {
+            data: snapshot,
+            pos: 0,
+        };
+        if input.bytes(MAGIC.len())? != MAGIC
+            || input.u32()? != VERSION
+            || input.u32()? != BYTE_ORDER
+        {
+            return Err(SnapshotError::Incompatible);
+        }
+        let archetypes = input.u32()?;
+        let sparse = input.u32()?;
+        for i in 0..archetypes
+            .checked_add(sparse)
+            .ok_or(SnapshotError::Malformed)?
+        {
+            self.load_archetype(&mut input, world, unregistered, i >= archetypes)?;
+        }
+        Ok(())
+    }
+
+    fn write_archetype<A: Allocator>(
+        &self,
+        world: &World<A>,
+        archetype: &Archetype,
+        unregistered: Unregistered,
+        out: &mut Writer,
+    ) -> Result<(), SnapshotError> {
+        let mut columns = Vec::with_capacity(archetype.types().len());
+        for ty in archetype.types() {
+            match self.get(ty.id()) {
+                Some(info) if info.is_pod() => columns.push(info),
+                _ if unregistered == Unregistered::Error => {
+                    return Err(SnapshotError::Unregistered(ty.type_name().to_string()));
+                }
+                _ => {}
+            }
+        }
+
+        out.u32(archetype.len());
+        out.u32(columns.len() as u32);
+        for info in &columns {
+            let layout = info.type_info().layout();
+            out.u32(info.name().len() as u32);
+            out.bytes(info.name().as_bytes());
+            out.u32(layout.size() as u32);
+            out.u32(layout.align() as u32);
+        }
+
+        out.align(mem::align_of::<u64>());
+        for index in 0..archetype.len() {
+            let entity = unsafe { world.find_entity_from_id(archetype.entity_id(index)) };
+            out.bytes(&entity.to_bits().to_ne_bytes());
+        }
+
+        for info in &columns {
+            let ty = info.type_info();
+            let size = ty.layout().size();
+            out.align(ty.layout().align());
+            let _borrow = archetype.borrow_column(ty.id());
+            let mut index = 0;
+            while index < archetype.len() {
+                let run = archetype.contiguous_len(index);
+                unsafe {
+                    let base = archetype.get_dynamic(ty.id(), size, index).unwrap();
+                    out.bytes(slice::from_raw_parts(base.as_ptr(), size * run as usize));
+                }
+                index += run;
+            }
+        }
+        Ok(())
+    }
+
+    fn load_archetype<A: Allocator>(
+        &self,
+        input: &mut Reader<'_>,
+        world: &mut World<A>,
+        unregistered: Unregistered,
+        sparse: bool,
+    ) -> Result<(), SnapshotError> {
+        let len = input.u32()? as usize;
+        let column_count = input.u32()?;
+        let mut layouts = Vec::<(Option<&ComponentInfo>, usize, usize)>::new();
+        for _ in 0..column_count {
+            let name_len = input.u32()? as usize;
+            let name =
+                str::from_utf8(input.bytes(name_len)?).map_err(|_| SnapshotError::Malformed)?;
+            let size = input.u32()? as usize;
+            let align = input.u32()? as usize;
+            if !align.is_power_of_two() {
+                return Err(SnapshotError::Malformed);
+            }
+            let info = match self.get_by_name(name) {
+                Some(info) if info.is_pod() => {
+                    let layout = info.type_info().layout();
+                    if layout.size() != size || layout.align() != align {
+                        return Err(SnapshotError::LayoutMismatch(info.name()));
+                    }
+                    if layouts
+                        .iter()
+                        .any(|x| x.0.is_some_and(|x| x.id() == info.id()))
+                    {
+                        return Err(SnapshotError::Malformed);
+                    }
+                    Some(info)
+                }
+                _ if unregistered == Unregistered::Error => {
+                    return Err(SnapshotError::Unregistered(name.into()));
+                }
+                _ => None,
+            };
+            layouts.push((info, size, align));
+        }
+
+        input.align(mem::align_of::<u64>())?;
+        let mut entities = Vec::with_capacity(len.min(input.remaining()));
+        for _ in 0..len {
+            let mut bits = [0; 8];
+            bits.copy_from_slice(input.bytes(8)?);
+            let entity =
+                Entity::from_bits(u64::from_ne_bytes(bits)).ok_or(SnapshotError::Malformed)?;
+            entities.push(entity);
+        }
+        let mut ids = entities.iter().map(|x| x.id()).collect::<Vec<_>>();
+        ids.sort_unstable();
+        if ids.windows(2).any(|x| x[0] == x[1]) {
+            return Err(SnapshotError::Malformed);
+        }
+
+        let mut columns = Vec::with_capacity(layouts.len());
+        for (info, size, align) in layouts {
+            input.align(align)?;
+            let data = input.bytes(size.checked_mul(len).ok_or(SnapshotError::Malformed)?)?;
+            if let Some(info) = info {
+                columns.push((info, data));
+            }
+        }
+
+        if sparse {
+            for (info, data) in columns {
+                let size = info.type_info().layout().size();
+                // Components are copied out byte by byte, so needn't be aligned
+                let inserted = unsafe {
+                    world.insert_sparse_column(&entities, info.id(), |f| {
+                        for i in 0..len {
+                            f(data.as_ptr().add(i * size) as *mut u8);
+                        }
+                    })
+                };
+                if !inserted {
+                    return Err(SnapshotError::Sparse(info.name()));
+                }
+            }
+            return Ok(());
+        }
+
+        columns.sort_unstable_by_key(|x| x.0.type_info());
+        let types = columns.iter().map(|x| x.0.type_info()).collect();
+        unsafe {
+            world.spawn_columns_at(&entities, types, |archetype, first| {
+                for (info, data) in &columns {
+                    let ty = info.type_info();
+                    let size = ty.layout().size();
+                    let mut index = first;
+                    let mut data = &data[..];
+                    while !data.is_empty() {
+                        let run = archetype.contiguous_len(index);
+                        let bytes = size * run as usize;
+                        let base = archetype.get_dynamic(ty.id(), size, index).unwrap();
+                        ptr::copy_nonoverlapping(data.as_ptr(), base.as_ptr(), bytes);
+                        data = &data[bytes..];
+                        index += run;
+                    }
+                }
+            })
+        }
+        .map_err(|ty| SnapshotError::Sparse(self.get(ty.id()).unwrap().name()))
+    }
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn bytes(&mut self, x: &[u8]) {
+        self.0.extend_from_slice(x);
+    }
+
+    fn u32(&mut self, x: u32) {
+        self.bytes(&x.to_ne_bytes());
+    }
+
+    /// Pad with zeroes to a multiple of `align`
+    fn align(&mut self, align: usize) {
+        let len = (self.0.len() + align - 1) & !(align - 1);
+        self.0.resize(len, 0);
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        if len > self.remaining() {
+            return Err(SnapshotError::Malformed);
+        }
+        let x = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(x)
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotError> {
+        let mut x = [0; 4];
+        x.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_ne_bytes(x))
+    }
+
+    /// Skip padding up to a multiple of `align`
+    fn align(&mut self, align: usize) -> Result<(), SnapshotError> {
+        let padding = self.pos.wrapping_neg() & (align - 1);
+        self.bytes(padding).map(|_| ())
+    }
+}
+
+/// Errors that arise when writing or loading a snapshot
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SnapshotError {
+    /// The snapshot was written by an incompatible version of hecs, or on a platform with a
+    /// different byte order
+    Incompatible,
+    /// The snapshot is truncated or otherwise corrupt
+    Malformed,
+    /// A component's type wasn't registered with `Registration::pod`
+    Unregistered(String),
+    /// A component type's size or alignment differs from when the snapshot was written
+    LayoutMismatch(&'static str),
+    /// A component type is stored in a sparse set in the snapshot but not in the world, or vice
+    /// versa
+    Sparse(&'static str),
+}
+
+#[cfg(feature = "std")]
+impl Error for SnapshotError {}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use SnapshotError::*;
+        match *self {
+            Incompatible => f.write_str("snapshot is from an incompatible version or platform"),
+            Malformed => f.write_str("malformed snapshot"),
+            Unregistered(ref name) => write!(f, "unregistered component {}", name),
+            LayoutMismatch(name) => write!(f, "layout of component {} has changed", name),
+            Sparse(name) => write!(f, "sparse storage of component {} doesn't match", name),
+        }
+    }
+}
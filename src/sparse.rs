@@ -0,0 +1,202 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::alloc::{vec, vec::Vec};
+use core::any::TypeId;
+use core::ptr::NonNull;
+
+use crate::allocator::Allocator;
+use crate::archetype::{Archetype, TypeIdMap, TypeInfo};
+use crate::Component;
+
+/// Storage for component types that live outside of archetypes, keyed by entity ID
+///
+/// Adding or removing a sparse component never moves an entity between archetypes, making sparse
+/// storage well suited to components that are toggled frequently. The tradeoff is an extra
+/// indirection whenever they're accessed.
+#[doc(hidden)]
+#[derive(Default)]
+pub struct SparseSets {
+    sets: TypeIdMap<SparseSet>,
+}
+
+impl SparseSets {
+    pub(crate) fn register<T: Component>(&mut self) {
+        self.sets
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| SparseSet::new(TypeInfo::of::<T>()));
+    }
+
+    #[inline]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.sets.is_empty()
+    }
+
+    #[inline]
+    pub(crate) fn contains(&self, ty: TypeId) -> bool {
+        self.sets.contains_key(&ty)
+    }
+
+    pub(crate) fn get(&self, ty: TypeId) -> Option<&SparseSet> {
+        self.sets.get(&ty)
+    }
+
+    pub(crate) fn get_mut(&mut self, ty: TypeId) -> Option<&mut SparseSet> {
+        self.sets.get_mut(&ty)
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &'_ SparseSet> + '_ {
+        self.sets.values()
+    }
+
+    /// Drop every component belonging to the entity with ID `id`
+    pub(crate) fn remove_all(&mut self, id: u32) {
+        for set in self.sets.values_mut() {
+            set.remove(id);
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        for set in self.sets.values_mut() {
+            set.clear();
+        }
+    }
+
+    pub(crate) fn shrink_to_fit<A: Allocator>(&mut self, alloc: &A) {
+        for set in self.sets.values_mut() {
+            set.shrink_to_fit(alloc);
+        }
+    }
+
+    /// Drop every component and release all memory
+    pub(crate) fn free<A: Allocator>(&mut self, alloc: &A) {
+        for set in self.sets.values_mut() {
+            set.storage.free(alloc);
+        }
+    }
+}
+
+/// Densely packed components of a single type, indexed by entity ID
+pub(crate) struct SparseSet {
+    /// Archetype consisting of only this component type, which owns the components
+    storage: Archetype,
+    /// Row in `storage` of the component belonging to each entity ID, or `u32::MAX`
+    rows: Vec<u32>,
+}
+
+impl SparseSet {
+    fn new(ty: TypeInfo) -> Self {
+        Self {
+            storage: Archetype::new(vec![ty]),
+            rows: Vec::new(),
+        }
+    }
+
+    pub(crate) fn type_info(&self) -> TypeInfo {
+        self.storage.types()[0]
+    }
+
+    /// The single-column archetype holding the components
+    pub(crate) fn storage(&self) -> &Archetype {
+        &self.storage
+    }
+
+    /// Row within `storage` of the component belonging to the entity with ID `id`
+    #[inline]
+    pub(crate) fn row(&self, id: u32) -> Option<u32> {
+        match self.rows.get(id as usize) {
+            Some(&row) if row != u32::MAX => Some(row),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn rows(&self) -> &[u32] {
+        &self.rows
+    }
+
+    /// Pointer to the component belonging to the entity with ID `id`
+    pub(crate) fn get(&self, id: u32) -> Option<NonNull<u8>> {
+        let ty = self.type_info();
+        unsafe {
+            self.storage
+                .get_dynamic(ty.id(), ty.layout().size(), self.row(id)?)
+        }
+    }
+
+    /// Move the component at `component` into the set, dropping any it replaces
+    ///
+    /// # Safety
+    /// `component` must point to a valid instance of this set's component type, which must not be
+    /// used afterwards
+    pub(crate) unsafe fn insert<A: Allocator>(&mut self, alloc: &A, id: u32, component: *mut u8) {
+        let ty = self.type_info();
+        let row = match self.row(id) {
+            Some(row) => {
+                ty.drop(
+                    self.storage
+                        .get_dynamic(ty.id(), ty.layout().size(), row)
+                        .unwrap()
+                        .as_ptr(),
+                );
+                row
+            }
+            None => {
+                if self.rows.len() <= id as usize {
+                    self.rows.resize(id as usize + 1, u32::MAX);
+                }
+                let row = self.storage.allocate(alloc, id);
+                self.rows[id as usize] = row;
+                row
+            }
+        };
+        self.storage
+            .put_dynamic(component, ty.id(), ty.layout().size(), row);
+    }
+
+    /// Drop the component belonging to the entity with ID `id`, if any
+    pub(crate) fn remove(&mut self, id: u32) {
+        if let Some(row) = self.row(id) {
+            self.rows[id as usize] = u32::MAX;
+            if let Some(moved) = unsafe { self.storage.remove(row) } {
+                self.rows[moved as usize] = row;
+            }
+        }
+    }
+
+    /// Forget the component belonging to the entity with ID `id` without dropping it
+    ///
+    /// # Safety
+    /// The component must already have been moved out
+    pub(crate) unsafe fn forget(&mut self, id: u32) {
+        if let Some(row) = self.row(id) {
+            self.rows[id as usize] = u32::MAX;
+            if let Some(moved) = self.storage.move_to(row, |_, _, _| {}) {
+                self.rows[moved as usize] = row;
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.storage.clear();
+        self.rows.clear();
+    }
+
+    fn shrink_to_fit<A: Allocator>(&mut self, alloc: &A) {
+        self.storage.shrink_to_fit(alloc);
+        while self.rows.last() == Some(&u32::MAX) {
+            self.rows.pop();
+        }
+        self.rows.shrink_to_fit();
+    }
+}
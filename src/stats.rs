@@ -0,0 +1,207 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::alloc::vec::Vec;
+use core::mem;
+
+use crate::archetype::Archetype;
+use crate::entities::{Entities, EntityMeta};
+
+/// Breakdown of the memory used by a `World`; see `World::memory_stats`
+///
+/// Bytes are counted as "used" if they hold a live entity's components or metadata, and as
+/// "reserved" if they've been allocated, whether used or not. Bookkeeping such as the hash map
+/// from component types to archetypes isn't counted.
+#[derive(Debug, Clone)]
+pub struct MemoryStats {
+    archetypes: Vec<ArchetypeStats>,
+    sparse: Vec<ArchetypeStats>,
+    entities: EntityStats,
+}
+
+impl MemoryStats {
+    pub(crate) fn new<'a>(
+        archetypes: impl Iterator<Item = &'a Archetype>,
+        sparse: impl Iterator<Item = &'a Archetype>,
+        entities: &Entities,
+    ) -> Self {
+        Self {
+            archetypes: archetypes.map(ArchetypeStats::new).collect(),
+            sparse: sparse.map(ArchetypeStats::new).collect(),
+            entities: EntityStats::new(entities),
+        }
+    }
+
+    /// Statistics for each archetype, in the same order as `World::archetypes`
+    pub fn archetypes(&self) -> &[ArchetypeStats] {
+        &self.archetypes
+    }
+
+    /// Statistics for the storage of each component type registered with
+    /// `World::register_sparse`
+    pub fn sparse(&self) -> &[ArchetypeStats] {
+        &self.sparse
+    }
+
+    /// Statistics for the table of entity generations and locations
+    pub fn entities(&self) -> &EntityStats {
+        &self.entities
+    }
+
+    /// Total bytes holding live entities' components and metadata
+    pub fn bytes_used(&self) -> usize {
+        self.storage().map(|x| x.bytes_used).sum::<usize>() + self.entities.bytes_used
+    }
+
+    /// Total bytes allocated for components and entity metadata
+    pub fn bytes_reserved(&self) -> usize {
+        self.storage().map(|x| x.bytes_reserved).sum::<usize>() + self.entities.bytes_reserved
+    }
+
+    /// Number of archetypes holding fewer than `n` entities, including empty archetypes
+    ///
+    /// A large number of small archetypes slows down queries, which visit each archetype
+    /// separately, and wastes memory on partially filled allocations. Consider
+    /// `World::remove_empty_archetypes`, or storing frequently toggled components in sparse sets.
+    pub fn archetypes_smaller_than(&self, n: u32) -> usize {
+        self.archetypes.iter().filter(|x| x.len < n).count()
+    }
+
+    fn storage(&self) -> impl Iterator<Item = &ArchetypeStats> {
+        self.archetypes.iter().chain(self.sparse.iter())
+    }
+}
+
+/// Memory used by a single archetype, or by the storage of a sparse component type
+#[derive(Debug, Clone)]
+pub struct ArchetypeStats {
+    component_types: Vec<&'static str>,
+    len: u32,
+    capacity: u32,
+    bytes_used: usize,
+    bytes_reserved: usize,
+}
+
+impl ArchetypeStats {
+    fn new(archetype: &Archetype) -> Self {
+        let row_size = archetype
+            .types()
+            .iter()
+            .map(|ty| ty.layout().size())
+            .sum::<usize>()
+            + mem::size_of::<u32>();
+        Self {
+            component_types: archetype.types().iter().map(|ty| ty.type_name()).collect(),
+            len: archetype.len(),
+            capacity: archetype.capacity(),
+            bytes_used: archetype.len() as usize * row_size,
+            bytes_reserved: archetype.data_size()
+                + archetype.capacity() as usize * mem::size_of::<u32>(),
+        }
+    }
+
+    /// Names of the component types stored
+    pub fn component_types(&self) -> &[&'static str] {
+        &self.component_types
+    }
+
+    /// Number of entities stored
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    /// Whether no entities are stored
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of entities that can be stored without allocating
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    /// Bytes holding the components and IDs of stored entities
+    pub fn bytes_used(&self) -> usize {
+        self.bytes_used
+    }
+
+    /// Bytes allocated for components and entity IDs
+    pub fn bytes_reserved(&self) -> usize {
+        self.bytes_reserved
+    }
+}
+
+/// Memory used to track the generation and location of every entity ID
+#[derive(Debug, Clone)]
+pub struct EntityStats {
+    len: usize,
+    capacity: usize,
+    freelist_len: usize,
+    retired: usize,
+    bytes_used: usize,
+    bytes_reserved: usize,
+}
+
+impl EntityStats {
+    fn new(entities: &Entities) -> Self {
+        let pending = entities.pending_capacity();
+        let live = (0..entities.meta.len() as u32)
+            .filter(|&id| entities.live(id).is_some())
+            .count();
+        Self {
+            len: entities.meta.len(),
+            capacity: entities.meta.capacity(),
+            freelist_len: entities.freelist_len(),
+            retired: entities.retired(),
+            bytes_used: live * mem::size_of::<EntityMeta>(),
+            bytes_reserved: entities.meta.capacity() * mem::size_of::<EntityMeta>()
+                + pending * mem::size_of::<u32>(),
+        }
+    }
+
+    /// Number of entity IDs ever allocated, i.e. one past the largest ID
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether no entity IDs have been allocated
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of entity IDs that can be tracked without allocating
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of IDs of despawned entities that are available for reuse
+    pub fn freelist_len(&self) -> usize {
+        self.freelist_len
+    }
+
+    /// Number of IDs permanently withdrawn from use; see `World::retired_ids`
+    pub fn retired(&self) -> usize {
+        self.retired
+    }
+
+    /// Bytes holding metadata for live entities
+    pub fn bytes_used(&self) -> usize {
+        self.bytes_used
+    }
+
+    /// Bytes allocated for entity metadata and the freelist
+    pub fn bytes_reserved(&self) -> usize {
+        self.bytes_reserved
+    }
+}
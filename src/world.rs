@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::alloc::vec::Vec;
+use crate::alloc::{vec, vec::Vec};
 use core::any::TypeId;
 use core::convert::TryFrom;
 use core::{fmt, mem, ptr};
@@ -20,14 +20,23 @@ use core::{fmt, mem, ptr};
 #[cfg(feature = "std")]
 use std::error::Error;
 
-use hashbrown::{HashMap, HashSet};
+use hashbrown::HashMap;
 
 use crate::alloc::boxed::Box;
-use crate::archetype::Archetype;
-use crate::entities::{Entities, Location, ReserveEntitiesIterator};
+use crate::alloc::sync::Arc;
+use crate::archetype::{Archetype, TypeIdMap, TypeInfo};
+use crate::dynamic_query::{DynamicQuery, DynamicQueryBorrow};
+use crate::entities::{
+    Entities, IdRange, Location, ReserveEntitiesIterator, ReusePolicy, SharedEntities, WideEntity,
+};
+use crate::hierarchy::{self, Ancestors, Children, Descendants, Parent};
+use crate::query::Fetch;
+use crate::relation::{self, RelatedBy, RelationSources, RelationTargets, Relations};
+use crate::sparse::SparseSets;
+use crate::stats::MemoryStats;
 use crate::{
-    Bundle, DynamicBundle, Entity, EntityRef, MissingComponent, NoSuchEntity, Query, QueryBorrow,
-    QueryOne, Ref, RefMut,
+    Allocator, Bundle, DynamicBundle, Entity, EntityRef, Global, MissingComponent, NoSuchEntity,
+    Query, QueryBorrow, QueryOne, Ref, RefMut,
 };
 
 /// An unordered collection of entities, each having any number of distinctly typed components
@@ -37,29 +46,321 @@ use crate::{
 ///
 /// The components of entities who have the same set of component types are stored in contiguous
 /// runs, allowing for extremely fast, cache-friendly iteration.
-pub struct World {
+///
+/// Storage is obtained from the allocator `A`, which defaults to the global allocator; see
+/// `World::new_in`.
+pub struct World<A: Allocator = Global> {
     entities: Entities,
     index: HashMap<Box<[TypeId]>, u32>,
     archetypes: Vec<Archetype>,
     archetype_generation: u64,
+    /// Size in bytes of the chunks archetypes store components in, if chunked
+    chunk_size: Option<usize>,
+    /// Components stored outside of archetypes; see `register_sparse`
+    sparse: SparseSets,
+    /// Maintains relationships held by a component of the key type when its entity is despawned
+    unlink: TypeIdMap<UnlinkFn<A>>,
+    alloc: A,
 }
 
+type UnlinkFn<A> = fn(&mut World<A>, Entity);
+
 impl World {
     /// Create an empty world
     pub fn new() -> Self {
+        Self::new_in(Global)
+    }
+
+    /// Create an empty world that stores components in fixed-size chunks of `chunk_size` bytes
+    ///
+    /// By default, each archetype keeps its components in a single allocation, which is
+    /// reallocated and copied whenever it runs out of room. A chunked archetype instead grows by
+    /// allocating additional chunks, each holding every component of as many entities as fit, so
+    /// existing components never move as a result of spawning. The tradeoff is that queries
+    /// visit each chunk separately, and random access is slightly slower.
+    ///
+    /// Each chunk holds at least one entity, regardless of `chunk_size`.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// let mut world = World::with_chunk_size(16 * 1024);
+    /// let entities = world.spawn_batch((0..10_000).map(|i| (i, i as f32))).collect::<Vec<_>>();
+    /// assert_eq!(world.query::<(&i32, &f32)>().iter().count(), 10_000);
+    /// assert_eq!(*world.get::<i32>(entities[9_999]).unwrap(), 9_999);
+    /// ```
+    pub fn with_chunk_size(chunk_size: usize) -> Self {
+        Self::with_chunk_size_in(chunk_size, Global)
+    }
+}
+
+impl<A: Allocator> World<A> {
+    /// Create an empty world that obtains memory for its storage from `alloc`
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// # use core::{alloc::Layout, ptr::NonNull, sync::atomic::{AtomicUsize, Ordering}};
+    /// #[derive(Default)]
+    /// struct Counting(AtomicUsize);
+    ///
+    /// unsafe impl Allocator for Counting {
+    ///     fn allocate(&self, layout: Layout) -> Option<NonNull<u8>> {
+    ///         self.0.fetch_add(1, Ordering::Relaxed);
+    ///         Global.allocate(layout)
+    ///     }
+    ///
+    ///     unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+    ///         Global.deallocate(ptr, layout)
+    ///     }
+    /// }
+    ///
+    /// let mut world = World::new_in(Counting::default());
+    /// world.spawn((123, true));
+    /// assert!(world.allocator().0.load(Ordering::Relaxed) > 0);
+    /// ```
+    pub fn new_in(alloc: A) -> Self {
         // `flush` assumes archetype 0 always exists, representing entities with no components.
         let mut archetypes = Vec::new();
         archetypes.push(Archetype::new(Vec::new()));
         let mut index = HashMap::default();
         index.insert(Box::default(), 0);
+        let mut unlink = TypeIdMap::default();
+        unlink.insert(
+            TypeId::of::<Parent>(),
+            hierarchy::unlink_parent::<A> as UnlinkFn<A>,
+        );
+        unlink.insert(TypeId::of::<Children>(), hierarchy::unlink_children::<A>);
         Self {
-            entities: Entities::default(),
+            entities: Entities::new(),
             index,
             archetypes,
             archetype_generation: 0,
+            chunk_size: None,
+            sparse: SparseSets::default(),
+            unlink,
+            alloc,
+        }
+    }
+
+    /// Create an empty world that stores components in fixed-size chunks of `chunk_size` bytes
+    /// obtained from `alloc`
+    ///
+    /// See `World::with_chunk_size` and `World::new_in`.
+    pub fn with_chunk_size_in(chunk_size: usize, alloc: A) -> Self {
+        let mut world = Self::new_in(alloc);
+        world.chunk_size = Some(chunk_size);
+        world
+    }
+
+    /// The allocator this world obtains memory for its storage from
+    pub fn allocator(&self) -> &A {
+        &self.alloc
+    }
+
+    /// Construct an archetype for entities with exactly the components in `types`
+    fn new_archetype(&self, types: Vec<TypeInfo>) -> Archetype {
+        let linked = types.iter().any(|ty| self.unlink.contains_key(&ty.id()));
+        let mut archetype = match self.chunk_size {
+            Some(size) => Archetype::new_chunked(types, size),
+            None => Archetype::new(types),
+        };
+        if linked {
+            archetype.set_linked();
+        }
+        archetype
+    }
+
+    /// Run `unlink` whenever an entity with a component of type `ty` is despawned
+    fn add_unlink(&mut self, ty: TypeId, unlink: UnlinkFn<A>) {
+        if self.unlink.contains_key(&ty) {
+            return;
+        }
+        self.unlink.insert(ty, unlink);
+        for archetype in &mut self.archetypes {
+            if archetype.types().iter().any(|x| x.id() == ty) {
+                archetype.set_linked();
+            }
         }
     }
 
+    /// Store components of type `T` in a sparse set rather than in archetypes
+    ///
+    /// Sparse components are keyed by entity ID and don't contribute to an entity's archetype, so
+    /// adding or removing them never moves the entity's other components. This suits components
+    /// that are toggled frequently, such as markers, at the cost of slower iteration. They're
+    /// otherwise accessed through the usual methods, except that queries must use `Sparse<&T>`,
+    /// `Sparse<&mut T>`, `WithSparse`, or `WithoutSparse`: plain `&T` and `&mut T` queries match
+    /// no entities, and panic in debug builds.
+    ///
+    /// Has no effect if `T` is already registered. Panics if any archetype includes `T`, even one
+    /// whose entities have all since been despawned or moved; see `remove_empty_archetypes`.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// struct Stunned;
+    /// let mut world = World::new();
+    /// world.register_sparse::<Stunned>();
+    /// let e = world.spawn((123,));
+    /// let archetypes = world.archetypes().len();
+    /// world.insert_one(e, Stunned).unwrap();
+    /// assert!(world.get::<Stunned>(e).is_ok());
+    /// world.remove_one::<Stunned>(e).unwrap();
+    /// assert_eq!(world.archetypes().len(), archetypes);
+    /// ```
+    pub fn register_sparse<T: Component>(&mut self) {
+        assert!(
+            !self.archetypes.iter().any(|x| x.has::<T>()),
+            "{} is already stored in archetypes",
+            core::any::type_name::<T>()
+        );
+        self.sparse.register::<T>();
+    }
+
+    /// Find or create the archetype for entities with exactly the components in `info`, which
+    /// must be sorted
+    fn archetype_for(&mut self, info: Vec<TypeInfo>) -> u32 {
+        let elements = info.iter().map(|x| x.id()).collect::<Box<[TypeId]>>();
+        if let Some(&index) = self.index.get(&elements) {
+            return index;
+        }
+        let index = self.archetypes.len() as u32;
+        let archetype = self.new_archetype(info);
+        self.archetypes.push(archetype);
+        self.index.insert(elements, index);
+        self.archetype_generation += 1;
+        index
+    }
+
+    /// The types in `info` that are stored in archetypes, or `None` if no type is sparse
+    fn dense_types(&self, info: &[TypeInfo]) -> Option<Vec<TypeInfo>> {
+        if self.sparse.is_empty() || !info.iter().any(|x| self.sparse.contains(x.id())) {
+            return None;
+        }
+        Some(
+            info.iter()
+                .filter(|x| !self.sparse.contains(x.id()))
+                .copied()
+                .collect(),
+        )
+    }
+
+    /// Restrict the IDs used by `spawn` and `reserve_entities` to `ids`
+    ///
+    /// Combined with `add_id_range`, this partitions the ID space so that entities created in
+    /// different ranges can never collide, e.g. between entities a client predicts locally and
+    /// those assigned by a server. IDs of despawned entities are reused only by the range they
+    /// came from. Entity locations are stored in a table indexed by ID, so ranges should be kept
+    /// close to zero.
+    ///
+    /// Panics if any entities have been spawned or reserved in the default range, or if `ids` is
+    /// empty or overlaps a range added with `add_id_range`.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// let mut world = World::new();
+    /// world.set_id_range(0..10_000);
+    /// let predicted = world.add_id_range(10_000..20_000);
+    /// let a = world.spawn((123,));
+    /// let b = world.spawn_in(predicted, (456,));
+    /// assert!(a.id() < 10_000);
+    /// assert!(b.id() >= 10_000);
+    /// ```
+    pub fn set_id_range(&mut self, ids: core::ops::Range<u32>) {
+        self.entities.set_range(IdRange::DEFAULT, ids);
+    }
+
+    /// Change the order in which the IDs of despawned entities are reused
+    ///
+    /// The default, `ReusePolicy::Lifo`, reuses IDs as soon as possible. Delaying reuse with
+    /// `Fifo` or `Quarantine` makes it more likely that a bug involving a stale `Entity` handle
+    /// surfaces as a `NoSuchEntity` error rather than silently acting on an unrelated entity that
+    /// inherited its ID. Applies to every range, and to every world sharing this world's
+    /// `SharedEntities`.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// let mut world = World::new();
+    /// world.set_reuse_policy(ReusePolicy::Quarantine(2));
+    /// let a = world.spawn(());
+    /// world.despawn(a).unwrap();
+    /// assert_ne!(world.spawn(()).id(), a.id());
+    /// ```
+    pub fn set_reuse_policy(&mut self, policy: ReusePolicy) {
+        self.flush();
+        self.entities.set_policy(policy);
+    }
+
+    /// Let the generations of IDs wrap around when they saturate, rather than retiring the IDs
+    ///
+    /// IDs are then never withdrawn from use, at the cost of `Entity` handles becoming ambiguous:
+    /// after billions of despawns of entities sharing one ID, a stale `Entity` may alias a newer
+    /// entity. Handles that must remain unambiguous for that long should be kept as `WideEntity`s,
+    /// which effectively have 64-bit generations; see `wide_entity`. Applies to every world
+    /// sharing this world's `SharedEntities`.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// let mut world = World::new();
+    /// world.set_wide_generations(true);
+    /// let a = world.spawn((123,));
+    /// let wide = world.wide_entity(a).unwrap();
+    /// assert_eq!(world.resolve_wide(wide), Some(a));
+    /// world.despawn(a).unwrap();
+    /// assert_eq!(world.resolve_wide(wide), None);
+    /// ```
+    pub fn set_wide_generations(&mut self, wide: bool) {
+        self.entities.set_wide(wide);
+    }
+
+    /// Add a range of IDs to allocate entities from with `spawn_in` and `reserve_entities_in`
+    ///
+    /// The default range initially covers every ID, so it must be narrowed with `set_id_range`
+    /// first. Panics if `ids` is empty or overlaps another range.
+    pub fn add_id_range(&mut self, ids: core::ops::Range<u32>) -> IdRange {
+        self.entities.add_range(ids)
+    }
+
+    /// Allocate entity IDs from `entities`, which may be shared with other worlds
+    ///
+    /// Entities spawned or reserved in any world sharing `entities` can be passed to `spawn_at`
+    /// in the others, so the same handle identifies the same object everywhere. Each world still
+    /// stores only its own components and entity locations. ID ranges are configured on the shared
+    /// allocator, so `set_id_range` and `add_id_range` affect every world using it.
+    ///
+    /// Panics if any entities have been spawned in this world.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// # use std::sync::Arc;
+    /// let entities = Arc::new(SharedEntities::new());
+    /// let mut simulation = World::new();
+    /// simulation.share_entities(entities.clone());
+    /// let mut render = World::new();
+    /// render.share_entities(entities);
+    ///
+    /// let a = simulation.spawn((1.5f32,));
+    /// let b = simulation.reserve_entity();
+    /// render.spawn_at(a, ("sprite",));
+    /// render.spawn_at(b, ("mesh",));
+    /// assert_eq!(*render.get::<&str>(a).unwrap(), "sprite");
+    /// assert_ne!(render.spawn(("light",)), b);
+    /// ```
+    pub fn share_entities(&mut self, entities: Arc<SharedEntities>) {
+        self.flush();
+        self.entities.share(entities);
+    }
+
+    /// The entity ID allocator shared with other worlds, if any; see `share_entities`
+    pub fn shared_entities(&self) -> Option<&Arc<SharedEntities>> {
+        self.entities.shared()
+    }
+
     /// Create an entity with certain components
     ///
     /// Returns the ID of the newly created entity.
@@ -79,33 +380,157 @@ impl World {
     /// let b = world.spawn((456, true));
     /// ```
     pub fn spawn(&mut self, components: impl DynamicBundle) -> Entity {
+        self.spawn_in(IdRange::DEFAULT, components)
+    }
+
+    /// Create an entity with certain components, with an ID from `range`
+    ///
+    /// Panics if `range` has no IDs left. See `spawn` and `add_id_range`.
+    pub fn spawn_in(&mut self, range: IdRange, components: impl DynamicBundle) -> Entity {
         // Ensure all entity allocations are accounted for so `self.entities` can realloc if
         // necessary
         self.flush();
 
-        let entity = self.entities.alloc();
-        let archetype_id = components.with_ids(|ids| {
-            self.index.get(ids).copied().unwrap_or_else(|| {
-                let x = self.archetypes.len() as u32;
-                self.archetypes.push(Archetype::new(components.type_info()));
-                self.index.insert(ids.into(), x);
-                self.archetype_generation += 1;
-                x
+        let entity = self.entities.alloc_in(&self.alloc, range);
+        self.spawn_inner(entity, components);
+        entity
+    }
+
+    /// Create an entity with certain components and a specific `Entity` handle
+    ///
+    /// Despawns any existing entity with the same `Entity::id`. Useful for keeping handles intact
+    /// when deserializing, and for mirroring entities between worlds that share a
+    /// `SharedEntities`.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// let mut world = World::new();
+    /// let a = world.spawn((123,));
+    /// world.despawn(a).unwrap();
+    /// world.spawn_at(a, ("abc",));
+    /// assert_eq!(*world.get::<&str>(a).unwrap(), "abc");
+    /// assert_ne!(world.spawn((456,)), a);
+    /// ```
+    pub fn spawn_at(&mut self, handle: Entity, components: impl DynamicBundle) {
+        self.flush();
+        if let Some(existing) = self.entities.live(handle.id) {
+            // Keep the ID reserved so another world sharing it can't claim it in the meantime
+            self.despawn_with(existing, false).unwrap();
+        }
+        self.entities.alloc_at(&self.alloc, handle);
+        self.spawn_inner(handle, components);
+    }
+
+    /// Store the components of a newly allocated entity
+    fn spawn_inner(&mut self, entity: Entity, components: impl DynamicBundle) {
+        let has_sparse = !self.sparse.is_empty()
+            && components.with_ids(|ids| ids.iter().any(|&x| self.sparse.contains(x)));
+        let archetype_id = if has_sparse {
+            let info = self.dense_types(&components.type_info()).unwrap();
+            self.archetype_for(info)
+        } else {
+            components.with_ids(|ids| {
+                self.index.get(ids).copied().unwrap_or_else(|| {
+                    let x = self.archetypes.len() as u32;
+                    let archetype = self.new_archetype(components.type_info());
+                    self.archetypes.push(archetype);
+                    self.index.insert(ids.into(), x);
+                    self.archetype_generation += 1;
+                    x
+                })
             })
-        });
+        };
 
         let archetype = &mut self.archetypes[archetype_id as usize];
+        let sparse = &mut self.sparse;
+        let alloc = &self.alloc;
         unsafe {
-            let index = archetype.allocate(entity.id);
-            components.put(|ptr, ty| {
-                archetype.put_dynamic(ptr, ty.id(), ty.layout().size(), index);
+            let index = archetype.allocate(alloc, entity.id);
+            components.put(|ptr, ty| match sparse.get_mut(ty.id()) {
+                Some(set) => set.insert(alloc, entity.id, ptr),
+                None => archetype.put_dynamic(ptr, ty.id(), ty.layout().size(), index),
             });
             self.entities.meta[entity.id as usize].location = Location {
                 archetype: archetype_id,
                 index,
             };
         }
-        entity
+    }
+
+    /// Create entities with specific `Entity` handles and components of the same `types`,
+    /// despawning any existing entities with the same IDs as by `spawn_at`
+    ///
+    /// The entities occupy consecutive rows of their archetype, in the order of `handles`. `put`
+    /// is invoked with the archetype and the first of those rows, and must initialize every
+    /// component of every new entity. Fails, leaving the world untouched, with the first of
+    /// `types` that's registered as sparse.
+    ///
+    /// # Safety
+    /// `handles` must have distinct IDs, and `types` must be sorted and distinct
+    pub(crate) unsafe fn spawn_columns_at(
+        &mut self,
+        handles: &[Entity],
+        types: Vec<TypeInfo>,
+        put: impl FnOnce(&mut Archetype, u32),
+    ) -> Result<(), TypeInfo> {
+        if let Some(&ty) = types.iter().find(|x| self.sparse.contains(x.id())) {
+            return Err(ty);
+        }
+        self.flush();
+        for &handle in handles {
+            if let Some(existing) = self.entities.live(handle.id) {
+                self.despawn_with(existing, false).unwrap();
+            }
+        }
+        let archetype_id = self.archetype_for(types);
+        let archetype = &mut self.archetypes[archetype_id as usize];
+        archetype.reserve(
+            &self.alloc,
+            u32::try_from(handles.len()).expect("too many entities"),
+        );
+        let first = archetype.len();
+        for &handle in handles {
+            self.entities.alloc_at(&self.alloc, handle);
+            let index = archetype.allocate(&self.alloc, handle.id);
+            self.entities.meta[handle.id as usize].location = Location {
+                archetype: archetype_id,
+                index,
+            };
+        }
+        put(archetype, first);
+        Ok(())
+    }
+
+    /// Give each of the live entities `handles` a component of the sparse type `ty`, replacing
+    /// any it already has
+    ///
+    /// `put` is invoked with a function that must be passed a pointer to each new component in
+    /// the order of `handles`. Returns `false`, leaving the world untouched, if `ty` isn't
+    /// registered as sparse or any entity doesn't exist.
+    ///
+    /// # Safety
+    /// Each pointer passed to the function must point to a valid `ty` that's not used afterwards
+    pub(crate) unsafe fn insert_sparse_column(
+        &mut self,
+        handles: &[Entity],
+        ty: TypeId,
+        put: impl FnOnce(&mut dyn FnMut(*mut u8)),
+    ) -> bool {
+        self.flush();
+        if !self.sparse.contains(ty) || !handles.iter().all(|&x| self.entities.contains(x)) {
+            return false;
+        }
+        let set = self.sparse.get_mut(ty).unwrap();
+        let alloc = &self.alloc;
+        let mut handles = handles.iter();
+        put(&mut |ptr| set.insert(alloc, handles.next().unwrap().id, ptr));
+        true
+    }
+
+    /// Archetypes holding the components stored in sparse sets
+    pub(crate) fn sparse_storages(&self) -> impl Iterator<Item = &'_ Archetype> + '_ {
+        self.sparse.iter().map(|x| x.storage())
     }
 
     /// Efficiently spawn a large number of entities with the same components
@@ -121,7 +546,7 @@ impl World {
     ///     assert_eq!(*world.get::<i32>(entities[i]).unwrap(), i as i32);
     /// }
     /// ```
-    pub fn spawn_batch<I>(&mut self, iter: I) -> SpawnBatchIter<'_, I::IntoIter>
+    pub fn spawn_batch<I>(&mut self, iter: I) -> SpawnBatchIter<'_, I::IntoIter, A>
     where
         I: IntoIterator,
         I::Item: Bundle,
@@ -141,6 +566,8 @@ impl World {
             entities: &mut self.entities,
             archetype_id,
             archetype: &mut self.archetypes[archetype_id as usize],
+            sparse: &mut self.sparse,
+            alloc: &self.alloc,
         }
     }
 
@@ -165,16 +592,420 @@ impl World {
         self.entities.reserve_entity()
     }
 
+    /// Allocate many entity IDs from `range` concurrently
+    ///
+    /// See `reserve_entities` and `add_id_range`.
+    pub fn reserve_entities_in(&self, range: IdRange, count: u32) -> ReserveEntitiesIterator<'_> {
+        self.entities.reserve_entities_in(range, count)
+    }
+
+    /// Allocate an entity ID from `range` concurrently
+    ///
+    /// See `reserve_entities` and `add_id_range`.
+    pub fn reserve_entity_in(&self, range: IdRange) -> Entity {
+        self.entities.reserve_entity_in(range)
+    }
+
     /// Destroy an entity and all its components
+    ///
+    /// If the entity is part of a hierarchy, it's removed from its parent's `Children`, and its own
+    /// children lose their `Parent`. Use `despawn_recursive` to destroy the children as well.
     pub fn despawn(&mut self, entity: Entity) -> Result<(), NoSuchEntity> {
+        self.despawn_with(entity, true)
+    }
+
+    /// Destroy an entity, optionally allowing its ID to be reused
+    fn despawn_with(&mut self, entity: Entity, reuse_id: bool) -> Result<(), NoSuchEntity> {
         self.flush();
-        let loc = self.entities.free(entity)?;
+        let archetype = self.entities.get(entity)?.archetype as usize;
+        if self.archetypes[archetype].is_linked() {
+            // Unlinking may move other entities, and `entity` itself if it's related to itself, so
+            // the unlink functions for types no longer present simply find nothing to do
+            for i in 0..self.archetypes[archetype].types().len() {
+                let ty = self.archetypes[archetype].types()[i].id();
+                if let Some(&unlink) = self.unlink.get(&ty) {
+                    unlink(self, entity);
+                }
+            }
+        }
+        let loc = if reuse_id {
+            self.entities.free(entity)?
+        } else {
+            self.entities.free_keep_id(entity)?
+        };
+        self.sparse.remove_all(entity.id);
         if let Some(moved) = unsafe { self.archetypes[loc.archetype as usize].remove(loc.index) } {
             self.entities.meta[moved as usize].location.index = loc.index;
         }
         Ok(())
     }
 
+    /// Destroy every entity matching `Q`, returning how many were destroyed
+    ///
+    /// Equivalent to collecting the entities of `world.query::<Q>()` and despawning each one, but
+    /// far more efficient: each affected archetype is compacted in a single pass, and archetypes
+    /// matched in their entirety are simply emptied. Only `Q`'s filtering matters, so e.g. `&T`,
+    /// `&mut T`, and `With<T, ()>` are equivalent here.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// struct Dead;
+    /// let mut world = World::new();
+    /// let a = world.spawn((123, Dead));
+    /// let b = world.spawn((456,));
+    /// let c = world.spawn(("abc", Dead));
+    /// assert_eq!(world.despawn_matching::<&Dead>(), 2);
+    /// assert!(!world.contains(a));
+    /// assert!(world.contains(b));
+    /// assert!(!world.contains(c));
+    /// ```
+    pub fn despawn_matching<Q: Query>(&mut self) -> usize {
+        self.flush();
+        let masks = self.query_masks::<Q>();
+        self.despawn_masked(&masks)
+    }
+
+    /// For each archetype, either an empty `Vec` if no entities match `Q`, or whether each of its
+    /// entities matches
+    fn query_masks<Q: Query>(&self) -> Vec<Vec<bool>> {
+        let sparse = &self.sparse;
+        self.archetypes
+            .iter()
+            .map(|archetype| {
+                let mut mask = Vec::new();
+                if archetype.len() == 0 || Q::Fetch::access(archetype).is_none() {
+                    return mask;
+                }
+                mask.reserve(archetype.len() as usize);
+                for chunk in 0..archetype.chunk_count() {
+                    let fetch = Q::Fetch::new(archetype, chunk, sparse);
+                    for n in 0..archetype.chunk_len(chunk) as usize {
+                        mask.push(matches!(fetch, Some(ref x) if unsafe { x.matches(n) }));
+                    }
+                }
+                mask
+            })
+            .collect()
+    }
+
+    /// Destroy every entity for which `f` returns `false`
+    ///
+    /// Equivalent to despawning each rejected entity individually, but far more efficient: each
+    /// affected archetype is compacted in a single pass.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// let mut world = World::new();
+    /// let a = world.spawn((1,));
+    /// let b = world.spawn((2, true));
+    /// let c = world.spawn((3,));
+    /// world.retain(|_, entity| entity.get::<bool>().is_none());
+    /// assert!(world.contains(a));
+    /// assert!(!world.contains(b));
+    /// assert!(world.contains(c));
+    /// assert_eq!(*world.get::<i32>(c).unwrap(), 3);
+    /// ```
+    pub fn retain(&mut self, mut f: impl FnMut(Entity, EntityRef<'_>) -> bool) {
+        self.flush();
+        let entities = &self.entities;
+        let sparse = &self.sparse;
+        let masks = self
+            .archetypes
+            .iter()
+            .map(|archetype| {
+                let mut mask = Vec::with_capacity(archetype.len() as usize);
+                let mut any = false;
+                for index in 0..archetype.len() {
+                    let id = archetype.entity_id(index);
+                    let remove = unsafe {
+                        !f(
+                            entities.resolve_unknown_gen(id),
+                            EntityRef::new(archetype, index, sparse),
+                        )
+                    };
+                    any |= remove;
+                    mask.push(remove);
+                }
+                if !any {
+                    mask.clear();
+                }
+                mask
+            })
+            .collect::<Vec<_>>();
+        self.despawn_masked(&masks);
+    }
+
+    /// Destroy the entities selected by `masks`, which holds for each archetype either an empty
+    /// `Vec` or a flag for each of its entities
+    fn despawn_masked(&mut self, masks: &[Vec<bool>]) -> usize {
+        let mut count = 0;
+        // Unlinking relationships may move other entities, so those that have any are despawned
+        // individually once the rest are gone
+        let mut linked = Vec::new();
+        let entities = &mut self.entities;
+        for (archetype, mask) in self.archetypes.iter_mut().zip(masks) {
+            let first = match mask.iter().position(|&x| x) {
+                Some(x) => x as u32,
+                None => continue,
+            };
+            let selected = (first..archetype.len()).filter(|&index| mask[index as usize]);
+            if archetype.is_linked() {
+                linked.extend(selected.map(|index| unsafe {
+                    entities.resolve_unknown_gen(archetype.entity_id(index))
+                }));
+                continue;
+            }
+            for index in selected {
+                let id = archetype.entity_id(index);
+                let entity = unsafe { entities.resolve_unknown_gen(id) };
+                entities.free(entity).unwrap();
+                self.sparse.remove_all(id);
+                count += 1;
+            }
+            unsafe {
+                archetype.remove_rows(mask);
+            }
+            for index in first..archetype.len() {
+                let id = archetype.entity_id(index);
+                entities.meta[id as usize].location.index = index;
+            }
+        }
+        count += linked.len();
+        for entity in linked {
+            self.despawn(entity).unwrap();
+        }
+        count
+    }
+
+    /// Destroy an entity and all of its descendants
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// let mut world = World::new();
+    /// let root = world.spawn(());
+    /// let child = world.spawn(());
+    /// let grandchild = world.spawn(());
+    /// world.set_parent(child, root).unwrap();
+    /// world.set_parent(grandchild, child).unwrap();
+    /// world.despawn_recursive(child).unwrap();
+    /// assert!(world.contains(root));
+    /// assert!(!world.contains(child));
+    /// assert!(!world.contains(grandchild));
+    /// assert!(world.get::<Children>(root).is_err());
+    /// ```
+    pub fn despawn_recursive(&mut self, entity: Entity) -> Result<(), NoSuchEntity> {
+        self.flush();
+        self.entities.get(entity)?;
+        let descendants = self.descendants(entity).collect::<Vec<_>>();
+        // Descendants follow their ancestors in pre-order, so this despawns leaves first
+        for x in descendants.into_iter().rev() {
+            self.despawn(x)?;
+        }
+        self.despawn(entity)
+    }
+
+    /// Make `parent` the parent of `child` in the entity hierarchy
+    ///
+    /// Adds a `Parent` component to `child` and appends `child` to the `Children` of `parent`,
+    /// first detaching `child` from its previous parent, if any.
+    ///
+    /// Panics if `child` is `parent` or one of its ancestors, since that would form a cycle.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// let mut world = World::new();
+    /// let parent = world.spawn(());
+    /// let child = world.spawn((123,));
+    /// world.set_parent(child, parent).unwrap();
+    /// assert_eq!(world.get::<Parent>(child).unwrap().get(), parent);
+    /// assert_eq!(&**world.get::<Children>(parent).unwrap(), &[child]);
+    /// ```
+    pub fn set_parent(&mut self, child: Entity, parent: Entity) -> Result<(), NoSuchEntity> {
+        self.flush();
+        self.entities.get(child)?;
+        self.entities.get(parent)?;
+        assert!(
+            child != parent && !self.ancestors(parent).any(|x| x == child),
+            "an entity cannot be its own ancestor"
+        );
+        if let Ok(old) = self.get::<Parent>(child).map(|x| x.get()) {
+            if old == parent {
+                return Ok(());
+            }
+            hierarchy::remove_child(self, old, child);
+        }
+        self.insert_one(child, Parent(parent))?;
+        let added = if let Ok(mut children) = self.get_mut::<Children>(parent) {
+            children.0.push(child);
+            true
+        } else {
+            false
+        };
+        if !added {
+            self.insert_one(parent, Children(vec![child]))?;
+        }
+        Ok(())
+    }
+
+    /// Detach `child` from its parent in the entity hierarchy
+    ///
+    /// Returns the former parent, if any.
+    pub fn remove_parent(&mut self, child: Entity) -> Result<Option<Entity>, NoSuchEntity> {
+        match self.remove_one::<Parent>(child) {
+            Ok(Parent(parent)) => {
+                hierarchy::remove_child(self, parent, child);
+                Ok(Some(parent))
+            }
+            Err(ComponentError::NoSuchEntity) => Err(NoSuchEntity),
+            Err(ComponentError::MissingComponent(_)) => Ok(None),
+        }
+    }
+
+    /// Iterate over the descendants of `entity` in depth-first pre-order
+    ///
+    /// `entity` itself is not included.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// let mut world = World::new();
+    /// let root = world.spawn(());
+    /// let a = world.spawn(());
+    /// let b = world.spawn(());
+    /// let c = world.spawn(());
+    /// world.set_parent(a, root).unwrap();
+    /// world.set_parent(b, a).unwrap();
+    /// world.set_parent(c, root).unwrap();
+    /// assert_eq!(world.descendants(root).collect::<Vec<_>>(), &[a, b, c]);
+    /// assert_eq!(world.ancestors(b).collect::<Vec<_>>(), &[a, root]);
+    /// ```
+    pub fn descendants(&self, entity: Entity) -> Descendants<'_, A> {
+        Descendants::new(self, entity)
+    }
+
+    /// Iterate over the ancestors of `entity`, starting with its parent
+    pub fn ancestors(&self, entity: Entity) -> Ancestors<'_, A> {
+        Ancestors::new(self, entity)
+    }
+
+    /// Relate `source` to `target` by a relationship of kind `R`
+    ///
+    /// An entity may hold any number of relationships of the same kind, so long as their targets
+    /// are distinct. If `source` is already related to `target` by `R`, the value of that
+    /// relationship is replaced. Relationships are stored in a `Relations<R>` component on
+    /// `source`, mirrored by a `RelatedBy<R>` component on `target`, and are removed automatically
+    /// when either entity is despawned.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// struct Owns { since: u32 }
+    ///
+    /// let mut world = World::new();
+    /// let alice = world.spawn(("alice",));
+    /// let sword = world.spawn(("sword",));
+    /// let shield = world.spawn(("shield",));
+    /// world.relate(alice, sword, Owns { since: 1 }).unwrap();
+    /// world.relate(alice, shield, Owns { since: 3 }).unwrap();
+    /// assert_eq!(world.relation_targets::<Owns>(alice).collect::<Vec<_>>(), &[sword, shield]);
+    /// assert_eq!(world.relation_sources::<Owns>(sword).collect::<Vec<_>>(), &[alice]);
+    ///
+    /// // Despawning a target removes relationships to it
+    /// world.despawn(sword).unwrap();
+    /// assert_eq!(world.relation_targets::<Owns>(alice).collect::<Vec<_>>(), &[shield]);
+    ///
+    /// // Wildcard queries visit every relationship of a kind
+    /// for (owner, owns) in world.query::<&Relations<Owns>>().iter() {
+    ///     for (item, value) in owns.iter() {
+    ///         assert_eq!((owner, item, value.since), (alice, shield, 3));
+    ///     }
+    /// }
+    /// ```
+    pub fn relate<R: Component>(
+        &mut self,
+        source: Entity,
+        target: Entity,
+        relation: R,
+    ) -> Result<(), NoSuchEntity> {
+        self.flush();
+        self.entities.get(source)?;
+        self.entities.get(target)?;
+        self.add_unlink(
+            TypeId::of::<Relations<R>>(),
+            relation::unlink_relations::<R, A>,
+        );
+        self.add_unlink(
+            TypeId::of::<RelatedBy<R>>(),
+            relation::unlink_related_by::<R, A>,
+        );
+
+        let mut relation = Some(relation);
+        if let Ok(mut relations) = self.get_mut::<Relations<R>>(source) {
+            let relation = relation.take().unwrap();
+            if let Some(x) = relations.pairs.iter_mut().find(|x| x.0 == target) {
+                x.1 = relation;
+                return Ok(());
+            }
+            relations.pairs.push((target, relation));
+        }
+        if let Some(relation) = relation {
+            self.insert_one(
+                source,
+                Relations {
+                    pairs: vec![(target, relation)],
+                },
+            )?;
+        }
+
+        let added = if let Ok(mut related) = self.get_mut::<RelatedBy<R>>(target) {
+            related.sources.push(source);
+            true
+        } else {
+            false
+        };
+        if !added {
+            self.insert_one(target, RelatedBy::<R>::new(source))?;
+        }
+        Ok(())
+    }
+
+    /// Remove the relationship of kind `R` from `source` to `target`
+    ///
+    /// Returns the value of the removed relationship, if it existed.
+    pub fn unrelate<R: Component>(
+        &mut self,
+        source: Entity,
+        target: Entity,
+    ) -> Result<Option<R>, NoSuchEntity> {
+        self.flush();
+        self.entities.get(source)?;
+        let value = relation::remove_target::<R, A>(self, source, target);
+        if value.is_some() {
+            relation::remove_source::<R, A>(self, target, source);
+        }
+        Ok(value)
+    }
+
+    /// Iterate over the targets of every relationship of kind `R` held by `source`
+    ///
+    /// Yields nothing if `source` doesn't exist. Panics if the `Relations<R>` component of
+    /// `source` is uniquely borrowed.
+    pub fn relation_targets<R: Component>(&self, source: Entity) -> RelationTargets<'_, R> {
+        RelationTargets::new(self.get::<Relations<R>>(source).ok())
+    }
+
+    /// Iterate over every entity that has a relationship of kind `R` targeting `target`
+    ///
+    /// Yields nothing if `target` doesn't exist. Panics if the `RelatedBy<R>` component of
+    /// `target` is uniquely borrowed.
+    pub fn relation_sources<R: Component>(&self, target: Entity) -> RelationSources<'_, R> {
+        RelationSources::new(self.get::<RelatedBy<R>>(target).ok())
+    }
+
     /// Ensure `additional` entities with exact components `T` can be spawned without reallocating
     pub fn reserve<T: Bundle>(&mut self, additional: u32) {
         self.reserve_inner::<T>(additional);
@@ -182,19 +1013,25 @@ impl World {
 
     fn reserve_inner<T: Bundle>(&mut self, additional: u32) -> u32 {
         self.flush();
-        self.entities.reserve(additional);
+        self.entities.reserve(&self.alloc, additional);
 
+        if let Some(info) = self.dense_types(&T::static_type_info()) {
+            let archetype_id = self.archetype_for(info);
+            self.archetypes[archetype_id as usize].reserve(&self.alloc, additional);
+            return archetype_id;
+        }
         let archetype_id = T::with_static_ids(|ids| {
             self.index.get(ids).copied().unwrap_or_else(|| {
                 let x = self.archetypes.len() as u32;
-                self.archetypes.push(Archetype::new(T::static_type_info()));
+                let archetype = self.new_archetype(T::static_type_info());
+                self.archetypes.push(archetype);
                 self.index.insert(ids.into(), x);
                 self.archetype_generation += 1;
                 x
             })
         });
 
-        self.archetypes[archetype_id as usize].reserve(additional);
+        self.archetypes[archetype_id as usize].reserve(&self.alloc, additional);
         archetype_id
     }
 
@@ -205,14 +1042,205 @@ impl World {
         for x in &mut self.archetypes {
             x.clear();
         }
+        self.sparse.clear();
         self.entities.clear();
     }
 
+    /// Release memory that isn't needed to store the entities currently in the world
+    ///
+    /// Storage is never shrunk automatically, so a world that briefly held many entities keeps
+    /// enough memory for all of them until this is called, e.g. after `clear` or after despawning
+    /// a large batch. Empty archetypes are shrunk but kept; see `remove_empty_archetypes`.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// let mut world = World::new();
+    /// world.spawn_batch((0..1000).map(|i| (i,)));
+    /// world.clear();
+    /// world.shrink_to_fit();
+    /// world.spawn((123,));
+    /// ```
+    pub fn shrink_to_fit(&mut self) {
+        for x in &mut self.archetypes {
+            x.shrink_to_fit(&self.alloc);
+        }
+        self.sparse.shrink_to_fit(&self.alloc);
+        self.entities.shrink_to_fit(&self.alloc);
+        self.index.shrink_to_fit();
+    }
+
+    /// Reorder the entities of every archetype containing `T` by the key `f` extracts from their
+    /// `T`
+    ///
+    /// Components are physically moved, so subsequent queries visit the entities of each such
+    /// archetype in ascending order of key until entities are next spawned, despawned, or moved
+    /// between archetypes. The order of different archetypes relative to each other is
+    /// unaffected. The sort is stable. Has no effect on sparse components.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// let mut world = World::new();
+    /// world.spawn_batch([3, 1, 2].iter().map(|&depth| (depth, "mesh")));
+    /// world.sort_by_key(|&depth: &i32| depth);
+    /// let order = world.query::<&i32>().iter().map(|(_, &x)| x).collect::<Vec<_>>();
+    /// assert_eq!(order, [1, 2, 3]);
+    /// ```
+    pub fn sort_by_key<T: Component, K: Ord>(&mut self, mut f: impl FnMut(&T) -> K) {
+        let mut order = Vec::new();
+        for archetype in &mut self.archetypes {
+            if !archetype.has::<T>() {
+                continue;
+            }
+            let keys = (0..archetype.len())
+                .map(|index| unsafe { f(archetype.get_at::<T>(index).unwrap().as_ref()) })
+                .collect::<Vec<_>>();
+            order.clear();
+            order.extend(0..archetype.len());
+            order.sort_by(|&a, &b| keys[a as usize].cmp(&keys[b as usize]));
+            if order
+                .iter()
+                .enumerate()
+                .all(|(new, &old)| new as u32 == old)
+            {
+                continue;
+            }
+            archetype.permute(&order);
+            for index in 0..archetype.len() {
+                let id = archetype.entity_id(index);
+                self.entities.meta[id as usize].location.index = index;
+            }
+        }
+    }
+
+    /// Summarize how much memory the world's storage occupies, and how much of it is in use
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// let mut world = World::new();
+    /// let entities = world.spawn_batch((0..100).map(|i| (i, true))).collect::<Vec<_>>();
+    /// for &e in &entities[10..] {
+    ///     world.despawn(e).unwrap();
+    /// }
+    /// let stats = world.memory_stats();
+    /// assert!(stats.bytes_used() < stats.bytes_reserved());
+    /// assert_eq!(stats.entities().freelist_len(), 90);
+    /// let archetype = stats.archetypes().iter().find(|x| x.len() == 10).unwrap();
+    /// assert!(archetype.capacity() >= 100);
+    /// assert_eq!(archetype.component_types().len(), 2);
+    /// ```
+    pub fn memory_stats(&self) -> MemoryStats {
+        MemoryStats::new(
+            self.archetypes.iter(),
+            self.sparse.iter().map(|x| x.storage()),
+            &self.entities,
+        )
+    }
+
+    /// Number of entity IDs permanently withdrawn from use
+    ///
+    /// Each time an entity is despawned, its ID's generation is incremented so that stale `Entity`
+    /// handles can be detected. Once the generation can't be incremented further, the ID is
+    /// retired rather than risk a stale handle aliasing a new entity. This only happens after
+    /// billions of despawns of entities sharing one ID, and costs only that one ID's slot, or never
+    /// with `set_wide_generations`. Includes IDs retired by other worlds sharing the same
+    /// `SharedEntities`.
+    pub fn retired_ids(&self) -> usize {
+        self.entities.retired()
+    }
+
+    /// Drop every archetype that contains no entities, returning how many were removed
+    ///
+    /// An archetype is created for each distinct combination of component types an entity has
+    /// ever had, and is otherwise kept forever. Every query visits every archetype, so a world
+    /// that has accumulated many unused archetypes can be slower to query than necessary. They
+    /// will be recreated as needed.
+    ///
+    /// Removing archetypes changes `archetypes_generation`, invalidating any information derived
+    /// from `archetypes`.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// let mut world = World::new();
+    /// let a = world.spawn((123, true));
+    /// world.spawn((456,));
+    /// let count = world.archetypes().len();
+    /// world.remove_one::<bool>(a).unwrap();
+    /// let generation = world.archetypes_generation();
+    /// assert_eq!(world.remove_empty_archetypes(), 1);
+    /// assert_eq!(world.archetypes().len(), count - 1);
+    /// assert_ne!(world.archetypes_generation(), generation);
+    /// assert_eq!(*world.get::<i32>(a).unwrap(), 123);
+    /// ```
+    pub fn remove_empty_archetypes(&mut self) -> usize {
+        // Archetype 0 represents entities with no components, and must always exist
+        let mut remap = Vec::with_capacity(self.archetypes.len());
+        let mut count = 0;
+        for (i, x) in self.archetypes.iter().enumerate() {
+            if i == 0 || x.len() != 0 {
+                remap.push(count);
+                count += 1;
+            } else {
+                remap.push(u32::MAX);
+            }
+        }
+        let removed = self.archetypes.len() - count as usize;
+        if removed == 0 {
+            return 0;
+        }
+
+        for (archetype, &x) in self.archetypes.iter_mut().zip(&remap) {
+            if x == u32::MAX {
+                archetype.free(&self.alloc);
+            }
+        }
+        let mut old = remap.iter();
+        self.archetypes.retain(|_| *old.next().unwrap() != u32::MAX);
+        self.index.retain(|_, x| {
+            *x = remap[*x as usize];
+            *x != u32::MAX
+        });
+        for (index, archetype) in self.archetypes.iter_mut().enumerate() {
+            archetype.remap_edges(&remap);
+            for row in 0..archetype.len() {
+                let id = archetype.entity_id(row);
+                self.entities.meta[id as usize].location.archetype = index as u32;
+            }
+        }
+        self.archetype_generation += 1;
+        removed
+    }
+
     /// Whether `entity` still exists
     pub fn contains(&self, entity: Entity) -> bool {
         self.entities.contains(entity)
     }
 
+    /// A handle to `entity` that remains unambiguous even if its generation wraps around, or
+    /// `None` if it doesn't exist
+    ///
+    /// See `set_wide_generations`.
+    pub fn wide_entity(&self, entity: Entity) -> Option<WideEntity> {
+        if !self.contains(entity) {
+            return None;
+        }
+        Some(WideEntity {
+            epoch: self.entities.epoch(entity.id),
+            entity,
+        })
+    }
+
+    /// The entity `entity` refers to, or `None` if it no longer exists
+    pub fn resolve_wide(&self, entity: WideEntity) -> Option<Entity> {
+        if self.entities.epoch(entity.entity.id) != entity.epoch || !self.contains(entity.entity) {
+            return None;
+        }
+        Some(entity.entity)
+    }
+
     /// Efficiently iterate over all entities that have certain components
     ///
     /// Calling `iter` on the returned value yields `(Entity, Q)` tuples, where `Q` is some query
@@ -253,7 +1281,35 @@ impl World {
     /// assert!(entities.contains(&(b, 456, false)));
     /// ```
     pub fn query<Q: Query>(&self) -> QueryBorrow<'_, Q> {
-        QueryBorrow::new(&self.entities.meta, &self.archetypes)
+        QueryBorrow::new(&self.entities.meta, &self.archetypes, &self.sparse)
+    }
+
+    /// Efficiently iterate over all entities matching a query built at runtime
+    ///
+    /// Like `query`, but the component types are described by `TypeId`s in a `DynamicQuery`, and
+    /// components are exposed as raw pointers. Borrows are dynamically checked following the same
+    /// rules as `query`.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// # use std::any::TypeId;
+    /// let mut world = World::new();
+    /// let a = world.spawn((123, true, "abc"));
+    /// let b = world.spawn((456, false));
+    /// let c = world.spawn((42, "def"));
+    /// let mut query = DynamicQuery::new();
+    /// query.read(TypeId::of::<i32>()).with(TypeId::of::<bool>());
+    /// let entities = world.query_dynamic(&query)
+    ///     .iter()
+    ///     .map(|(e, row)| (e, unsafe { *row.get(0).cast::<i32>().as_ref() }))
+    ///     .collect::<Vec<_>>();
+    /// assert_eq!(entities.len(), 2);
+    /// assert!(entities.contains(&(a, 123)));
+    /// assert!(entities.contains(&(b, 456)));
+    /// ```
+    pub fn query_dynamic<'a>(&'a self, query: &'a DynamicQuery) -> DynamicQueryBorrow<'a> {
+        DynamicQueryBorrow::new(&self.entities.meta, &self.archetypes, query)
     }
 
     /// Prepare a query against a single entity
@@ -277,7 +1333,13 @@ impl World {
     /// ```
     pub fn query_one<Q: Query>(&self, entity: Entity) -> Result<QueryOne<'_, Q>, NoSuchEntity> {
         let loc = self.entities.get(entity)?;
-        Ok(unsafe { QueryOne::new(&self.archetypes[loc.archetype as usize], loc.index) })
+        Ok(unsafe {
+            QueryOne::new(
+                &self.archetypes[loc.archetype as usize],
+                loc.index,
+                &self.sparse,
+            )
+        })
     }
 
     /// Borrow the `T` component of `entity`
@@ -286,6 +1348,10 @@ impl World {
     /// components.
     pub fn get<T: Component>(&self, entity: Entity) -> Result<Ref<'_, T>, ComponentError> {
         let loc = self.entities.get(entity)?;
+        if let Some(set) = self.sparse.get(TypeId::of::<T>()) {
+            let row = set.row(entity.id).ok_or_else(MissingComponent::new::<T>)?;
+            return Ok(unsafe { Ref::new(set.storage(), row)? });
+        }
         if loc.archetype == 0 {
             return Err(MissingComponent::new::<T>().into());
         }
@@ -297,6 +1363,10 @@ impl World {
     /// Panics if the component is already borrowed from another entity with the same components.
     pub fn get_mut<T: Component>(&self, entity: Entity) -> Result<RefMut<'_, T>, ComponentError> {
         let loc = self.entities.get(entity)?;
+        if let Some(set) = self.sparse.get(TypeId::of::<T>()) {
+            let row = set.row(entity.id).ok_or_else(MissingComponent::new::<T>)?;
+            return Ok(unsafe { RefMut::new(set.storage(), row)? });
+        }
         if loc.archetype == 0 {
             return Err(MissingComponent::new::<T>().into());
         }
@@ -308,8 +1378,14 @@ impl World {
     /// Does not immediately borrow any component.
     pub fn entity(&self, entity: Entity) -> Result<EntityRef<'_>, NoSuchEntity> {
         Ok(match self.entities.get(entity)? {
-            Location { archetype: 0, .. } => EntityRef::empty(),
-            loc => unsafe { EntityRef::new(&self.archetypes[loc.archetype as usize], loc.index) },
+            Location { archetype: 0, .. } => EntityRef::empty(&self.sparse, entity.id),
+            loc => unsafe {
+                EntityRef::new(
+                    &self.archetypes[loc.archetype as usize],
+                    loc.index,
+                    &self.sparse,
+                )
+            },
         })
     }
 
@@ -338,7 +1414,7 @@ impl World {
     /// assert!(ids.contains(&b));
     /// ```
     pub fn iter(&self) -> Iter<'_> {
-        Iter::new(&self.archetypes, &self.entities)
+        Iter::new(&self.archetypes, &self.entities, &self.sparse)
     }
 
     /// Add `components` to `entity`
@@ -362,41 +1438,23 @@ impl World {
         entity: Entity,
         components: impl DynamicBundle,
     ) -> Result<(), NoSuchEntity> {
-        use hashbrown::hash_map::Entry;
-
         self.flush();
+        let source = self.entities.get(entity)?.archetype;
+        let target = self.insert_target(source, &components);
         let loc = self.entities.get_mut(entity)?;
+        let sparse = &mut self.sparse;
+        let alloc = &self.alloc;
         unsafe {
-            // Assemble Vec<TypeInfo> for the final entity
-            let arch = &mut self.archetypes[loc.archetype as usize];
-            let mut info = arch.types().to_vec();
-            for ty in components.type_info() {
-                if let Some(ptr) = arch.get_dynamic(ty.id(), ty.layout().size(), loc.index) {
-                    ty.drop(ptr.as_ptr());
-                } else {
-                    info.push(ty);
-                }
-            }
-            info.sort();
-
-            // Find the archetype it'll live in
-            let elements = info.iter().map(|x| x.id()).collect();
-            let target = match self.index.entry(elements) {
-                Entry::Occupied(x) => *x.get(),
-                Entry::Vacant(x) => {
-                    let index = self.archetypes.len() as u32;
-                    self.archetypes.push(Archetype::new(info));
-                    x.insert(index);
-                    self.archetype_generation += 1;
-                    index
-                }
-            };
-
             if target == loc.archetype {
                 // Update components in the current archetype
                 let arch = &mut self.archetypes[loc.archetype as usize];
-                components.put(|ptr, ty| {
-                    arch.put_dynamic(ptr, ty.id(), ty.layout().size(), loc.index);
+                components.put(|ptr, ty| match sparse.get_mut(ty.id()) {
+                    Some(set) => set.insert(alloc, entity.id, ptr),
+                    None => {
+                        let size = ty.layout().size();
+                        ty.drop(arch.get_dynamic(ty.id(), size, loc.index).unwrap().as_ptr());
+                        arch.put_dynamic(ptr, ty.id(), size, loc.index);
+                    }
                 });
                 return Ok(());
             }
@@ -407,7 +1465,7 @@ impl World {
                 loc.archetype as usize,
                 target as usize,
             );
-            let target_index = target_arch.allocate(entity.id);
+            let target_index = target_arch.allocate(alloc, entity.id);
             loc.archetype = target;
             let old_index = mem::replace(&mut loc.index, target_index);
             if let Some(moved) = source_arch.move_to(old_index, |ptr, ty, size| {
@@ -415,13 +1473,58 @@ impl World {
             }) {
                 self.entities.meta[moved as usize].location.index = old_index;
             }
-            components.put(|ptr, ty| {
-                target_arch.put_dynamic(ptr, ty.id(), ty.layout().size(), target_index);
+            components.put(|ptr, ty| match sparse.get_mut(ty.id()) {
+                Some(set) => set.insert(alloc, entity.id, ptr),
+                None => {
+                    let size = ty.layout().size();
+                    if source_arch.has_dynamic(ty.id()) {
+                        // Replacing a component that was moved over from the source
+                        let old = target_arch
+                            .get_dynamic(ty.id(), size, target_index)
+                            .unwrap();
+                        ty.drop(old.as_ptr());
+                    }
+                    target_arch.put_dynamic(ptr, ty.id(), size, target_index);
+                }
             });
         }
         Ok(())
     }
 
+    /// Find the archetype that entities in `source` move to when `components` is inserted
+    fn insert_target(&mut self, source: u32, components: &impl DynamicBundle) -> u32 {
+        self.insert_target_with(source, components.key(), || components.type_info())
+    }
+
+    /// Find the archetype that entities in `source` move to when a bundle with the given key and
+    /// component types is inserted
+    fn insert_target_with(
+        &mut self,
+        source: u32,
+        key: Option<TypeId>,
+        type_info: impl FnOnce() -> Vec<TypeInfo>,
+    ) -> u32 {
+        if let Some(target) = key.and_then(|x| self.archetypes[source as usize].insert_edge(x)) {
+            return target;
+        }
+
+        // Assemble Vec<TypeInfo> for the final entity
+        let arch = &self.archetypes[source as usize];
+        let mut info = arch.types().to_vec();
+        for ty in type_info() {
+            if !self.sparse.contains(ty.id()) && !arch.has_dynamic(ty.id()) {
+                info.push(ty);
+            }
+        }
+        info.sort();
+
+        let target = self.archetype_for(info);
+        if let Some(key) = key {
+            self.archetypes[source as usize].set_insert_edge(key, target);
+        }
+        target
+    }
+
     /// Add `component` to `entity`
     ///
     /// See `insert`.
@@ -433,6 +1536,192 @@ impl World {
         self.insert(entity, (component,))
     }
 
+    /// Add the components returned by `f` to every entity matching `Q`, returning how many
+    /// entities were affected
+    ///
+    /// Equivalent to calling `insert` on each entity of `world.query::<Q>()`, but far more
+    /// efficient: each affected archetype's entities are moved to their new archetype together,
+    /// reserving space once and copying whole columns where possible. Only `Q`'s filtering
+    /// matters. As with `insert`, existing components of the same types are replaced.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// struct Frozen;
+    /// let mut world = World::new();
+    /// let a = world.spawn((1, 2.0f32));
+    /// let b = world.spawn((3,));
+    /// let c = world.spawn((4.0f32,));
+    /// assert_eq!(world.insert_matching::<&i32, _>(|_| (Frozen,)), 2);
+    /// assert!(world.get::<Frozen>(a).is_ok());
+    /// assert!(world.get::<Frozen>(b).is_ok());
+    /// assert!(world.get::<Frozen>(c).is_err());
+    /// ```
+    pub fn insert_matching<Q: Query, T: Bundle>(
+        &mut self,
+        mut f: impl FnMut(Entity) -> T,
+    ) -> usize {
+        self.flush();
+        let masks = self.query_masks::<Q>();
+        let mut count = 0;
+        for (source, mask) in masks.iter().enumerate() {
+            let source = source as u32;
+            let first = match mask.iter().position(|&x| x) {
+                Some(x) => x as u32,
+                None => continue,
+            };
+            // Produce every bundle up front, so a panic in `f` can't leave entities half-moved
+            let entities = &self.entities;
+            let source_arch = &self.archetypes[source as usize];
+            let bundles = (first..mask.len() as u32)
+                .filter(|&index| mask[index as usize])
+                .map(|index| unsafe {
+                    let entity = entities.resolve_unknown_gen(source_arch.entity_id(index));
+                    (entity, f(entity))
+                })
+                .collect::<Vec<_>>();
+            count += bundles.len();
+
+            let target = self.insert_target_with(source, T::static_key(), T::static_type_info);
+            let sparse = &mut self.sparse;
+            let alloc = &self.alloc;
+            if target == source {
+                // Update components in the current archetype
+                let arch = &mut self.archetypes[source as usize];
+                for (entity, bundle) in bundles {
+                    let index = self.entities.meta[entity.id as usize].location.index;
+                    unsafe {
+                        bundle.put(|ptr, ty| match sparse.get_mut(ty.id()) {
+                            Some(set) => set.insert(alloc, entity.id, ptr),
+                            None => {
+                                let size = ty.layout().size();
+                                ty.drop(arch.get_dynamic(ty.id(), size, index).unwrap().as_ptr());
+                                arch.put_dynamic(ptr, ty.id(), size, index);
+                            }
+                        });
+                    }
+                }
+                continue;
+            }
+
+            // Move into a new archetype
+            let (source_arch, target_arch) =
+                index2(&mut self.archetypes, source as usize, target as usize);
+            unsafe {
+                let base = source_arch.move_rows(alloc, mask, target_arch, |_, _| {
+                    unreachable!("insertion never removes components")
+                });
+                for ((entity, bundle), index) in bundles.into_iter().zip(base..) {
+                    self.entities.meta[entity.id as usize].location = Location {
+                        archetype: target,
+                        index,
+                    };
+                    bundle.put(|ptr, ty| match sparse.get_mut(ty.id()) {
+                        Some(set) => set.insert(alloc, entity.id, ptr),
+                        None => {
+                            let size = ty.layout().size();
+                            if source_arch.has_dynamic(ty.id()) {
+                                // Replacing a component that was moved over from the source
+                                let old = target_arch.get_dynamic(ty.id(), size, index).unwrap();
+                                ty.drop(old.as_ptr());
+                            }
+                            target_arch.put_dynamic(ptr, ty.id(), size, index);
+                        }
+                    });
+                }
+            }
+            for index in first..source_arch.len() {
+                let id = source_arch.entity_id(index);
+                self.entities.meta[id as usize].location.index = index;
+            }
+        }
+        count
+    }
+
+    /// Remove and drop the components in `T` from every entity matching `Q`, returning how many
+    /// entities were affected
+    ///
+    /// Equivalent to calling `remove` on each entity of `world.query::<Q>()` and dropping the
+    /// result, but far more efficient: each affected archetype's entities are moved to their new
+    /// archetype together, reserving space once and copying whole columns where possible. Only
+    /// `Q`'s filtering matters. Entities that lack any component in `T` are left untouched.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// struct Frozen;
+    /// let mut world = World::new();
+    /// let a = world.spawn((1, Frozen));
+    /// let b = world.spawn(("abc", Frozen));
+    /// assert_eq!(world.remove_matching::<&i32, (Frozen,)>(), 1);
+    /// assert!(world.get::<Frozen>(a).is_err());
+    /// assert!(world.get::<Frozen>(b).is_ok());
+    /// ```
+    pub fn remove_matching<Q: Query, T: Bundle>(&mut self) -> usize {
+        self.flush();
+        let masks = self.query_masks::<Q>();
+        let mut count = 0;
+        for (source, mut mask) in masks.into_iter().enumerate() {
+            let source = source as u32;
+            let sparse = &mut self.sparse;
+            let arch = &self.archetypes[source as usize];
+            let complete = T::with_static_ids(|ids| {
+                ids.iter()
+                    .all(|&ty| sparse.contains(ty) || arch.has_dynamic(ty))
+            });
+            if !complete {
+                continue;
+            }
+            // Skip entities missing any of the sparse components
+            T::with_static_ids(|ids| {
+                for set in ids.iter().filter_map(|&ty| sparse.get(ty)) {
+                    for (index, x) in mask.iter_mut().enumerate() {
+                        *x = *x && set.row(arch.entity_id(index as u32)).is_some();
+                    }
+                }
+            });
+            let first = match mask.iter().position(|&x| x) {
+                Some(x) => x as u32,
+                None => continue,
+            };
+            T::with_static_ids(|ids| {
+                for &ty in ids {
+                    if let Some(set) = sparse.get_mut(ty) {
+                        for index in first..mask.len() as u32 {
+                            if mask[index as usize] {
+                                set.remove(arch.entity_id(index));
+                            }
+                        }
+                    }
+                }
+            });
+            count += mask.iter().filter(|&&x| x).count();
+
+            let target = self.remove_target::<T>(source);
+            if target == source {
+                continue;
+            }
+            let (source_arch, target_arch) =
+                index2(&mut self.archetypes, source as usize, target as usize);
+            unsafe {
+                let base =
+                    source_arch.move_rows(&self.alloc, &mask, target_arch, |ptr, ty| ty.drop(ptr));
+                for index in base..target_arch.len() {
+                    let id = target_arch.entity_id(index);
+                    self.entities.meta[id as usize].location = Location {
+                        archetype: target,
+                        index,
+                    };
+                }
+            }
+            for index in first..source_arch.len() {
+                let id = source_arch.entity_id(index);
+                self.entities.meta[id as usize].location.index = index;
+            }
+        }
+        count
+    }
+
     /// Remove components from `entity`
     ///
     /// Computational cost is proportional to the number of components `entity` has. The entity
@@ -453,33 +1742,27 @@ impl World {
     /// assert_eq!(*world.get::<bool>(e).unwrap(), true);
     /// ```
     pub fn remove<T: Bundle>(&mut self, entity: Entity) -> Result<T, ComponentError> {
-        use hashbrown::hash_map::Entry;
-
         self.flush();
+        let source = self.entities.get(entity)?.archetype;
+        let target = self.remove_target::<T>(source);
         let loc = self.entities.get_mut(entity)?;
         unsafe {
-            let removed = T::with_static_ids(|ids| ids.iter().copied().collect::<HashSet<_>>());
-            let info = self.archetypes[loc.archetype as usize]
-                .types()
-                .iter()
-                .cloned()
-                .filter(|x| !removed.contains(&x.id()))
-                .collect::<Vec<_>>();
-            let elements = info.iter().map(|x| x.id()).collect();
-            let target = match self.index.entry(elements) {
-                Entry::Occupied(x) => *x.get(),
-                Entry::Vacant(x) => {
-                    self.archetypes.push(Archetype::new(info));
-                    let index = (self.archetypes.len() - 1) as u32;
-                    x.insert(index);
-                    self.archetype_generation += 1;
-                    index
-                }
-            };
             let old_index = loc.index;
             let source_arch = &self.archetypes[loc.archetype as usize];
-            let bundle =
-                T::get(|ty| source_arch.get_dynamic(ty.id(), ty.layout().size(), old_index))?;
+            let sparse = &mut self.sparse;
+            let bundle = T::get(|ty| match sparse.get(ty.id()) {
+                Some(set) => set.get(entity.id),
+                None => source_arch.get_dynamic(ty.id(), ty.layout().size(), old_index),
+            })?;
+            if !sparse.is_empty() {
+                T::with_static_ids(|ids| {
+                    for &ty in ids {
+                        if let Some(set) = sparse.get_mut(ty) {
+                            set.forget(entity.id);
+                        }
+                    }
+                });
+            }
             // If we actually removed any components, the entity needs to be moved into a new archetype
             if loc.archetype != target {
                 let (source_arch, target_arch) = index2(
@@ -487,7 +1770,7 @@ impl World {
                     loc.archetype as usize,
                     target as usize,
                 );
-                let target_index = target_arch.allocate(entity.id);
+                let target_index = target_arch.allocate(&self.alloc, entity.id);
                 loc.archetype = target;
                 loc.index = target_index;
                 if let Some(moved) = source_arch.move_to(old_index, |src, ty, size| {
@@ -503,6 +1786,29 @@ impl World {
         }
     }
 
+    /// Find the archetype that entities in `source` move to when `T` is removed
+    fn remove_target<T: Bundle>(&mut self, source: u32) -> u32 {
+        let key = T::static_key();
+        if let Some(target) = key.and_then(|x| self.archetypes[source as usize].remove_edge(x)) {
+            return target;
+        }
+
+        let arch = &self.archetypes[source as usize];
+        let info = T::with_static_ids(|ids| {
+            arch.types()
+                .iter()
+                .filter(|x| !ids.contains(&x.id()))
+                .copied()
+                .collect::<Vec<_>>()
+        });
+
+        let target = self.archetype_for(info);
+        if let Some(key) = key {
+            self.archetypes[source as usize].set_remove_edge(key, target);
+        }
+        target
+    }
+
     /// Remove the `T` component from `entity`
     ///
     /// See `remove`.
@@ -520,14 +1826,17 @@ impl World {
     /// same component of `entity` may be live simultaneous to the returned reference.
     pub unsafe fn get_unchecked<T: Component>(&self, entity: Entity) -> Result<&T, ComponentError> {
         let loc = self.entities.get(entity)?;
+        if let Some(set) = self.sparse.get(TypeId::of::<T>()) {
+            let ptr = set.get(entity.id).ok_or_else(MissingComponent::new::<T>)?;
+            return Ok(&*ptr.cast::<T>().as_ptr());
+        }
         if loc.archetype == 0 {
             return Err(MissingComponent::new::<T>().into());
         }
         Ok(&*self.archetypes[loc.archetype as usize]
-            .get::<T>()
+            .get_at::<T>(loc.index)
             .ok_or_else(MissingComponent::new::<T>)?
-            .as_ptr()
-            .add(loc.index as usize))
+            .as_ptr())
     }
 
     /// Uniquely borrow the `T` component of `entity` without safety checks
@@ -543,14 +1852,17 @@ impl World {
         entity: Entity,
     ) -> Result<&mut T, ComponentError> {
         let loc = self.entities.get(entity)?;
+        if let Some(set) = self.sparse.get(TypeId::of::<T>()) {
+            let ptr = set.get(entity.id).ok_or_else(MissingComponent::new::<T>)?;
+            return Ok(&mut *ptr.cast::<T>().as_ptr());
+        }
         if loc.archetype == 0 {
             return Err(MissingComponent::new::<T>().into());
         }
         Ok(&mut *self.archetypes[loc.archetype as usize]
-            .get::<T>()
+            .get_at::<T>(loc.index)
             .ok_or_else(MissingComponent::new::<T>)?
-            .as_ptr()
-            .add(loc.index as usize))
+            .as_ptr())
     }
 
     /// Convert all reserved entities into empty entities that can be iterated and accessed
@@ -558,8 +1870,10 @@ impl World {
     /// Invoked implicitly by `spawn`, `despawn`, `insert`, and `remove`.
     pub fn flush(&mut self) {
         let arch = &mut self.archetypes[0];
-        self.entities
-            .flush(|id, location| location.index = unsafe { arch.allocate(id) });
+        let alloc = &self.alloc;
+        self.entities.flush(alloc, |id, location| {
+            location.index = unsafe { arch.allocate(alloc, id) }
+        });
     }
 
     /// Inspect the archetypes that entities are organized into
@@ -593,8 +1907,30 @@ impl World {
     }
 }
 
-unsafe impl Send for World {}
-unsafe impl Sync for World {}
+impl<A: Allocator> Drop for World<A> {
+    fn drop(&mut self) {
+        for archetype in &mut self.archetypes {
+            archetype.free(&self.alloc);
+        }
+        self.sparse.free(&self.alloc);
+        self.entities.free_memory(&self.alloc);
+    }
+}
+
+unsafe impl<A: Allocator> Send for World<A> {}
+unsafe impl<A: Allocator> Sync for World<A> {}
+
+impl<A: Allocator> fmt::Debug for World<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("World")
+            .field(
+                "entities",
+                &self.archetypes.iter().map(|x| x.len()).sum::<u32>(),
+            )
+            .field("archetypes", &self.archetypes)
+            .finish()
+    }
+}
 
 impl Default for World {
     fn default() -> Self {
@@ -602,7 +1938,7 @@ impl Default for World {
     }
 }
 
-impl<'a> IntoIterator for &'a World {
+impl<'a, A: Allocator> IntoIterator for &'a World<A> {
     type IntoIter = Iter<'a>;
     type Item = (Entity, EntityRef<'a>);
     fn into_iter(self) -> Iter<'a> {
@@ -663,15 +1999,17 @@ impl<T: Send + Sync + 'static> Component for T {}
 pub struct Iter<'a> {
     archetypes: core::slice::Iter<'a, Archetype>,
     entities: &'a Entities,
+    sparse: &'a SparseSets,
     current: Option<&'a Archetype>,
     index: u32,
 }
 
 impl<'a> Iter<'a> {
-    fn new(archetypes: &'a [Archetype], entities: &'a Entities) -> Self {
+    fn new(archetypes: &'a [Archetype], entities: &'a Entities, sparse: &'a SparseSets) -> Self {
         Self {
             archetypes: archetypes.iter(),
             entities,
+            sparse,
             current: None,
             index: 0,
         }
@@ -703,7 +2041,7 @@ impl<'a> Iterator for Iter<'a> {
                             id,
                             generation: self.entities.meta[id as usize].generation,
                         },
-                        unsafe { EntityRef::new(current, index) },
+                        unsafe { EntityRef::new(current, index, self.sparse) },
                     ));
                 }
             }
@@ -715,10 +2053,10 @@ impl<'a> Iterator for Iter<'a> {
     }
 }
 
-impl<A: DynamicBundle> Extend<A> for World {
+impl<A: Allocator, B: DynamicBundle> Extend<B> for World<A> {
     fn extend<T>(&mut self, iter: T)
     where
-        T: IntoIterator<Item = A>,
+        T: IntoIterator<Item = B>,
     {
         for x in iter {
             self.spawn(x);
@@ -726,8 +2064,8 @@ impl<A: DynamicBundle> Extend<A> for World {
     }
 }
 
-impl<A: DynamicBundle> core::iter::FromIterator<A> for World {
-    fn from_iter<I: IntoIterator<Item = A>>(iter: I) -> Self {
+impl<B: DynamicBundle> core::iter::FromIterator<B> for World {
+    fn from_iter<I: IntoIterator<Item = B>>(iter: I) -> Self {
         let mut world = World::new();
         world.extend(iter);
         world
@@ -739,7 +2077,7 @@ impl<A: DynamicBundle> core::iter::FromIterator<A> for World {
 pub struct ArchetypesGeneration(u64);
 
 /// Entity IDs created by `World::spawn_batch`
-pub struct SpawnBatchIter<'a, I>
+pub struct SpawnBatchIter<'a, I, A: Allocator = Global>
 where
     I: Iterator,
     I::Item: Bundle,
@@ -748,9 +2086,11 @@ where
     entities: &'a mut Entities,
     archetype_id: u32,
     archetype: &'a mut Archetype,
+    sparse: &'a mut SparseSets,
+    alloc: &'a A,
 }
 
-impl<I> Drop for SpawnBatchIter<'_, I>
+impl<I, A: Allocator> Drop for SpawnBatchIter<'_, I, A>
 where
     I: Iterator,
     I::Item: Bundle,
@@ -760,7 +2100,7 @@ where
     }
 }
 
-impl<I> Iterator for SpawnBatchIter<'_, I>
+impl<I, A: Allocator> Iterator for SpawnBatchIter<'_, I, A>
 where
     I: Iterator,
     I::Item: Bundle,
@@ -769,12 +2109,15 @@ where
 
     fn next(&mut self) -> Option<Entity> {
         let components = self.inner.next()?;
-        let entity = self.entities.alloc();
+        let alloc = self.alloc;
+        let entity = self.entities.alloc(alloc);
         unsafe {
-            let index = self.archetype.allocate(entity.id);
-            components.put(|ptr, ty| {
-                self.archetype
-                    .put_dynamic(ptr, ty.id(), ty.layout().size(), index);
+            let index = self.archetype.allocate(alloc, entity.id);
+            let archetype = &mut *self.archetype;
+            let sparse = &mut *self.sparse;
+            components.put(|ptr, ty| match sparse.get_mut(ty.id()) {
+                Some(set) => set.insert(alloc, entity.id, ptr),
+                None => archetype.put_dynamic(ptr, ty.id(), ty.layout().size(), index),
             });
             self.entities.meta[entity.id as usize].location = Location {
                 archetype: self.archetype_id,
@@ -789,7 +2132,7 @@ where
     }
 }
 
-impl<I, T> ExactSizeIterator for SpawnBatchIter<'_, I>
+impl<I, T, A: Allocator> ExactSizeIterator for SpawnBatchIter<'_, I, A>
 where
     I: ExactSizeIterator<Item = T>,
     T: Bundle,
@@ -826,10 +2169,112 @@ mod tests {
         assert!(*world.get::<bool>(b).unwrap());
     }
 
+    #[test]
+    fn wide_generations() {
+        let mut world = World::new();
+        world.set_wide_generations(true);
+        let a = world.spawn(());
+        world.entities.meta[a.id as usize].generation = crate::entities::Generation::MAX;
+        let a = Entity {
+            generation: crate::entities::Generation::MAX,
+            id: a.id,
+        };
+        let wide_a = world.wide_entity(a).unwrap();
+        world.despawn(a).unwrap();
+        let b = world.spawn(());
+        assert_eq!(b.id, a.id);
+        assert_eq!(world.retired_ids(), 0);
+        let wide_b = world.wide_entity(b).unwrap();
+        assert_ne!(wide_a, wide_b);
+        assert_eq!(world.resolve_wide(wide_a), None);
+        assert_eq!(world.resolve_wide(wide_b), Some(b));
+        assert_eq!(WideEntity::from_bits(wide_b.to_bits()), Some(wide_b));
+    }
+
     #[test]
     fn remove_nothing() {
         let mut world = World::new();
         let a = world.spawn(("abc", 123));
         world.remove::<()>(a).unwrap();
     }
+
+    #[test]
+    fn cached_transitions() {
+        let mut world = World::new();
+        let a = world.spawn((123,));
+        let b = world.spawn((456,));
+        let source = world.entities.get(a).unwrap().archetype;
+        world.insert(a, (true, "abc")).unwrap();
+        let target = world.entities.get(a).unwrap().archetype;
+        let key = TypeId::of::<(bool, &str)>();
+        assert_eq!(
+            world.archetypes[source as usize].insert_edge(key),
+            Some(target)
+        );
+        world.insert(b, (false, "def")).unwrap();
+        assert_eq!(world.entities.get(b).unwrap().archetype, target);
+        assert!(!*world.get::<bool>(b).unwrap());
+
+        // Replacing existing components follows a cached edge back to the same archetype
+        world.insert(b, (true, "ghi")).unwrap();
+        world.insert(b, (true, "ghi")).unwrap();
+        assert_eq!(world.entities.get(b).unwrap().archetype, target);
+        assert_eq!(*world.get::<&str>(b).unwrap(), "ghi");
+
+        assert_eq!(world.remove::<(bool, &str)>(a), Ok((true, "abc")));
+        assert_eq!(
+            world.archetypes[target as usize].remove_edge(key),
+            Some(source)
+        );
+        assert_eq!(world.remove::<(bool, &str)>(b), Ok((true, "ghi")));
+        assert_eq!(world.entities.get(b).unwrap().archetype, source);
+        assert_eq!(*world.get::<i32>(b).unwrap(), 456);
+    }
+
+    #[test]
+    fn shrink_to_fit() {
+        for mut world in [World::new(), World::with_chunk_size(64)] {
+            let entities = world
+                .spawn_batch((0..1000).map(|i| (i, i as f32)))
+                .collect::<Vec<_>>();
+            for &e in &entities[10..] {
+                world.despawn(e).unwrap();
+            }
+            world.shrink_to_fit();
+            let archetype =
+                &world.archetypes[world.entities.get(entities[0]).unwrap().archetype as usize];
+            assert!(archetype.capacity() < 20);
+            assert_eq!(*world.get::<i32>(entities[9]).unwrap(), 9);
+
+            world.clear();
+            world.shrink_to_fit();
+            assert!(world.archetypes.iter().all(|x| x.capacity() == 0));
+            let e = world.spawn((1, 2.0f32));
+            assert_eq!(*world.get::<f32>(e).unwrap(), 2.0);
+        }
+    }
+
+    #[test]
+    fn remove_empty_archetypes() {
+        let mut world = World::new();
+        let a = world.spawn((123,));
+        let b = world.spawn(("abc",));
+        world.insert_one(a, true).unwrap();
+        world.remove_one::<bool>(a).unwrap();
+        world.despawn(b).unwrap();
+        assert_eq!(world.remove_empty_archetypes(), 2);
+        assert_eq!(world.remove_empty_archetypes(), 0);
+        assert_eq!(world.archetypes.len(), 2);
+
+        // Locations are renumbered, and transitions to removed archetypes forgotten
+        assert_eq!(world.entities.get(a).unwrap().archetype, 1);
+        assert_eq!(*world.get::<i32>(a).unwrap(), 123);
+        assert_eq!(
+            world.archetypes[1].insert_edge(TypeId::of::<(bool,)>()),
+            None
+        );
+        world.insert_one(a, false).unwrap();
+        assert!(!*world.get::<bool>(a).unwrap());
+        assert_eq!(world.query::<&i32>().iter().count(), 1);
+    }
 }
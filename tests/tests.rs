@@ -368,3 +368,1177 @@ fn query_one() {
     world.despawn(a).unwrap();
     assert!(world.query_one::<&i32>(a).is_err());
 }
+
+#[test]
+fn query_dynamic() {
+    use std::any::TypeId;
+
+    let mut world = World::new();
+    let a = world.spawn(("abc", 123));
+    let b = world.spawn(("def", 456, true));
+    world.spawn((789, 'x'));
+
+    let mut query = DynamicQuery::new();
+    query
+        .read(TypeId::of::<&str>())
+        .write(TypeId::of::<i32>())
+        .without(TypeId::of::<char>());
+    assert_eq!(query.columns(), 2);
+    for (_, row) in world.query_dynamic(&query).iter() {
+        assert_eq!(row.len(), 2);
+        unsafe {
+            *row.get(1).cast::<i32>().as_ptr() += 1;
+        }
+    }
+    assert_eq!(*world.get::<i32>(a).unwrap(), 124);
+    assert_eq!(*world.get::<i32>(b).unwrap(), 457);
+
+    let mut query = DynamicQuery::new();
+    query.read(TypeId::of::<i32>()).with(TypeId::of::<bool>());
+    let mut borrow = world.query_dynamic(&query);
+    let chunks = borrow.iter_chunks().collect::<Vec<_>>();
+    assert_eq!(chunks.len(), 1);
+    assert_eq!(chunks[0].len(), 1);
+    assert_eq!(chunks[0].entity(0), b);
+    assert_eq!(unsafe { *chunks[0].column(0).cast::<i32>().as_ref() }, 457);
+}
+
+#[test]
+#[should_panic(expected = "already borrowed")]
+fn illegal_dynamic_borrow() {
+    use std::any::TypeId;
+
+    let mut world = World::new();
+    world.spawn(("abc", 123));
+
+    let mut query = DynamicQuery::new();
+    query.write(TypeId::of::<i32>()).read(TypeId::of::<i32>());
+    world.query_dynamic(&query).iter();
+}
+
+#[test]
+fn registry_format() {
+    #[derive(Debug)]
+    struct Position(i32, i32);
+
+    let mut registry = ComponentRegistry::new();
+    registry.register::<Position>("position").debug();
+    registry.register::<&str>("name").display().debug();
+    registry.register::<bool>("flag");
+    registry.register::<i32>("count").default();
+
+    let mut world = World::new();
+    let e = world.spawn(("abc", Position(1, 2), true, 'x'));
+    let entity = world.entity(e).unwrap();
+    let position = entity.get::<Position>().unwrap();
+    assert_eq!((position.0, position.1), (1, 2));
+    drop(position);
+    assert_eq!(
+        registry.display_entity(entity).to_string(),
+        "{position: Position(1, 2), name: abc, flag: ..}"
+    );
+    assert_eq!(
+        format!("{:?}", registry.display_entity(entity)),
+        r#"{"position": Position(1, 2), "name": "abc", "flag": ..}"#
+    );
+    assert_eq!(
+        registry.display_world(&world).to_string(),
+        format!(
+            "{:?}: {{position: Position(1, 2), name: abc, flag: ..}}\n",
+            e
+        )
+    );
+
+    let info = registry.get_by_name("count").unwrap();
+    assert_eq!(info.id(), std::any::TypeId::of::<i32>());
+    let mut builder = EntityBuilder::new();
+    assert!(info.add_default(&mut builder));
+    assert!(!registry
+        .get_by_name("flag")
+        .unwrap()
+        .add_default(&mut builder));
+    let f = world.spawn(builder.build());
+    assert_eq!(*world.get::<i32>(f).unwrap(), 0);
+}
+
+#[test]
+fn registry_format_panic() {
+    struct Faulty;
+
+    impl std::fmt::Display for Faulty {
+        fn fmt(&self, _: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            panic!("unprintable");
+        }
+    }
+
+    let mut registry = ComponentRegistry::new();
+    registry.register::<Faulty>("faulty").display();
+
+    let mut world = World::new();
+    let e = world.spawn((Faulty,));
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        registry
+            .display_entity(world.entity(e).unwrap())
+            .to_string()
+    }));
+    assert!(result.is_err());
+    // The component's column was released despite the panic
+    assert!(world.get_mut::<Faulty>(e).is_ok());
+}
+
+#[test]
+fn registry_stable_ids() {
+    let mut registry = ComponentRegistry::new();
+    registry.register::<i32>("health").stable_id(1);
+    registry.register::<bool>("flag").stable_id(2);
+    assert_eq!(registry.get_by_stable_id(2).unwrap().name(), "flag");
+
+    // Changing a type's ID frees the old one
+    registry.register::<bool>("flag").stable_id(3);
+    assert!(registry.get_by_stable_id(2).is_none());
+    assert_eq!(registry.get_by_stable_id(3).unwrap().name(), "flag");
+
+    // Registering a type again discards its ID
+    registry.register::<i32>("hp");
+    assert!(registry.get_by_stable_id(1).is_none());
+    assert_eq!(registry.get_by_name("hp").unwrap().stable_id(), None);
+    registry.register::<u8>("byte").stable_id(1);
+}
+
+#[test]
+#[should_panic(expected = "component ID 1 already registered")]
+fn registry_stable_id_conflict() {
+    let mut registry = ComponentRegistry::new();
+    registry.register::<i32>("health").stable_id(1);
+    registry.register::<bool>("flag").stable_id(1);
+}
+
+#[test]
+fn debug_names() {
+    let mut world = World::new();
+    let e = world.spawn((123,));
+    world.spawn((456,));
+    assert_eq!(
+        format!("{:?}", world.entity(e).unwrap()),
+        r#"EntityRef { components: ["i32"] }"#
+    );
+    assert_eq!(
+        format!("{:?}", world),
+        r#"World { entities: 2, archetypes: [Archetype { types: [], len: 0 }, Archetype { types: ["i32"], len: 2 }] }"#
+    );
+}
+
+#[test]
+#[should_panic(expected = "i32 already borrowed")]
+fn illegal_dynamic_borrow_name() {
+    use std::any::TypeId;
+
+    let mut world = World::new();
+    let e = world.spawn(("abc", 123));
+    let _borrow = world.get_mut::<i32>(e).unwrap();
+    let mut query = DynamicQuery::new();
+    query.read(TypeId::of::<i32>());
+    world.query_dynamic(&query).iter();
+}
+
+#[test]
+fn hierarchy() {
+    let mut world = World::new();
+    let root = world.spawn(("root",));
+    let a = world.spawn(("a",));
+    let b = world.spawn(("b",));
+    let c = world.spawn(());
+    world.set_parent(a, root).unwrap();
+    world.set_parent(b, root).unwrap();
+    world.set_parent(c, a).unwrap();
+    assert_eq!(world.descendants(root).collect::<Vec<_>>(), &[a, c, b]);
+
+    // Reparenting detaches from the previous parent
+    world.set_parent(c, b).unwrap();
+    assert!(world.get::<Children>(a).is_err());
+    assert_eq!(&**world.get::<Children>(b).unwrap(), &[c]);
+    assert_eq!(world.ancestors(c).collect::<Vec<_>>(), &[b, root]);
+
+    // Despawning a child updates its parent
+    world.despawn(a).unwrap();
+    assert_eq!(&**world.get::<Children>(root).unwrap(), &[b]);
+
+    // Despawning a parent orphans its children
+    world.despawn(b).unwrap();
+    assert!(world.get::<Children>(root).is_err());
+    assert!(world.get::<Parent>(c).is_err());
+    assert!(world.contains(c));
+
+    assert_eq!(world.remove_parent(c), Ok(None));
+    world.set_parent(c, root).unwrap();
+    assert_eq!(world.remove_parent(c), Ok(Some(root)));
+    assert!(world.get::<Children>(root).is_err());
+}
+
+#[test]
+#[should_panic(expected = "cannot be its own ancestor")]
+fn hierarchy_cycle() {
+    let mut world = World::new();
+    let a = world.spawn(());
+    let b = world.spawn(());
+    world.set_parent(b, a).unwrap();
+    world.set_parent(a, b).unwrap();
+}
+
+#[test]
+fn relations() {
+    struct Targets;
+    #[derive(Debug, Eq, PartialEq)]
+    struct Owns(u32);
+
+    let mut world = World::new();
+    let a = world.spawn(("a",));
+    let b = world.spawn(("b",));
+    let c = world.spawn(("c",));
+    world.relate(a, b, Owns(1)).unwrap();
+    world.relate(a, c, Owns(2)).unwrap();
+    world.relate(b, c, Owns(3)).unwrap();
+    world.relate(a, b, Targets).unwrap();
+    world.relate(a, b, Owns(4)).unwrap();
+
+    assert_eq!(
+        world.relation_targets::<Owns>(a).collect::<Vec<_>>(),
+        &[b, c]
+    );
+    assert_eq!(
+        world.relation_sources::<Owns>(c).collect::<Vec<_>>(),
+        &[a, b]
+    );
+    assert_eq!(
+        world.get::<Relations<Owns>>(a).unwrap().get(b),
+        Some(&Owns(4))
+    );
+    assert_eq!(world.query::<&Relations<Owns>>().iter().count(), 2);
+    assert_eq!(world.query::<&RelatedBy<Targets>>().iter().count(), 1);
+
+    assert_eq!(world.unrelate::<Owns>(a, c), Ok(Some(Owns(2))));
+    assert_eq!(world.unrelate::<Owns>(a, c), Ok(None));
+    assert_eq!(world.relation_sources::<Owns>(c).collect::<Vec<_>>(), &[b]);
+
+    // Despawning a target removes every relationship pointing at it
+    world.despawn(b).unwrap();
+    assert!(world.get::<Relations<Owns>>(a).is_err());
+    assert!(world.get::<Relations<Targets>>(a).is_err());
+    assert!(world.get::<RelatedBy<Owns>>(c).is_err());
+    assert_eq!(world.relation_targets::<Owns>(a).count(), 0);
+    assert_eq!(world.relation_sources::<Owns>(b).count(), 0);
+}
+
+#[test]
+fn despawn_self_relation() {
+    struct Likes;
+
+    let mut world = World::new();
+    let a = world.spawn(("a",));
+    let b = world.spawn(("b",));
+    let c = world.spawn(("c",));
+    world.relate(a, a, Likes).unwrap();
+    world.relate(a, b, Likes).unwrap();
+    world.relate(c, a, Likes).unwrap();
+    world.relate(b, b, Likes).unwrap();
+
+    // Unlinking `a` from itself moves it to another archetype partway through despawning
+    world.despawn(a).unwrap();
+    assert!(!world.contains(a));
+    assert_eq!(world.relation_sources::<Likes>(b).collect::<Vec<_>>(), &[b]);
+    assert!(world.get::<Relations<Likes>>(c).is_err());
+    assert_eq!(*world.get::<&str>(b).unwrap(), "b");
+    assert_eq!(*world.get::<&str>(c).unwrap(), "c");
+
+    world.despawn(b).unwrap();
+    assert!(world.get::<RelatedBy<Likes>>(b).is_err());
+    assert_eq!(world.iter().count(), 1);
+    assert_eq!(world.query::<&Relations<Likes>>().iter().count(), 0);
+}
+
+#[test]
+fn sparse() {
+    use std::any::TypeId;
+
+    #[derive(Debug, Eq, PartialEq)]
+    struct Stunned(u32);
+
+    let mut world = World::new();
+    world.register_sparse::<Stunned>();
+    let a = world.spawn((1, Stunned(10)));
+    let b = world.spawn((2,));
+    let c = world.spawn((3, "abc"));
+    let archetypes = world.archetypes().len();
+
+    world.insert_one(b, Stunned(20)).unwrap();
+    world.insert(c, (Stunned(30), true)).unwrap();
+    assert_eq!(world.archetypes().len(), archetypes + 1);
+    assert_eq!(*world.get::<Stunned>(b).unwrap(), Stunned(20));
+    assert_eq!(world.remove_one::<Stunned>(a), Ok(Stunned(10)));
+    assert!(world.get::<Stunned>(a).is_err());
+    assert_eq!(world.archetypes().len(), archetypes + 1);
+    world.get_mut::<Stunned>(c).unwrap().0 += 1;
+
+    let mut stunned = world
+        .query::<(&i32, Sparse<&Stunned>)>()
+        .iter()
+        .map(|(e, (&i, s))| (e, i, s.0))
+        .collect::<Vec<_>>();
+    stunned.sort_by_key(|x| x.1);
+    assert_eq!(stunned, &[(b, 2, 20), (c, 3, 31)]);
+    assert_eq!(world.query::<Sparse<&Stunned>>().iter().len(), 2);
+    assert_eq!(
+        world
+            .query::<&i32>()
+            .without_sparse::<Stunned>()
+            .iter()
+            .map(|(e, _)| e)
+            .collect::<Vec<_>>(),
+        &[a]
+    );
+    for (_, (x, s)) in world
+        .query::<(&mut i32, Option<Sparse<&mut Stunned>>)>()
+        .iter()
+    {
+        if let Some(s) = s {
+            *x += s.0 as i32;
+        }
+    }
+    assert_eq!(*world.get::<i32>(a).unwrap(), 1);
+    assert_eq!(*world.get::<i32>(b).unwrap(), 22);
+    assert_eq!(
+        world
+            .query_one::<Sparse<&Stunned>>(a)
+            .unwrap()
+            .get()
+            .map(|x| x.0),
+        None
+    );
+    assert!(world.entity(b).unwrap().get::<Stunned>().is_some());
+    assert!(world
+        .entity(b)
+        .unwrap()
+        .component_types()
+        .any(|x| x == TypeId::of::<Stunned>()));
+    assert!(format!("{:?}", world.entity(b).unwrap()).contains("Stunned"));
+
+    world.despawn(b).unwrap();
+    let d = world.spawn((4,));
+    assert_eq!(d.id(), b.id());
+    assert!(world.get::<Stunned>(d).is_err());
+    assert_eq!(world.query::<Sparse<&Stunned>>().iter().count(), 1);
+}
+
+#[test]
+#[should_panic(expected = "already borrowed")]
+fn illegal_sparse_borrow() {
+    struct Stunned;
+    let mut world = World::new();
+    world.register_sparse::<Stunned>();
+    world.spawn((Stunned,));
+    let mut a = world.query::<Sparse<&mut Stunned>>();
+    let _a = a.iter();
+    let mut b = world.query::<Sparse<&Stunned>>();
+    let _b = b.iter();
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "must be queried through `Sparse`")]
+fn sparse_dense_query() {
+    struct Stunned;
+    let mut world = World::new();
+    world.register_sparse::<Stunned>();
+    world.spawn((Stunned,));
+    world.query::<&Stunned>().iter();
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "can't be queried through `Sparse`")]
+fn dense_sparse_query() {
+    let mut world = World::new();
+    world.spawn((123,));
+    world.query::<Sparse<&i32>>().iter();
+}
+
+#[test]
+fn chunked() {
+    use std::any::TypeId;
+
+    // Room for four (i32, f32) rows per chunk
+    let mut world = World::with_chunk_size(32);
+    let entities = world
+        .spawn_batch((0..10).map(|i| (i, i as f32)))
+        .collect::<Vec<_>>();
+    let address = |world: &World| &*world.get::<i32>(entities[0]).unwrap() as *const i32;
+    let first = address(&world);
+    for i in 10..30 {
+        world.spawn((i, i as f32));
+    }
+    // Growing never moves existing components
+    assert_eq!(
+        &*world.get::<i32>(entities[0]).unwrap() as *const i32,
+        first
+    );
+
+    let mut seen = world
+        .query::<(&i32, &f32)>()
+        .iter()
+        .map(|(_, (&i, &f))| {
+            assert_eq!(i as f32, f);
+            i
+        })
+        .collect::<Vec<_>>();
+    seen.sort_unstable();
+    assert_eq!(seen, (0..30).collect::<Vec<_>>());
+
+    let batches = world
+        .query::<&i32>()
+        .iter_batched(3)
+        .map(|batch| batch.count())
+        .collect::<Vec<_>>();
+    assert!(batches.iter().all(|&n| n <= 3));
+    assert_eq!(batches.iter().sum::<usize>(), 30);
+
+    let mut query = DynamicQuery::new();
+    query.read(TypeId::of::<i32>());
+    let mut borrow = world.query_dynamic(&query);
+    let chunks = borrow.iter_chunks().map(|x| x.len()).collect::<Vec<_>>();
+    assert!(chunks.iter().all(|&n| n <= 4));
+    assert_eq!(chunks.iter().sum::<usize>(), 30);
+    drop(borrow);
+
+    for &e in &entities[..5] {
+        world.despawn(e).unwrap();
+    }
+    world.insert_one(entities[5], "abc").unwrap();
+    assert_eq!(world.remove_one::<f32>(entities[6]), Ok(6.0));
+    assert_eq!(world.query::<&f32>().iter().count(), 24);
+    assert_eq!(
+        *world.query_one::<&i32>(entities[9]).unwrap().get().unwrap(),
+        9
+    );
+    assert_eq!(
+        world.query_one::<(&i32, &&str)>(entities[5]).unwrap().get(),
+        Some((&5, &"abc"))
+    );
+}
+
+#[test]
+fn custom_allocator() {
+    use std::alloc::Layout;
+    use std::ptr::NonNull;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct Tracking {
+        live: AtomicUsize,
+        total: AtomicUsize,
+    }
+
+    unsafe impl Allocator for Tracking {
+        fn allocate(&self, layout: Layout) -> Option<NonNull<u8>> {
+            self.live.fetch_add(layout.size(), Ordering::Relaxed);
+            self.total.fetch_add(1, Ordering::Relaxed);
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            self.live.fetch_sub(layout.size(), Ordering::Relaxed);
+            Global.deallocate(ptr, layout)
+        }
+    }
+
+    let tracking = Tracking::default();
+    for chunk_size in [None, Some(64)] {
+        let mut world = match chunk_size {
+            None => World::new_in(&tracking),
+            Some(size) => World::with_chunk_size_in(size, &tracking),
+        };
+        world.register_sparse::<char>();
+        let entities = world
+            .spawn_batch((0..100).map(|i| (i, i as f32)))
+            .collect::<Vec<_>>();
+        world.insert(entities[0], ("abc", 'x')).unwrap();
+        world.despawn(entities[1]).unwrap();
+        let reserved = world.reserve_entity();
+        world.insert_one(reserved, true).unwrap();
+        assert_eq!(world.query::<&f32>().iter().count(), 99);
+        let empty = world.spawn((1u8,));
+        world.despawn(empty).unwrap();
+        assert_eq!(world.remove_empty_archetypes(), 1);
+        assert!(world.allocator().live.load(Ordering::Relaxed) > 0);
+        drop(world);
+        assert_eq!(tracking.live.load(Ordering::Relaxed), 0);
+    }
+    assert!(tracking.total.load(Ordering::Relaxed) > 0);
+}
+
+#[test]
+fn memory_stats() {
+    let mut world = World::new();
+    world.register_sparse::<char>();
+    let a = world.spawn((1, true));
+    world.spawn_batch((0..100).map(|i| (i, 0.5f32)));
+    world.insert_one(a, 'x').unwrap();
+
+    let stats = world.memory_stats();
+    assert_eq!(stats.archetypes().len(), world.archetypes().len());
+    assert_eq!(stats.archetypes_smaller_than(2), 2);
+    assert_eq!(stats.archetypes_smaller_than(101), 3);
+    let batch = stats.archetypes().iter().find(|x| x.len() == 100).unwrap();
+    assert_eq!(batch.bytes_used(), 100 * (4 + 4 + 4));
+    assert!(batch.bytes_reserved() >= batch.bytes_used());
+    assert_eq!(stats.sparse().len(), 1);
+    assert_eq!(stats.sparse()[0].len(), 1);
+    assert_eq!(stats.sparse()[0].component_types(), &["char"]);
+    assert_eq!(stats.entities().len(), 101);
+    assert_eq!(stats.entities().freelist_len(), 0);
+    assert!(stats.bytes_used() <= stats.bytes_reserved());
+
+    world.despawn(a).unwrap();
+    world.shrink_to_fit();
+    let stats = world.memory_stats();
+    assert_eq!(stats.entities().freelist_len(), 1);
+    assert_eq!(stats.sparse()[0].bytes_reserved(), 0);
+    assert!(stats.archetypes().iter().all(|x| x.capacity() == x.len()));
+}
+
+#[test]
+fn sort_by_key() {
+    for mut world in [World::new(), World::with_chunk_size(32)] {
+        let keys = [5, 3, 9, 1, 7, 3, 0, 8, 2, 6];
+        let entities = keys
+            .iter()
+            .map(|&k| world.spawn((k, k.to_string())))
+            .collect::<Vec<_>>();
+        let other = world.spawn((4, true));
+        world.sort_by_key(|&k: &i32| core::cmp::Reverse(k));
+
+        let sorted = world
+            .query::<(&i32, &String)>()
+            .iter()
+            .map(|(_, (&k, s))| {
+                assert_eq!(k.to_string(), *s);
+                k
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(sorted, [9, 8, 7, 6, 5, 3, 3, 2, 1, 0]);
+        for (&e, &k) in entities.iter().zip(&keys) {
+            assert_eq!(*world.get::<i32>(e).unwrap(), k);
+        }
+        assert_eq!(*world.get::<i32>(other).unwrap(), 4);
+
+        world.despawn(entities[2]).unwrap();
+        world.insert_one(entities[3], 'x').unwrap();
+        for (&e, &k) in entities.iter().zip(&keys).filter(|x| *x.0 != entities[2]) {
+            assert_eq!(*world.get::<String>(e).unwrap(), k.to_string());
+        }
+    }
+}
+
+#[test]
+fn bulk_despawn() {
+    struct Dead;
+    for mut world in [World::new(), World::with_chunk_size(64)] {
+        world.register_sparse::<Dead>();
+        let entities = world
+            .spawn_batch((0..50).map(|i| (i, i.to_string())))
+            .collect::<Vec<_>>();
+        for &e in entities.iter().step_by(3) {
+            world.insert_one(e, Dead).unwrap();
+        }
+        let parent = world.spawn((100,));
+        world.set_parent(entities[1], parent).unwrap();
+        world.set_parent(entities[3], parent).unwrap();
+
+        assert_eq!(world.despawn_matching::<WithSparse<Dead, ()>>(), 17);
+        for (i, &e) in entities.iter().enumerate() {
+            assert_eq!(world.contains(e), i % 3 != 0);
+        }
+        assert_eq!(**world.get::<Children>(parent).unwrap(), [entities[1]]);
+        assert_eq!(world.query::<Sparse<&Dead>>().iter().count(), 0);
+
+        world.retain(|_, x| match x.get::<i32>() {
+            Some(x) => *x % 2 == 0 || *x == 100,
+            None => true,
+        });
+        for (i, &e) in entities.iter().enumerate() {
+            let alive = i % 3 != 0 && i % 2 == 0;
+            assert_eq!(world.contains(e), alive);
+            if alive {
+                assert_eq!(*world.get::<String>(e).unwrap(), i.to_string());
+            }
+        }
+        assert!(world.get::<Children>(parent).is_err());
+
+        assert_eq!(world.despawn_matching::<&String>(), 16);
+        assert_eq!(world.query::<()>().iter().count(), 1);
+        let e = world.spawn((1, Dead));
+        assert!(world.get::<Dead>(e).is_ok());
+    }
+}
+
+#[test]
+fn bulk_insert_remove() {
+    use std::sync::Arc;
+
+    struct Frozen;
+    let tracker = Arc::new(());
+    for mut world in [World::new(), World::with_chunk_size(64)] {
+        world.register_sparse::<char>();
+        let plain = world
+            .spawn_batch((0..20).map(|i| (i, i.to_string())))
+            .collect::<Vec<_>>();
+        // Already has the inserted type, and is also the target of `plain`
+        let tracked = world
+            .spawn_batch((20..30).map(|i| (i, i.to_string(), tracker.clone())))
+            .collect::<Vec<_>>();
+        let other = world.spawn(("abc",));
+        for &e in plain.iter().step_by(2) {
+            world.insert_one(e, 'x').unwrap();
+        }
+
+        let count = world.insert_matching::<WithSparse<char, &i32>, _>(|e| (tracker.clone(), e));
+        assert_eq!(count, 10);
+        assert_eq!(Arc::strong_count(&tracker), 21);
+        let count = world.insert_matching::<&i32, _>(|_| (Frozen, tracker.clone()));
+        assert_eq!(count, 30);
+        assert_eq!(Arc::strong_count(&tracker), 31);
+        assert!(world.get::<Frozen>(other).is_err());
+        for (i, &e) in plain.iter().chain(&tracked).enumerate() {
+            assert_eq!(*world.get::<i32>(e).unwrap(), i as i32);
+            assert_eq!(*world.get::<String>(e).unwrap(), i.to_string());
+            assert!(world.get::<Frozen>(e).is_ok());
+            if i < 20 && i % 2 == 0 {
+                assert_eq!(*world.get::<Entity>(e).unwrap(), e);
+                assert!(world.get::<char>(e).is_ok());
+            }
+        }
+
+        // None of these have a `char`, so they're left intact
+        let count = world.remove_matching::<Without<Entity, &Frozen>, (Frozen, char)>();
+        assert_eq!(count, 0);
+        assert_eq!(world.query::<&Frozen>().iter().count(), 30);
+        let count = world.remove_matching::<Without<Entity, &Frozen>, (Arc<()>, Frozen)>();
+        assert_eq!(count, 20);
+        assert_eq!(Arc::strong_count(&tracker), 11);
+        assert_eq!(world.query::<Sparse<&char>>().iter().count(), 10);
+        let count = world.remove_matching::<(), (Arc<()>, Entity)>();
+        assert_eq!(count, 10);
+        assert_eq!(Arc::strong_count(&tracker), 1);
+        for (i, &e) in plain.iter().chain(&tracked).enumerate() {
+            assert_eq!(*world.get::<String>(e).unwrap(), i.to_string());
+            assert_eq!(world.get::<Frozen>(e).is_ok(), i < 20 && i % 2 == 0);
+        }
+        assert_eq!(world.remove_matching::<(), (Frozen,)>(), 10);
+        world.despawn(plain[4]).unwrap();
+        assert_eq!(*world.get::<i32>(plain[5]).unwrap(), 5);
+    }
+}
+
+#[test]
+fn bulk_insert_remove_region() {
+    struct Selected;
+    #[derive(Debug, PartialEq)]
+    struct Frozen(u64);
+
+    for mut world in [World::new(), World::with_chunk_size(64)] {
+        world.register_sparse::<Selected>();
+        let entities = world
+            .spawn_batch((0..100).map(|i| (i, i.to_string())))
+            .collect::<Vec<_>>();
+        for &e in &entities[30..70] {
+            world.insert_one(e, Selected).unwrap();
+        }
+        // Moved entities land after those already in the target
+        let existing = world.spawn((-1, "x".to_string(), Frozen(0)));
+
+        let count =
+            world.insert_matching::<WithSparse<Selected, &i32>, _>(|e| (Frozen(e.to_bits()),));
+        assert_eq!(count, 40);
+        for (i, &e) in entities.iter().enumerate() {
+            assert_eq!(*world.get::<i32>(e).unwrap(), i as i32);
+            assert_eq!(*world.get::<String>(e).unwrap(), i.to_string());
+            let frozen = world.get::<Frozen>(e).ok().map(|x| x.0);
+            assert_eq!(frozen, (30..70).contains(&i).then(|| e.to_bits()));
+        }
+        assert_eq!(*world.get::<Frozen>(existing).unwrap(), Frozen(0));
+
+        let count = world.remove_matching::<WithSparse<Selected, &i32>, (Frozen,)>();
+        assert_eq!(count, 40);
+        for (i, &e) in entities.iter().enumerate() {
+            assert_eq!(*world.get::<i32>(e).unwrap(), i as i32);
+            assert_eq!(*world.get::<String>(e).unwrap(), i.to_string());
+            assert!(world.get::<Frozen>(e).is_err());
+        }
+        assert_eq!(*world.get::<String>(existing).unwrap(), "x");
+        assert_eq!(world.query::<&Frozen>().iter().count(), 1);
+    }
+}
+
+#[test]
+fn id_ranges() {
+    let mut world = World::new();
+    world.set_id_range(0..1000);
+    let predicted = world.add_id_range(1000..2000);
+
+    let a = world.spawn_in(predicted, ("a",));
+    let reserved = world.reserve_entity_in(predicted);
+    let b = world.spawn(("b",));
+    assert_eq!((a.id(), reserved.id(), b.id()), (1000, 1001, 0));
+    world.insert_one(reserved, "reserved").unwrap();
+    assert_eq!(world.iter().count(), 3);
+
+    world.despawn(a).unwrap();
+    assert!(!world.contains(a));
+    let c = world.spawn(("c",));
+    assert_eq!(c.id(), 1);
+    let d = world.spawn_in(predicted, ("d",));
+    assert_eq!(d.id(), 1000);
+
+    let mut names = world
+        .query::<&&str>()
+        .iter()
+        .map(|(e, &name)| (e.id(), name))
+        .collect::<Vec<_>>();
+    names.sort_unstable();
+    assert_eq!(names, [(0, "b"), (1, "c"), (1000, "d"), (1001, "reserved")]);
+}
+
+#[test]
+#[should_panic(expected = "ID ranges must not overlap")]
+fn id_ranges_overlap() {
+    let mut world = World::new();
+    world.add_id_range(10..20);
+}
+
+#[test]
+fn entity_niche() {
+    assert_eq!(std::mem::size_of::<Entity>(), 8);
+    assert_eq!(
+        std::mem::size_of::<Option<Entity>>(),
+        std::mem::size_of::<Entity>()
+    );
+
+    assert_eq!(Entity::from_bits(0), None);
+    assert_eq!(Entity::from_bits(42), None);
+    assert_eq!(Entity::from_bits(u64::from(u32::MAX)), None);
+    let e = World::new().spawn(());
+    assert_eq!(Entity::from_bits(e.to_bits()), Some(e));
+    assert_ne!(e.to_bits(), 0);
+}
+
+#[test]
+fn spawn_at() {
+    let mut world = World::new();
+    let a = Entity::from_bits(1 << 32 | 3).unwrap();
+    world.spawn_at(a, ("a",));
+    assert_eq!(*world.get::<&str>(a).unwrap(), "a");
+    world.spawn_at(a, (42,));
+    assert!(world.get::<&str>(a).is_err());
+    assert_eq!(*world.get::<i32>(a).unwrap(), 42);
+    assert_eq!(world.iter().count(), 1);
+
+    // Skipped IDs remain available
+    let mut ids = (0..4).map(|_| world.spawn(()).id()).collect::<Vec<_>>();
+    ids.sort_unstable();
+    assert_eq!(ids, [0, 1, 2, 4]);
+    assert!(!world.contains(Entity::from_bits(1 << 32 | 5).unwrap()));
+}
+
+#[test]
+fn shared_entities() {
+    let entities = std::sync::Arc::new(SharedEntities::new());
+    let mut sim = World::new();
+    sim.share_entities(entities.clone());
+    let mut render = World::new();
+    render.share_entities(entities);
+
+    let a = sim.spawn(("a",));
+    let b = sim.reserve_entity();
+    render.spawn_at(a, (1,));
+    render.spawn_at(b, (2,));
+    let c = render.spawn((3,));
+    assert!(c != a && c != b);
+    assert!(sim.contains(b));
+    sim.flush();
+    assert!(!sim.contains(c));
+    assert!(sim.get::<&str>(b).is_err());
+    assert_eq!(*render.get::<i32>(b).unwrap(), 2);
+
+    // Despawning in either world releases the ID exactly once
+    sim.despawn(a).unwrap();
+    render.despawn(a).unwrap();
+    let a2 = sim.spawn(("a2",));
+    assert_eq!(a2.id(), a.id());
+    assert_ne!(render.spawn(()).id(), a.id());
+    render.spawn_at(a2, (4,));
+    assert!(!render.contains(a));
+    assert_eq!(*render.get::<i32>(a2).unwrap(), 4);
+}
+
+#[test]
+fn shared_entities_drop_reserved() {
+    let entities = std::sync::Arc::new(SharedEntities::new());
+    let mut world = World::new();
+    world.share_entities(entities.clone());
+    let mut ids = world
+        .reserve_entities(2)
+        .map(|x| x.id())
+        .collect::<Vec<_>>();
+    drop(world);
+
+    // IDs reserved but never flushed are returned to the allocator
+    let mut other = World::new();
+    other.share_entities(entities);
+    let mut reused = (0..2).map(|_| other.spawn(()).id()).collect::<Vec<_>>();
+    ids.sort_unstable();
+    reused.sort_unstable();
+    assert_eq!(ids, reused);
+}
+
+#[test]
+fn entity_map() {
+    struct Links {
+        next: Option<Entity>,
+        all: Vec<Entity>,
+    }
+
+    impl MapEntities for Links {
+        fn map_entities(&mut self, map: &EntityMap) {
+            self.next.map_entities(map);
+            self.all.map_entities(map);
+        }
+    }
+
+    let mut source = World::new();
+    let a = source.spawn(());
+    let b = source.spawn(());
+    let outside = source.spawn(());
+
+    let mut world = World::new();
+    world.spawn(());
+    let mut map = EntityMap::new();
+    let new_b = map.get_or_reserve(b, &world);
+    assert_eq!(map.get_or_reserve(b, &world), new_b);
+    let new_a = world.spawn(());
+    assert_eq!(map.insert(a, new_a), None);
+    assert_eq!(map.len(), 2);
+
+    let mut links = Links {
+        next: Some(b),
+        all: vec![a, b, outside],
+    };
+    links.map_entities(&map);
+    assert_eq!(links.next, Some(new_b));
+    assert_eq!(links.all, [new_a, new_b, outside]);
+    world.insert_one(new_b, links).unwrap();
+    assert_eq!(world.get::<Links>(new_b).unwrap().next, Some(new_b));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_entity() {
+    let mut world = World::new();
+    let a = world.spawn(());
+    world.despawn(a).unwrap();
+    let b = world.spawn(());
+    let json = serde_json::to_string(&[a, b]).unwrap();
+    assert_eq!(serde_json::from_str::<[Entity; 2]>(&json).unwrap(), [a, b]);
+    assert!(serde_json::from_str::<Entity>("42").is_err());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serialize_rows() {
+    let mut registry = ComponentRegistry::new();
+    registry.register::<i32>("health").serde();
+    registry.register::<String>("name").serde();
+    registry.register::<bool>("flag");
+
+    let mut world = World::new();
+    let a = world.spawn((10, "goblin".to_string()));
+    let b = world.spawn((20, true));
+    let c = world.spawn(());
+    world.despawn(c).unwrap();
+    let c = world.spawn(());
+
+    assert!(serde_json::to_string(&registry.serialize_rows(&world, Unregistered::Error)).is_err());
+    let json = serde_json::to_string(&registry.serialize_rows(&world, Unregistered::Skip)).unwrap();
+
+    let mut loaded = World::new();
+    let mut de = serde_json::Deserializer::from_str(&json);
+    registry
+        .deserialize_rows(&mut de, &mut loaded, Unregistered::Error)
+        .unwrap();
+    assert_eq!(*loaded.get::<i32>(a).unwrap(), 10);
+    assert_eq!(*loaded.get::<String>(a).unwrap(), "goblin");
+    assert_eq!(*loaded.get::<i32>(b).unwrap(), 20);
+    assert!(loaded.get::<bool>(b).is_err());
+    assert!(loaded.contains(c));
+    assert_eq!(loaded.iter().count(), 3);
+
+    let json = format!("[[{}, {{\"health\": 5, \"mana\": 3}}]]", a.to_bits());
+    let mut de = serde_json::Deserializer::from_str(&json);
+    assert!(registry
+        .deserialize_rows(&mut de, &mut World::new(), Unregistered::Error)
+        .is_err());
+    let mut loaded = World::new();
+    let mut de = serde_json::Deserializer::from_str(&json);
+    registry
+        .deserialize_rows(&mut de, &mut loaded, Unregistered::Skip)
+        .unwrap();
+    assert_eq!(*loaded.get::<i32>(a).unwrap(), 5);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serialize_columns() {
+    use bincode::Options;
+
+    struct Marker;
+
+    let mut registry = ComponentRegistry::new();
+    registry.register::<i32>("health").serde();
+    registry.register::<String>("name").serde();
+    registry.register::<u8>("stunned").serde();
+
+    let mut world = World::new();
+    world.register_sparse::<u8>();
+    let a = world.spawn((10, "goblin".to_string()));
+    let b = world.spawn((20, 3u8));
+    let c = world.spawn((30, Marker));
+    let d = world.spawn(());
+    world.despawn(d).unwrap();
+    let d = world.spawn(());
+
+    assert!(
+        serde_json::to_string(&registry.serialize_columns(&world, Unregistered::Error)).is_err()
+    );
+    let bytes =
+        bincode::serialize(&registry.serialize_columns(&world, Unregistered::Skip)).unwrap();
+    let json =
+        serde_json::to_string(&registry.serialize_columns(&world, Unregistered::Skip)).unwrap();
+
+    let mut loaded = World::new();
+    loaded.register_sparse::<u8>();
+    let mut de = bincode::Deserializer::from_slice(
+        &bytes,
+        bincode::options()
+            .with_fixint_encoding()
+            .allow_trailing_bytes(),
+    );
+    registry
+        .deserialize_columns(&mut de, &mut loaded, Unregistered::Error)
+        .unwrap();
+    assert_eq!(*loaded.get::<i32>(a).unwrap(), 10);
+    assert_eq!(*loaded.get::<String>(a).unwrap(), "goblin");
+    assert_eq!(*loaded.get::<i32>(b).unwrap(), 20);
+    assert_eq!(*loaded.get::<u8>(b).unwrap(), 3);
+    assert_eq!(*loaded.get::<i32>(c).unwrap(), 30);
+    assert!(loaded.get::<Marker>(c).is_err());
+    assert!(loaded.contains(d));
+    assert_eq!(loaded.iter().count(), 4);
+
+    // Sparse types must be registered in the destination, too
+    let mut de = serde_json::Deserializer::from_str(&json);
+    assert!(registry
+        .deserialize_columns(&mut de, &mut World::new(), Unregistered::Error)
+        .is_err());
+
+    // Loading replaces existing entities with the same IDs
+    let mut de = serde_json::Deserializer::from_str(&json);
+    registry
+        .deserialize_columns(&mut de, &mut loaded, Unregistered::Error)
+        .unwrap();
+    assert_eq!(loaded.iter().count(), 4);
+    assert_eq!(*loaded.get::<i32>(c).unwrap(), 30);
+}
+
+#[test]
+fn snapshot() {
+    let mut registry = ComponentRegistry::new();
+    unsafe {
+        registry.register::<u64>("position").pod();
+        registry.register::<u16>("team").pod();
+        registry.register::<u8>("stunned").pod();
+    }
+    registry.register::<String>("name");
+
+    // Chunks of 4 entities, so columns aren't contiguous
+    let mut world = World::with_chunk_size(40);
+    world.register_sparse::<u8>();
+    let entities = world
+        .spawn_batch((0..10u64).map(|i| (i, i as u16)))
+        .collect::<Vec<_>>();
+    world.insert_one(entities[3], 7u8).unwrap();
+    let named = world.spawn((42u64, "bob".to_string()));
+
+    assert_eq!(
+        registry.snapshot(&world, Unregistered::Error),
+        Err(SnapshotError::Unregistered(
+            core::any::type_name::<String>().into()
+        ))
+    );
+    let snapshot = registry.snapshot(&world, Unregistered::Skip).unwrap();
+
+    let mut loaded = World::new();
+    loaded.register_sparse::<u8>();
+    registry
+        .load_snapshot(&snapshot, &mut loaded, Unregistered::Error)
+        .unwrap();
+    for (i, &e) in entities.iter().enumerate() {
+        assert_eq!(*loaded.get::<u64>(e).unwrap(), i as u64);
+        assert_eq!(*loaded.get::<u16>(e).unwrap(), i as u16);
+    }
+    assert_eq!(*loaded.get::<u8>(entities[3]).unwrap(), 7);
+    assert!(loaded.get::<u8>(entities[4]).is_err());
+    assert_eq!(*loaded.get::<u64>(named).unwrap(), 42);
+    assert!(loaded.get::<String>(named).is_err());
+    assert_eq!(loaded.iter().count(), 11);
+
+    assert_eq!(
+        registry.load_snapshot(&snapshot, &mut World::new(), Unregistered::Error),
+        Err(SnapshotError::Sparse("stunned"))
+    );
+    assert_eq!(
+        registry.load_snapshot(
+            &snapshot[..snapshot.len() - 1],
+            &mut World::new(),
+            Unregistered::Error
+        ),
+        Err(SnapshotError::Malformed)
+    );
+    let mut swapped = snapshot.clone();
+    swapped[12..16].reverse();
+    assert_eq!(
+        registry.load_snapshot(&swapped, &mut World::new(), Unregistered::Error),
+        Err(SnapshotError::Incompatible)
+    );
+
+    let mut other = ComponentRegistry::new();
+    unsafe {
+        other.register::<u32>("position").pod();
+    }
+    assert_eq!(
+        other.load_snapshot(&snapshot, &mut World::new(), Unregistered::Skip),
+        Err(SnapshotError::LayoutMismatch("position"))
+    );
+    let mut other = ComponentRegistry::new();
+    unsafe {
+        other.register::<u16>("team").pod();
+    }
+    assert_eq!(
+        other.load_snapshot(&snapshot, &mut World::new(), Unregistered::Error),
+        Err(SnapshotError::Unregistered("position".into()))
+    );
+    let mut loaded = World::new();
+    other
+        .load_snapshot(&snapshot, &mut loaded, Unregistered::Skip)
+        .unwrap();
+    assert_eq!(*loaded.get::<u16>(entities[5]).unwrap(), 5);
+    assert!(loaded.get::<u64>(entities[5]).is_err());
+}
+
+#[cfg(all(feature = "serde", feature = "std"))]
+#[test]
+fn scene() {
+    let mut registry = ComponentRegistry::new();
+    registry.register::<i32>("health").scene();
+    registry.register::<String>("name").scene();
+    registry.register::<u8>("speed").scene();
+    registry
+        .register::<SceneEntity>("target")
+        .scene()
+        .map_entities();
+    registry
+        .register::<Vec<SceneEntity>>("squad")
+        .scene()
+        .map_entities();
+    registry.register::<Entity>("entity").scene();
+    registry.register::<bool>("hidden");
+
+    let json = r#"{
+        "prefabs": {
+            "elite": { "prefab": "goblin", "components": { "health": 30 } },
+            "goblin": { "components": { "health": 10, "name": "goblin", "speed": 2 } }
+        },
+        "entities": [
+            { "components": { "target": "leader" } },
+            {
+                "id": "leader",
+                "prefab": "elite",
+                "components": { "name": "boss", "squad": ["leader", "grunt"] }
+            },
+            { "id": "grunt", "prefab": "goblin", "components": { "target": "leader", "hidden": true } }
+        ]
+    }"#;
+
+    let mut de = serde_json::Deserializer::from_str(json);
+    assert!(registry
+        .deserialize_scene(&mut de, Unregistered::Error)
+        .is_err());
+    let mut de = serde_json::Deserializer::from_str(json);
+    let scene = registry
+        .deserialize_scene(&mut de, Unregistered::Skip)
+        .unwrap();
+    assert_eq!(scene.len(), 3);
+    assert_eq!(scene.index_of("leader"), Some(1));
+    assert_eq!(scene.index_of("nobody"), None);
+    assert_eq!(scene.prefab("elite").unwrap().len(), 3);
+
+    let mut world = World::new();
+    let first = scene.spawn(&mut world);
+    let second = scene.spawn(&mut world);
+    assert_eq!(world.iter().count(), 6);
+    for spawned in [&first, &second] {
+        let (leader, grunt) = (spawned[1], spawned[2]);
+        assert_eq!(*world.get::<i32>(leader).unwrap(), 30);
+        assert_eq!(*world.get::<String>(leader).unwrap(), "boss");
+        assert_eq!(*world.get::<u8>(leader).unwrap(), 2);
+        assert_eq!(
+            *world.get::<Vec<SceneEntity>>(leader).unwrap(),
+            [SceneEntity::new(leader), SceneEntity::new(grunt)]
+        );
+        assert_eq!(
+            world.get::<SceneEntity>(spawned[0]).unwrap().entity(),
+            leader
+        );
+        assert_eq!(world.get::<SceneEntity>(grunt).unwrap().entity(), leader);
+    }
+    assert_ne!(first[1], second[1]);
+
+    // Plain entities keep their usual form within scenes
+    let json = format!(
+        r#"{{ "entities": [ {{ "components": {{ "entity": {} }} }} ] }}"#,
+        first[0].to_bits()
+    );
+    let mut de = serde_json::Deserializer::from_str(&json);
+    let scene = registry
+        .deserialize_scene(&mut de, Unregistered::Error)
+        .unwrap();
+    let spawned = scene.spawn(&mut world);
+    assert_eq!(*world.get::<Entity>(spawned[0]).unwrap(), first[0]);
+
+    for json in [
+        r#"{ "prefabs": { "a": { "prefab": "b" }, "b": { "prefab": "a" } } }"#,
+        r#"{ "entities": [ { "prefab": "missing" } ] }"#,
+        r#"{ "entities": [ { "id": "a" }, { "id": "a" } ] }"#,
+        r#"{ "entities": [ { "components": { "health": 1, "health": 2 } } ] }"#,
+        r#"{ "entities": [ { "id": "a", "components": { "target": "b" } } ] }"#,
+        r#"{ "entities": [ { "id": "a", "components": { "squad": ["a", "b"] } } ] }"#,
+        r#"{ "entities": [ { "id": "a", "components": { "target": 4294967296 } } ] }"#,
+        r#"{ "entities": [ { "components": { "entity": "a" } }, { "id": "a" } ] }"#,
+        r#"{
+            "prefabs": { "a": { "components": { "target": "b" } } },
+            "entities": [ { "id": "b" } ]
+        }"#,
+    ] {
+        let mut de = serde_json::Deserializer::from_str(json);
+        assert!(registry
+            .deserialize_scene(&mut de, Unregistered::Skip)
+            .is_err());
+    }
+}